@@ -64,6 +64,7 @@ impl Role{
             "admin" => {
                 perms.insert(Permission::CreateClinicianAccount);
                 perms.insert(Permission::RemoveClinicianAccount);
+                perms.insert(Permission::ViewAlerts);
             }
             "clinician" => {
                 perms.insert(Permission::CreatePatientAccount);
@@ -77,11 +78,13 @@ impl Role{
                 perms.insert(Permission::ViewGlucose);
                 perms.insert(Permission::AddGlucose);
                 perms.insert(Permission::CreateCaretakerLink);
+                perms.insert(Permission::ViewAlerts);
             }
             "caretaker" => {
                 perms.insert(Permission::ViewPatient);
                 perms.insert(Permission::ViewGlucose);
-
+                perms.insert(Permission::AddGlucose);
+                perms.insert(Permission::ViewAlerts);
             }
             _ => {
                 eprintln!("Warning: Unknown role '{}', no permissions assigned.", role_name);
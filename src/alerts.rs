@@ -1,3 +1,374 @@
-// Alert generation for glucose 
+// Alert generation for glucose
+use chrono::{Duration, NaiveDateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Result, params, params_from_iter};
+use rusqlite::types::Value;
+use crate::config::env_or;
+use crate::filter::{GlucoseSource, GlucoseSourceSettings};
+use crate::forecast::evaluate_forecast_low;
+use crate::insulin::get_patient_data_from_patient_table;
+use crate::suppression::is_snoozed;
+use crate::utils::{format_local_time, get_current_db_time_string, DB_TIME_FORMAT};
 
+// alert_type for each glucose threshold tier
+pub const URGENT_LOW_ALERT: &str = "Urgent Low";
+pub const LOW_GLUCOSE_ALERT: &str = "Low Glucose";
+pub const HIGH_GLUCOSE_ALERT: &str = "High Glucose";
+// readings older than this when they are evaluated (e.g. imported history) raise no threshold alert
+const MAX_ALERT_READING_AGE_MINUTES: i64 = 15;
 
+// records a new unresolved alert for the patient and returns its alert_id; an alert raised while
+// the patient's alerts of that type are snoozed is kept but marked so nobody is notified of it
+pub fn insert_alert(conn: &Connection, patient_id: &str, alert_type: &str, alert_message: &str) -> Result<i64> {
+    let snoozed = is_snoozed(conn, patient_id, alert_type)?;
+    conn.execute(
+        "INSERT INTO alerts (patient_id, alert_type, alert_message, alert_time, is_resolved, resolved_by, snoozed)
+         VALUES (?1, ?2, ?3, ?4, 0, NULL, ?5)",
+        params![patient_id, alert_type, alert_message, get_current_db_time_string(), snoozed],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+//-----------------------Threshold alerts-----------------------//
+
+// settings shared by every patient; the low and high thresholds come from the patient's prescription
+pub struct ThresholdAlertSettings {
+    // glucose at or below this (mg/dL) is an urgent low, whatever the patient's low threshold
+    pub urgent_low_mg_dl: f32,
+    // which stored glucose value is compared with the thresholds
+    pub glucose_source: GlucoseSource,
+    // after an alert, glucose must come back this far (mg/dL) past the threshold before it can fire again
+    pub hysteresis_mg_dl: f32,
+}
+
+impl ThresholdAlertSettings {
+    pub fn from_env() -> Self {
+        Self {
+            urgent_low_mg_dl: env_or("GLUCOGUARD_URGENT_LOW_MG_DL", 55.0),
+            glucose_source: GlucoseSourceSettings::from_env().alerts,
+            hysteresis_mg_dl: env_or("GLUCOGUARD_ALERT_HYSTERESIS_MG_DL", 15.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GlucoseAlertLevel {
+    UrgentLow,
+    Low,
+    High,
+}
+
+impl GlucoseAlertLevel {
+    pub fn alert_type(&self) -> &'static str {
+        match self {
+            GlucoseAlertLevel::UrgentLow => URGENT_LOW_ALERT,
+            GlucoseAlertLevel::Low => LOW_GLUCOSE_ALERT,
+            GlucoseAlertLevel::High => HIGH_GLUCOSE_ALERT,
+        }
+    }
+
+    // while glucose stays in the tier the alert is raised again after this many minutes;
+    // low and high alerts fire once until glucose has re-armed them
+    pub fn repeat_minutes(&self) -> Option<i64> {
+        match self {
+            GlucoseAlertLevel::UrgentLow => Some(15),
+            GlucoseAlertLevel::Low | GlucoseAlertLevel::High => None,
+        }
+    }
+}
+
+/// Tier of a glucose value: urgent low at or below `urgent_low` (never above the low threshold),
+/// low below `low_threshold`, high above `high_threshold`, otherwise `None`.
+pub fn classify_glucose(glucose: f32, low_threshold: f32, high_threshold: f32, urgent_low: f32) -> Option<GlucoseAlertLevel> {
+    if glucose <= urgent_low.min(low_threshold) {
+        Some(GlucoseAlertLevel::UrgentLow)
+    } else if glucose < low_threshold {
+        Some(GlucoseAlertLevel::Low)
+    } else if glucose > high_threshold {
+        Some(GlucoseAlertLevel::High)
+    } else {
+        None
+    }
+}
+
+// the patient's latest usable reading as (reading_time, glucose)
+fn get_latest_usable_reading(conn: &Connection, patient_id: &str, source: GlucoseSource) -> Result<Option<(NaiveDateTime, f32)>> {
+    let latest: Option<(String, f32)> = conn
+        .query_row(
+            &format!(
+                "SELECT reading_time, {} FROM glucose_readings
+                 WHERE patient_id = ?1 AND unusable_reason IS NULL
+                 ORDER BY datetime(reading_time) DESC
+                 LIMIT 1",
+                source.column()
+            ),
+            params![patient_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(latest.and_then(|(time, glucose)| {
+        NaiveDateTime::parse_from_str(&time, DB_TIME_FORMAT).ok().map(|t| (t, glucose))
+    }))
+}
+
+// when the patient's last alert of this type was raised
+fn get_last_alert_time(conn: &Connection, patient_id: &str, alert_type: &str) -> Result<Option<NaiveDateTime>> {
+    let last: Option<String> = conn
+        .query_row(
+            "SELECT alert_time FROM alerts
+             WHERE patient_id = ?1 AND alert_type = ?2
+             ORDER BY datetime(alert_time) DESC
+             LIMIT 1",
+            params![patient_id, alert_type],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(last.and_then(|time| NaiveDateTime::parse_from_str(&time, DB_TIME_FORMAT).ok()))
+}
+
+/// Hysteresis: whether a usable reading since `since` came back past the tier's threshold by
+/// `margin` (below `threshold - margin` for a high, above `threshold + margin` for a low).
+fn is_rearmed(conn: &Connection, patient_id: &str, level: GlucoseAlertLevel, threshold: f32, margin: f32, source: GlucoseSource, since: &NaiveDateTime) -> Result<bool> {
+    let (comparison, rearm_level) = match level {
+        GlucoseAlertLevel::High => ("<=", threshold - margin),
+        GlucoseAlertLevel::Low | GlucoseAlertLevel::UrgentLow => (">=", threshold + margin),
+    };
+    let count: i64 = conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM glucose_readings
+             WHERE patient_id = ?1 AND unusable_reason IS NULL
+               AND datetime(reading_time) > datetime(?2)
+               AND {} {} ?3",
+            source.column(),
+            comparison
+        ),
+        params![patient_id, since.format(DB_TIME_FORMAT).to_string(), rearm_level],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Compares the patient's latest usable reading with their low and high thresholds and the urgent
+/// low level, and raises an alert for the tier it falls in. Once a tier has alerted it fires
+/// again only after glucose has re-armed it by coming back past the threshold by the hysteresis
+/// margin; an urgent low also repeats after its repeat interval. Alerts of a snoozed type are
+/// recorded but not notified. Returns the tier alerted, if any.
+pub fn evaluate_glucose_thresholds(conn: &Connection, patient_id: &str, settings: &ThresholdAlertSettings) -> Result<Option<GlucoseAlertLevel>> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let (reading_time, glucose) = match get_latest_usable_reading(conn, patient_id, settings.glucose_source)? {
+        Some(reading) => reading,
+        None => return Ok(None),
+    };
+    let now = Utc::now().naive_utc();
+    if now - reading_time > Duration::minutes(MAX_ALERT_READING_AGE_MINUTES) {
+        return Ok(None);
+    }
+    let level = match classify_glucose(glucose, patient.low_glucose_threshold, patient.high_glucose_threshold, settings.urgent_low_mg_dl) {
+        Some(level) => level,
+        None => return Ok(None),
+    };
+
+    let urgent_low_level = settings.urgent_low_mg_dl.min(patient.low_glucose_threshold);
+    if let Some(last_alert) = get_last_alert_time(conn, patient_id, level.alert_type())? {
+        let threshold = match level {
+            GlucoseAlertLevel::UrgentLow => urgent_low_level,
+            GlucoseAlertLevel::Low => patient.low_glucose_threshold,
+            GlucoseAlertLevel::High => patient.high_glucose_threshold,
+        };
+        let rearmed = is_rearmed(conn, patient_id, level, threshold, settings.hysteresis_mg_dl, settings.glucose_source, &last_alert)?;
+        let repeat_due = level.repeat_minutes().is_some_and(|minutes| now - last_alert >= Duration::minutes(minutes));
+        if !rearmed && !repeat_due {
+            return Ok(None);
+        }
+    }
+
+    let message = match level {
+        GlucoseAlertLevel::UrgentLow => format!(
+            "Urgent low glucose: {:.0} mg/dL at {} (urgent low level {:.0} mg/dL). Treat immediately.",
+            glucose, format_local_time(&reading_time), urgent_low_level
+        ),
+        GlucoseAlertLevel::Low => format!(
+            "Low glucose: {:.0} mg/dL at {} (low threshold {:.0} mg/dL)",
+            glucose, format_local_time(&reading_time), patient.low_glucose_threshold
+        ),
+        GlucoseAlertLevel::High => format!(
+            "High glucose: {:.0} mg/dL at {} (high threshold {:.0} mg/dL)",
+            glucose, format_local_time(&reading_time), patient.high_glucose_threshold
+        ),
+    };
+    insert_alert(conn, patient_id, level.alert_type(), &message)?;
+    Ok(Some(level))
+}
+
+/// Runs the alert checks that depend on the latest glucose reading; called after every new
+/// reading (streamed, simulated or imported). Returns the number of alerts raised.
+pub fn evaluate_reading_alerts(conn: &Connection, patient_id: &str) -> Result<usize> {
+    let mut raised = 0;
+    if evaluate_glucose_thresholds(conn, patient_id, &ThresholdAlertSettings::from_env())?.is_some() {
+        raised += 1;
+    }
+    if evaluate_forecast_low(conn, patient_id)? {
+        raised += 1;
+    }
+    Ok(raised)
+}
+
+//-----------------------Alert inbox-----------------------//
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertStatusFilter {
+    Open,
+    Resolved,
+    All,
+}
+
+impl AlertStatusFilter {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AlertStatusFilter::Open => "open",
+            AlertStatusFilter::Resolved => "resolved",
+            AlertStatusFilter::All => "all",
+        }
+    }
+}
+
+// which alerts an inbox lists; alert_type None means every type
+pub struct AlertFilter {
+    pub status: AlertStatusFilter,
+    pub alert_type: Option<String>,
+}
+
+impl Default for AlertFilter {
+    fn default() -> Self {
+        AlertFilter { status: AlertStatusFilter::Open, alert_type: None }
+    }
+}
+
+// one row of alerts, with the names of the patient and of the user who resolved it
+pub struct Alert {
+    pub alert_id: i64,
+    pub patient_id: String,
+    pub patient_name: Option<String>,
+    pub alert_type: String,
+    pub alert_message: String,
+    pub alert_time: NaiveDateTime,
+    pub is_resolved: bool,
+    pub resolved_by: Option<String>,
+    pub resolved_at: Option<NaiveDateTime>,
+    pub resolution_note: Option<String>,
+    // raised while its type was snoozed, so nobody was notified
+    pub snoozed: bool,
+}
+
+fn parse_db_time(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, DB_TIME_FORMAT).ok()
+}
+
+// "?2, ?3, ..." for the patient ids bound after `first` other parameters
+fn id_placeholders(count: usize, first: usize) -> String {
+    (1..=count).map(|i| format!("?{}", first + i)).collect::<Vec<_>>().join(", ")
+}
+
+/// Alerts of the given patients matching the filter, newest first.
+pub fn get_alerts(conn: &Connection, patient_ids: &[String], filter: &AlertFilter) -> Result<Vec<Alert>> {
+    if patient_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let status_clause = match filter.status {
+        AlertStatusFilter::Open => "AND a.is_resolved = 0",
+        AlertStatusFilter::Resolved => "AND a.is_resolved = 1",
+        AlertStatusFilter::All => "",
+    };
+    let sql = format!(
+        "SELECT a.alert_id, a.patient_id, p.first_name || ' ' || p.last_name, a.alert_type, a.alert_message,
+                a.alert_time, a.is_resolved, COALESCE(u.user_name, a.resolved_by), a.resolved_at, a.resolution_note,
+                a.snoozed
+         FROM alerts a
+         LEFT JOIN patients p ON p.patient_id = a.patient_id
+         LEFT JOIN users u ON u.id = a.resolved_by
+         WHERE (?1 IS NULL OR a.alert_type = ?1) {}
+           AND a.patient_id IN ({})
+         ORDER BY datetime(a.alert_time) DESC, a.alert_id DESC",
+        status_clause,
+        id_placeholders(patient_ids.len(), 1)
+    );
+
+    let mut values: Vec<Value> = vec![filter.alert_type.clone().map_or(Value::Null, Value::from)];
+    values.extend(patient_ids.iter().cloned().map(Value::from));
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params_from_iter(values), |row| {
+        Ok(Alert {
+            alert_id: row.get(0)?,
+            patient_id: row.get(1)?,
+            patient_name: row.get(2)?,
+            alert_type: row.get(3)?,
+            alert_message: row.get(4)?,
+            alert_time: parse_db_time(&row.get::<_, String>(5)?).unwrap_or_default(),
+            is_resolved: row.get(6)?,
+            resolved_by: row.get(7)?,
+            resolved_at: row.get::<_, Option<String>>(8)?.as_deref().and_then(parse_db_time),
+            resolution_note: row.get(9)?,
+            snoozed: row.get(10)?,
+        })
+    })?;
+    rows.collect()
+}
+
+// alert types raised for the given patients, for the inbox's type filter
+pub fn get_alert_types(conn: &Connection, patient_ids: &[String]) -> Result<Vec<String>> {
+    if patient_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sql = format!(
+        "SELECT DISTINCT alert_type FROM alerts WHERE patient_id IN ({}) ORDER BY alert_type",
+        id_placeholders(patient_ids.len(), 0)
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params_from_iter(patient_ids), |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Marks an open alert of one of the given patients as resolved by `user_id`, with a note.
+/// Returns false when there is no such open alert.
+pub fn acknowledge_alert(conn: &Connection, alert_id: i64, patient_ids: &[String], user_id: &str, note: &str) -> Result<bool> {
+    if patient_ids.is_empty() {
+        return Ok(false);
+    }
+    let sql = format!(
+        "UPDATE alerts SET is_resolved = 1, resolved_by = ?1, resolved_at = ?2, resolution_note = ?3
+         WHERE alert_id = ?4 AND is_resolved = 0 AND patient_id IN ({})",
+        id_placeholders(patient_ids.len(), 4)
+    );
+    let mut values: Vec<Value> = vec![
+        user_id.to_string().into(),
+        get_current_db_time_string().into(),
+        note.to_string().into(),
+        alert_id.into(),
+    ];
+    values.extend(patient_ids.iter().cloned().map(Value::from));
+    Ok(conn.execute(&sql, params_from_iter(values))? > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glucose_tiers() {
+        assert_eq!(classify_glucose(50.0, 70.0, 180.0, 55.0), Some(GlucoseAlertLevel::UrgentLow));
+        assert_eq!(classify_glucose(55.0, 70.0, 180.0, 55.0), Some(GlucoseAlertLevel::UrgentLow));
+        assert_eq!(classify_glucose(65.0, 70.0, 180.0, 55.0), Some(GlucoseAlertLevel::Low));
+        assert_eq!(classify_glucose(70.0, 70.0, 180.0, 55.0), None);
+        assert_eq!(classify_glucose(180.0, 70.0, 180.0, 55.0), None);
+        assert_eq!(classify_glucose(181.0, 70.0, 180.0, 55.0), Some(GlucoseAlertLevel::High));
+    }
+
+    #[test]
+    fn urgent_low_never_sits_above_the_low_threshold() {
+        assert_eq!(classify_glucose(75.0, 70.0, 180.0, 80.0), None);
+        assert_eq!(classify_glucose(70.0, 70.0, 180.0, 80.0), Some(GlucoseAlertLevel::UrgentLow));
+    }
+}
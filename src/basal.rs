@@ -0,0 +1,492 @@
+// Basal insulin schedule configuration
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use chrono::{Duration, Local, NaiveDateTime, NaiveTime, Utc};
+use uuid::Uuid;
+use crate::insulin::{get_patient_data_from_patient_table, insert_insulin_log, PatientSafety};
+use crate::suspend::is_basal_suspended;
+use crate::utils::{format_local_time, DB_TIME_FORMAT};
+
+// basal changes only take effect this many hours after submission so they never overlap an earlier dose
+pub const BASAL_CHANGE_DELAY_HOURS: i64 = 24;
+
+// a time-of-day segment; the rate (units/hour) runs from start_time until the next segment starts
+pub struct BasalSegment {
+    pub start_time: NaiveTime,
+    pub rate: f32,
+}
+
+// one submitted schedule made of segments covering the whole day
+pub struct BasalSchedule {
+    pub schedule_id: String,
+    pub segments: Vec<BasalSegment>,
+    pub submitted_at: NaiveDateTime,
+    pub effective_at: NaiveDateTime,
+}
+
+// result of submitting a new basal schedule
+pub enum BasalScheduleOutcome {
+    Scheduled(NaiveDateTime),
+    Refused(String),
+}
+
+/// Checks a set of segments against the patient's prescribed basal rate and max dosage.
+/// Returns `Some(reason)` describing the first problem found, or `None` if the schedule is valid.
+pub fn validate_basal_segments(segments: &[BasalSegment], patient: &PatientSafety) -> Option<String> {
+    if segments.is_empty() {
+        return Some("A basal schedule needs at least one segment.".to_string());
+    }
+
+    if segments[0].start_time != NaiveTime::MIN {
+        return Some("The first segment must start at 00:00 so the whole day is covered.".to_string());
+    }
+
+    for pair in segments.windows(2) {
+        if pair[1].start_time <= pair[0].start_time {
+            return Some(format!(
+                "Segment starting at {} must start after the segment at {}.",
+                pair[1].start_time.format("%H:%M"),
+                pair[0].start_time.format("%H:%M")
+            ));
+        }
+    }
+
+    for segment in segments {
+        let start = segment.start_time.format("%H:%M");
+        if segment.rate < 0.0 {
+            return Some(format!("Rate for segment at {} cannot be negative.", start));
+        }
+        if segment.rate > patient.basal_rate {
+            return Some(format!(
+                "Rate of {:.2} units/hour at {} exceeds the prescribed basal rate of {:.2} units/hour.",
+                segment.rate, start, patient.basal_rate
+            ));
+        }
+        // a segment delivers its rate every hour, so one hour of delivery must stay within the max dose
+        if segment.rate > patient.max_dosage {
+            return Some(format!(
+                "Rate of {:.2} units/hour at {} exceeds the prescribed maximum dose of {:.2} units.",
+                segment.rate, start, patient.max_dosage
+            ));
+        }
+    }
+
+    None
+}
+
+/// Validates and stores a new basal schedule that becomes effective 24 hours from now.
+/// # Return Type
+/// - `Ok(BasalScheduleOutcome::Scheduled(effective_at))` → stored; `effective_at` is in UTC.
+/// - `Ok(BasalScheduleOutcome::Refused(reason))` → the schedule violated a safety limit.
+/// - `Err(e)` → a database error occurred.
+pub fn submit_basal_schedule(conn: &Connection, patient_id: &str, segments: &[BasalSegment], submitted_by: &str) -> Result<BasalScheduleOutcome> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(BasalScheduleOutcome::Refused("No patient record found.".to_string())),
+    };
+
+    if let Some(reason) = validate_basal_segments(segments, &patient) {
+        return Ok(BasalScheduleOutcome::Refused(reason));
+    }
+
+    let schedule_id = Uuid::new_v4().to_string();
+    let submitted_at = Utc::now().naive_utc();
+    let effective_at = submitted_at + Duration::hours(BASAL_CHANGE_DELAY_HOURS);
+
+    // store all segments together so a partial schedule is never left behind
+    let tx = conn.unchecked_transaction()?;
+    for segment in segments {
+        tx.execute(
+            "INSERT INTO basal_schedules (schedule_id, patient_id, start_time, rate, submitted_by, submitted_at, effective_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                schedule_id,
+                patient_id,
+                segment.start_time.format("%H:%M").to_string(),
+                segment.rate,
+                submitted_by,
+                submitted_at.format(DB_TIME_FORMAT).to_string(),
+                effective_at.format(DB_TIME_FORMAT).to_string()
+            ],
+        )?;
+    }
+    tx.commit()?;
+
+    Ok(BasalScheduleOutcome::Scheduled(effective_at))
+}
+
+// loads every schedule for the patient, oldest effective time first
+fn get_basal_schedules(conn: &Connection, patient_id: &str) -> Result<Vec<BasalSchedule>> {
+    let mut stmt = conn.prepare(
+        "SELECT schedule_id, start_time, rate, submitted_at, effective_at
+         FROM basal_schedules
+         WHERE patient_id = ?1
+         ORDER BY datetime(effective_at), schedule_id, start_time",
+    )?;
+
+    let rows = stmt.query_map(params![patient_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, f32>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?,
+        ))
+    })?;
+
+    // group segment rows into their schedules
+    let mut schedules: Vec<BasalSchedule> = Vec::new();
+    for row in rows {
+        let (schedule_id, start_time, rate, submitted_at, effective_at) = row?;
+        let segment = BasalSegment {
+            start_time: NaiveTime::parse_from_str(&start_time, "%H:%M").unwrap_or(NaiveTime::MIN),
+            rate,
+        };
+
+        match schedules.last_mut() {
+            Some(schedule) if schedule.schedule_id == schedule_id => schedule.segments.push(segment),
+            _ => schedules.push(BasalSchedule {
+                schedule_id,
+                segments: vec![segment],
+                submitted_at: NaiveDateTime::parse_from_str(&submitted_at, DB_TIME_FORMAT).unwrap_or_default(),
+                effective_at: NaiveDateTime::parse_from_str(&effective_at, DB_TIME_FORMAT).unwrap_or_default(),
+            }),
+        }
+    }
+
+    Ok(schedules)
+}
+
+// returns the schedule currently in effect (the latest one whose effective time has passed)
+pub fn get_active_basal_schedule(conn: &Connection, patient_id: &str) -> Result<Option<BasalSchedule>> {
+    let now = Utc::now().naive_utc();
+    let schedules = get_basal_schedules(conn, patient_id)?;
+    Ok(schedules.into_iter().rev().find(|s| s.effective_at <= now))
+}
+
+// returns schedules that have been submitted but are not yet in effect
+pub fn get_pending_basal_schedules(conn: &Connection, patient_id: &str) -> Result<Vec<BasalSchedule>> {
+    let now = Utc::now().naive_utc();
+    let schedules = get_basal_schedules(conn, patient_id)?;
+    Ok(schedules.into_iter().filter(|s| s.effective_at > now).collect())
+}
+
+// returns the segment rate that applies at the given local time of day
+pub fn rate_at_time(schedule: &BasalSchedule, time_of_day: NaiveTime) -> Option<f32> {
+    schedule
+        .segments
+        .iter()
+        .rev()
+        .find(|segment| segment.start_time <= time_of_day)
+        .map(|segment| segment.rate)
+}
+
+/// Returns the basal rate (units/hour) scheduled right now.
+/// Falls back to the prescribed `patients.basal_rate` when no schedule is active.
+pub fn get_scheduled_basal_rate(conn: &Connection, patient: &PatientSafety) -> Result<f32> {
+    let scheduled = get_active_basal_schedule(conn, &patient.patient_id)?
+        .and_then(|schedule| rate_at_time(&schedule, Local::now().time()));
+    Ok(scheduled.unwrap_or(patient.basal_rate))
+}
+
+fn print_basal_schedule(schedule: &BasalSchedule) {
+    println!(
+        "Submitted {} | Effective {}",
+        format_local_time(&schedule.submitted_at),
+        format_local_time(&schedule.effective_at)
+    );
+    for segment in &schedule.segments {
+        println!("  * from {} | {:.2} units/hour", segment.start_time.format("%H:%M"), segment.rate);
+    }
+}
+
+// displays the active basal schedule and any pending ones for the patient
+pub fn display_basal_schedules(conn: &Connection, patient_id: &str) {
+    println!("\n--- Active Basal Schedule ---");
+    match get_active_basal_schedule(conn, patient_id) {
+        Ok(Some(schedule)) => print_basal_schedule(&schedule),
+        Ok(None) => println!("No schedule in effect; the prescribed basal rate applies all day."),
+        Err(e) => eprintln!("Error retrieving active basal schedule: {}", e),
+    }
+
+    println!("\n--- Pending Basal Schedules ---");
+    match get_pending_basal_schedules(conn, patient_id) {
+        Ok(pending) if pending.is_empty() => println!("No pending basal schedule changes."),
+        Ok(pending) => pending.iter().for_each(print_basal_schedule),
+        Err(e) => eprintln!("Error retrieving pending basal schedules: {}", e),
+    }
+}
+
+
+//-----------------------Temporary basal rates-----------------------//
+
+// insulin_logs action types for temp basal events (no insulin is delivered by the event itself)
+pub const TEMP_BASAL_START_ACTION: &str = "TempBasalStart";
+pub const TEMP_BASAL_CANCEL_ACTION: &str = "TempBasalCancel";
+pub const TEMP_BASAL_EXPIRE_ACTION: &str = "TempBasalExpire";
+
+// a temp basal may not exceed this percentage of the scheduled rate
+pub const MAX_TEMP_BASAL_PERCENT: f32 = 200.0;
+
+// how a temp basal rate was requested
+pub enum TempBasalMode {
+    // percentage of the scheduled basal rate
+    Percent(f32),
+    // absolute rate in units/hour
+    Absolute(f32),
+}
+
+pub struct TempBasal {
+    pub temp_basal_id: i64,
+    pub mode: String,
+    pub value: f32,
+    pub rate: f32,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+}
+
+// result of starting a temp basal
+pub enum TempBasalOutcome {
+    Started(TempBasal),
+    Refused(String),
+}
+
+impl TempBasal {
+    // short description used for display and for insulin_logs reasons
+    pub fn describe(&self) -> String {
+        let requested = if self.mode == "percent" {
+            format!("{:.0}% of scheduled", self.value)
+        } else {
+            "absolute".to_string()
+        };
+        format!(
+            "{:.2} units/hour ({}) from {} until {}",
+            self.rate, requested, format_local_time(&self.start_time), format_local_time(&self.end_time)
+        )
+    }
+}
+
+/// Marks temp basals whose duration has passed as expired and logs a TempBasalExpire event for each.
+/// Pass `None` to process every patient.
+pub fn expire_temp_basals(conn: &Connection, patient_id: Option<&str>) -> Result<()> {
+    let now = Utc::now().naive_utc().format(DB_TIME_FORMAT).to_string();
+    let mut stmt = conn.prepare(
+        "SELECT temp_basal_id, patient_id, rate, end_time FROM temp_basals
+         WHERE status = 'active' AND datetime(end_time) <= datetime(?1)
+           AND (?2 IS NULL OR patient_id = ?2)",
+    )?;
+    let expired = stmt
+        .query_map(params![now, patient_id], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f32>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (temp_basal_id, patient_id, rate, end_time) in expired {
+        conn.execute(
+            "UPDATE temp_basals SET status = 'expired' WHERE temp_basal_id = ?1",
+            params![temp_basal_id],
+        )?;
+        let reason = format!(
+            "temp basal of {:.2} units/hour ended at {} UTC; scheduled basal resumed",
+            rate, end_time
+        );
+        insert_insulin_log(conn, &patient_id, TEMP_BASAL_EXPIRE_ACTION, 0.0, "system", Some(&reason))?;
+    }
+    Ok(())
+}
+
+// returns the temp basal currently running for the patient, expiring any that have run out first
+pub fn get_active_temp_basal(conn: &Connection, patient_id: &str) -> Result<Option<TempBasal>> {
+    expire_temp_basals(conn, Some(patient_id))?;
+
+    conn.query_row(
+        "SELECT temp_basal_id, mode, value, rate, start_time, end_time FROM temp_basals
+         WHERE patient_id = ?1 AND status = 'active'
+         ORDER BY datetime(start_time) DESC
+         LIMIT 1",
+        params![patient_id],
+        |row| {
+            Ok(TempBasal {
+                temp_basal_id: row.get(0)?,
+                mode: row.get(1)?,
+                value: row.get(2)?,
+                rate: row.get(3)?,
+                start_time: NaiveDateTime::parse_from_str(&row.get::<_, String>(4)?, DB_TIME_FORMAT).unwrap_or_default(),
+                end_time: NaiveDateTime::parse_from_str(&row.get::<_, String>(5)?, DB_TIME_FORMAT).unwrap_or_default(),
+            })
+        },
+    )
+    .optional()
+}
+
+/// Cancels the running temp basal, if any, and logs a TempBasalCancel event.
+/// Returns `Ok(true)` when a temp basal was cancelled.
+pub fn cancel_temp_basal(conn: &Connection, patient_id: &str, requested_by: &str) -> Result<bool> {
+    let active = match get_active_temp_basal(conn, patient_id)? {
+        Some(t) => t,
+        None => return Ok(false),
+    };
+
+    conn.execute(
+        "UPDATE temp_basals SET status = 'cancelled' WHERE temp_basal_id = ?1",
+        params![active.temp_basal_id],
+    )?;
+    let reason = format!("cancelled temp basal of {}; scheduled basal resumed", active.describe());
+    insert_insulin_log(conn, patient_id, TEMP_BASAL_CANCEL_ACTION, 0.0, requested_by, Some(&reason))?;
+    Ok(true)
+}
+
+/// Starts a temp basal for `duration_minutes`, replacing any temp basal already running.
+/// # Return Type
+/// - `Ok(TempBasalOutcome::Started(temp))` → stored and logged as TempBasalStart.
+/// - `Ok(TempBasalOutcome::Refused(reason))` → the rate or duration violated a limit.
+/// - `Err(e)` → a database error occurred.
+pub fn start_temp_basal(conn: &Connection, patient_id: &str, mode: TempBasalMode, duration_minutes: i64, requested_by: &str) -> Result<TempBasalOutcome> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(TempBasalOutcome::Refused("No patient record found.".to_string())),
+    };
+
+    if !(15..=1440).contains(&duration_minutes) {
+        return Ok(TempBasalOutcome::Refused("Duration must be between 15 minutes and 24 hours.".to_string()));
+    }
+
+    let scheduled_rate = get_scheduled_basal_rate(conn, &patient)?;
+    let (mode_name, value, rate) = match mode {
+        TempBasalMode::Percent(percent) => ("percent", percent, scheduled_rate * percent / 100.0),
+        TempBasalMode::Absolute(rate) => ("absolute", rate, rate),
+    };
+
+    if rate < 0.0 {
+        return Ok(TempBasalOutcome::Refused("Temp basal rate cannot be negative.".to_string()));
+    }
+    let max_rate = scheduled_rate * MAX_TEMP_BASAL_PERCENT / 100.0;
+    if rate > max_rate {
+        return Ok(TempBasalOutcome::Refused(format!(
+            "Temp basal of {:.2} units/hour exceeds {:.0}% of the scheduled basal rate ({:.2} units/hour).",
+            rate, MAX_TEMP_BASAL_PERCENT, max_rate
+        )));
+    }
+    if rate > patient.max_dosage {
+        return Ok(TempBasalOutcome::Refused(format!(
+            "Temp basal of {:.2} units/hour exceeds the prescribed maximum dose of {:.2} units.",
+            rate, patient.max_dosage
+        )));
+    }
+
+    // only one temp basal runs at a time
+    cancel_temp_basal(conn, patient_id, requested_by)?;
+
+    let start_time = Utc::now().naive_utc();
+    let end_time = start_time + Duration::minutes(duration_minutes);
+    conn.execute(
+        "INSERT INTO temp_basals (patient_id, mode, value, rate, start_time, end_time, status, requested_by)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'active', ?7)",
+        params![
+            patient_id,
+            mode_name,
+            value,
+            rate,
+            start_time.format(DB_TIME_FORMAT).to_string(),
+            end_time.format(DB_TIME_FORMAT).to_string(),
+            requested_by
+        ],
+    )?;
+
+    let temp = TempBasal {
+        temp_basal_id: conn.last_insert_rowid(),
+        mode: mode_name.to_string(),
+        value,
+        rate,
+        start_time,
+        end_time,
+    };
+    let reason = format!("started temp basal of {} for {} min", temp.describe(), duration_minutes);
+    insert_insulin_log(conn, patient_id, TEMP_BASAL_START_ACTION, 0.0, requested_by, Some(&reason))?;
+
+    Ok(TempBasalOutcome::Started(temp))
+}
+
+/// Returns the basal rate (units/hour) in effect right now: zero while suspended for a predicted low,
+/// else the running temp basal if there is one, otherwise the scheduled rate.
+pub fn get_current_basal_rate(conn: &Connection, patient: &PatientSafety) -> Result<f32> {
+    if is_basal_suspended(conn, &patient.patient_id)? {
+        return Ok(0.0);
+    }
+    match get_active_temp_basal(conn, &patient.patient_id)? {
+        Some(temp) => Ok(temp.rate),
+        None => get_scheduled_basal_rate(conn, patient),
+    }
+}
+
+// Expire finished temp basals in a background thread every 60 seconds
+pub fn run_temp_basal_expiry(db_path: &str) {
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || loop {
+        match Connection::open(&db_path) {
+            Ok(conn) => {
+                let _ = conn.busy_timeout(std::time::Duration::from_secs(5));
+                if let Err(e) = expire_temp_basals(&conn, None) {
+                    eprintln!("Failed to expire temp basals: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to open DB connection for temp basal expiry: {:?}", e),
+        }
+        std::thread::sleep(std::time::Duration::from_secs(60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // prescribed basal 1.0 U/h, max 10 units per hour
+    fn patient() -> PatientSafety {
+        PatientSafety {
+            patient_id: "patient".to_string(),
+            first_name: "Test".to_string(),
+            last_name: "Patient".to_string(),
+            max_dosage: 10.0,
+            low_glucose_threshold: 70.0,
+            high_glucose_threshold: 180.0,
+            basal_rate: 1.0,
+            bolus_rate: 1.0,
+            iob_model: "exponential".to_string(),
+            insulin_action_minutes: 240.0,
+            carb_ratio: Some(10.0),
+            correction_factor: Some(50.0),
+            target_glucose: Some(100.0),
+        }
+    }
+
+    fn segment(start: &str, rate: f32) -> BasalSegment {
+        BasalSegment { start_time: NaiveTime::parse_from_str(start, "%H:%M").unwrap(), rate }
+    }
+
+    #[test]
+    fn whole_day_schedule_within_limits_is_valid() {
+        let segments = [segment("00:00", 0.8), segment("06:00", 1.0), segment("22:00", 0.6)];
+        assert_eq!(validate_basal_segments(&segments, &patient()), None);
+    }
+
+    #[test]
+    fn schedule_must_cover_the_day_in_order() {
+        let patient = patient();
+        assert!(validate_basal_segments(&[], &patient).is_some());
+        assert!(validate_basal_segments(&[segment("01:00", 0.8)], &patient).is_some());
+        assert!(validate_basal_segments(&[segment("00:00", 0.8), segment("06:00", 0.8), segment("06:00", 0.9)], &patient).is_some());
+    }
+
+    #[test]
+    fn rates_must_stay_within_the_prescription() {
+        let mut patient = patient();
+        assert!(validate_basal_segments(&[segment("00:00", -0.1)], &patient).is_some());
+        assert!(validate_basal_segments(&[segment("00:00", 1.5)], &patient).is_some());
+        patient.basal_rate = 20.0;
+        assert!(validate_basal_segments(&[segment("00:00", 12.0)], &patient).is_some());
+    }
+}
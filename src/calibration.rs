@@ -0,0 +1,309 @@
+// Fingerstick calibration of CGM readings and sensor accuracy metrics (MARD, Clarke error grid)
+use chrono::NaiveDateTime;
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use crate::sensor::{get_current_sensor_session, get_sensor_session_at};
+use crate::units::GlucoseUnit;
+use crate::utils::{format_local_time, get_current_db_time_string, DB_TIME_FORMAT};
+
+// a CGM reading within this many minutes of a fingerstick is paired with it
+pub const CALIBRATION_PAIRING_MINUTES: i64 = 5;
+// a fingerstick implying a correction outside this range is recorded but not applied
+pub const MIN_CALIBRATION_FACTOR: f32 = 0.5;
+pub const MAX_CALIBRATION_FACTOR: f32 = 1.5;
+// recent accuracy worse than this (or any pair in zone D/E) is reported as sensor drift
+pub const DRIFT_MARD_PERCENT: f32 = 15.0;
+// accuracy metrics cover this many of the most recent paired fingersticks
+const ACCURACY_PAIR_LIMIT: i64 = 20;
+
+pub enum CalibrationOutcome {
+    // the sensor was calibrated; later readings are multiplied by the factor
+    Calibrated { cgm_glucose: f32, factor: f32 },
+    // the fingerstick was stored for accuracy tracking only
+    Recorded(String),
+}
+
+// the usable reading from the given sensor closest to `time`: (glucose_level, uncalibrated value)
+fn get_paired_cgm_reading(conn: &Connection, patient_id: &str, time: &str, sensor_session_id: Option<i64>) -> Result<Option<(f32, f32)>> {
+    let window = format!("{} minutes", CALIBRATION_PAIRING_MINUTES);
+    conn.query_row(
+        "SELECT glucose_level, COALESCE(raw_glucose_level, glucose_level)
+         FROM glucose_readings
+         WHERE patient_id = ?1
+           AND datetime(reading_time) BETWEEN datetime(?2, '-' || ?3) AND datetime(?2, '+' || ?3)
+           AND sensor_session_id IS ?4
+           AND unusable_reason IS NULL
+         ORDER BY ABS(julianday(reading_time) - julianday(?2)) ASC
+         LIMIT 1",
+        params![patient_id, time, window, sensor_session_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+}
+
+/// Stores a fingerstick value and, when a usable CGM reading was taken within
+/// `CALIBRATION_PAIRING_MINUTES`, calibrates the current sensor so its later readings agree with it.
+/// Corrections outside `MIN_CALIBRATION_FACTOR`–`MAX_CALIBRATION_FACTOR` are not applied because
+/// such a large disagreement points at a faulty sensor or a bad fingerstick. The reason for not
+/// calibrating gives glucose values in `unit`.
+pub fn log_fingerstick(conn: &Connection, patient_id: &str, fingerstick_glucose: f32, entered_by: &str, unit: GlucoseUnit) -> Result<CalibrationOutcome> {
+    let now = get_current_db_time_string();
+    let sensor_session_id = get_sensor_session_at(conn, patient_id, &now)?.map(|sensor| sensor.sensor_session_id);
+    let paired = get_paired_cgm_reading(conn, patient_id, &now, sensor_session_id)?;
+
+    let (cgm_glucose, factor, outcome) = match paired {
+        None => (
+            None,
+            None,
+            CalibrationOutcome::Recorded(format!(
+                "no CGM reading within {} minutes to calibrate against",
+                CALIBRATION_PAIRING_MINUTES
+            )),
+        ),
+        Some((cgm_glucose, raw_glucose)) => {
+            let factor = fingerstick_glucose / raw_glucose;
+            if (MIN_CALIBRATION_FACTOR..=MAX_CALIBRATION_FACTOR).contains(&factor) {
+                (Some(cgm_glucose), Some(factor), CalibrationOutcome::Calibrated { cgm_glucose, factor })
+            } else {
+                (
+                    Some(cgm_glucose),
+                    None,
+                    CalibrationOutcome::Recorded(format!(
+                        "fingerstick {} differs too much from the sensor ({}); check the sensor",
+                        unit.format(fingerstick_glucose), unit.format(cgm_glucose)
+                    )),
+                )
+            }
+        }
+    };
+
+    conn.execute(
+        "INSERT INTO calibrations (patient_id, fingerstick_glucose, cgm_glucose, correction_factor, calibration_time, entered_by, sensor_session_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![patient_id, fingerstick_glucose, cgm_glucose, factor, now, entered_by, sensor_session_id],
+    )?;
+    Ok(outcome)
+}
+
+/// Calibration in effect for a reading taken at `reading_time` by the given sensor: the latest
+/// applied calibration of that sensor made at or before it, as `(calibration_id, correction_factor)`.
+/// A new sensor starts uncalibrated.
+pub fn get_active_calibration(conn: &Connection, patient_id: &str, reading_time: &str, sensor_session_id: Option<i64>) -> Result<Option<(i64, f32)>> {
+    conn.query_row(
+        "SELECT calibration_id, correction_factor FROM calibrations
+         WHERE patient_id = ?1 AND correction_factor IS NOT NULL
+           AND datetime(calibration_time) <= datetime(?2)
+           AND sensor_session_id IS ?3
+         ORDER BY datetime(calibration_time) DESC, calibration_id DESC
+         LIMIT 1",
+        params![patient_id, reading_time, sensor_session_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+}
+
+//-----------------------Sensor accuracy-----------------------//
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClarkeZone {
+    A,
+    B,
+    C,
+    D,
+    E,
+}
+
+impl ClarkeZone {
+    pub const ALL: [ClarkeZone; 5] = [ClarkeZone::A, ClarkeZone::B, ClarkeZone::C, ClarkeZone::D, ClarkeZone::E];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ClarkeZone::A => "clinically accurate",
+            ClarkeZone::B => "benign error",
+            ClarkeZone::C => "overcorrection",
+            ClarkeZone::D => "failure to detect",
+            ClarkeZone::E => "erroneous treatment",
+        }
+    }
+}
+
+/// Clarke error grid zone of a CGM value against a reference (fingerstick) value, both in mg/dL.
+pub fn clarke_zone(reference: f32, cgm: f32) -> ClarkeZone {
+    let (x, y) = (reference, cgm);
+    if (x <= 70.0 && y <= 70.0) || (y <= 1.2 * x && y >= 0.8 * x) {
+        ClarkeZone::A
+    } else if (x >= 180.0 && y <= 70.0) || (x <= 70.0 && y >= 180.0) {
+        ClarkeZone::E
+    } else if ((70.0..=290.0).contains(&x) && y >= x + 110.0)
+        || ((130.0..=180.0).contains(&x) && y <= (7.0 / 5.0) * x - 182.0)
+    {
+        ClarkeZone::C
+    } else if (x >= 240.0 && (70.0..=180.0).contains(&y))
+        || (x <= 175.0 / 3.0 && (70.0..=180.0).contains(&y))
+        || ((175.0 / 3.0..=70.0).contains(&x) && y >= (6.0 / 5.0) * x)
+    {
+        ClarkeZone::D
+    } else {
+        ClarkeZone::B
+    }
+}
+
+pub struct SensorAccuracy {
+    pub pairs: usize,
+    // mean absolute relative difference of the CGM against the fingersticks, in percent
+    pub mard_percent: f32,
+    // number of pairs in each Clarke zone, in ClarkeZone::ALL order
+    pub zone_counts: [usize; 5],
+}
+
+impl SensorAccuracy {
+    pub fn zone_percent(&self, zone: ClarkeZone) -> f32 {
+        let index = ClarkeZone::ALL.iter().position(|z| *z == zone).unwrap_or(0);
+        self.zone_counts[index] as f32 * 100.0 / self.pairs as f32
+    }
+
+    // large relative error, or any pair that would lead to missed or wrong treatment
+    pub fn is_drifting(&self) -> bool {
+        self.mard_percent > DRIFT_MARD_PERCENT
+            || self.zone_counts[3] > 0
+            || self.zone_counts[4] > 0
+    }
+}
+
+/// MARD and Clarke zone distribution of (fingerstick, cgm) pairs. Returns `None` without pairs.
+pub fn calculate_sensor_accuracy(pairs: &[(f32, f32)]) -> Option<SensorAccuracy> {
+    let valid: Vec<&(f32, f32)> = pairs.iter().filter(|(reference, _)| *reference > 0.0).collect();
+    if valid.is_empty() {
+        return None;
+    }
+
+    let mard_percent = valid
+        .iter()
+        .map(|(reference, cgm)| (cgm - reference).abs() / reference)
+        .sum::<f32>()
+        * 100.0
+        / valid.len() as f32;
+
+    let mut zone_counts = [0usize; 5];
+    for (reference, cgm) in &valid {
+        let zone = clarke_zone(*reference, *cgm);
+        if let Some(index) = ClarkeZone::ALL.iter().position(|z| *z == zone) {
+            zone_counts[index] += 1;
+        }
+    }
+
+    Some(SensorAccuracy { pairs: valid.len(), mard_percent, zone_counts })
+}
+
+// one logged fingerstick and the CGM reading it was paired with
+pub struct Calibration {
+    pub calibration_time: String,
+    pub fingerstick_glucose: f32,
+    pub cgm_glucose: Option<f32>,
+    pub correction_factor: Option<f32>,
+}
+
+// recent calibrations of the patient's current sensor, newest first
+pub fn get_recent_calibrations(conn: &Connection, patient_id: &str) -> Result<Vec<Calibration>> {
+    let sensor_session_id = get_current_sensor_session(conn, patient_id)?.map(|sensor| sensor.sensor_session_id);
+    let mut stmt = conn.prepare(
+        "SELECT calibration_time, fingerstick_glucose, cgm_glucose, correction_factor
+         FROM calibrations
+         WHERE patient_id = ?1 AND sensor_session_id IS ?2
+         ORDER BY datetime(calibration_time) DESC, calibration_id DESC
+         LIMIT ?3",
+    )?;
+    stmt.query_map(params![patient_id, sensor_session_id, ACCURACY_PAIR_LIMIT], |row| {
+        Ok(Calibration {
+            calibration_time: row.get(0)?,
+            fingerstick_glucose: row.get(1)?,
+            cgm_glucose: row.get(2)?,
+            correction_factor: row.get(3)?,
+        })
+    })?
+    .collect()
+}
+
+/// Accuracy of the patient's current sensor over its most recent paired fingersticks.
+pub fn get_sensor_accuracy(conn: &Connection, patient_id: &str) -> Result<Option<SensorAccuracy>> {
+    let pairs: Vec<(f32, f32)> = get_recent_calibrations(conn, patient_id)?
+        .into_iter()
+        .filter_map(|c| c.cgm_glucose.map(|cgm| (c.fingerstick_glucose, cgm)))
+        .collect();
+    Ok(calculate_sensor_accuracy(&pairs))
+}
+
+// prints recent fingersticks next to the CGM, then MARD, the Clarke zone distribution and a drift warning
+pub fn display_sensor_accuracy(conn: &Connection, patient_id: &str, unit: GlucoseUnit) {
+    let calibrations = match get_recent_calibrations(conn, patient_id) {
+        Ok(calibrations) => calibrations,
+        Err(e) => {
+            eprintln!("Error retrieving calibrations: {}", e);
+            return;
+        }
+    };
+
+    println!("\n--- Fingerstick Calibrations ---");
+    if let Ok(Some(sensor)) = get_current_sensor_session(conn, patient_id) {
+        println!("Sensor: {}", sensor.serial_number);
+    }
+    if calibrations.is_empty() {
+        println!("No fingerstick values have been logged for this sensor.");
+        return;
+    }
+    for calibration in &calibrations {
+        let time = NaiveDateTime::parse_from_str(&calibration.calibration_time, DB_TIME_FORMAT)
+            .map(|t| format_local_time(&t))
+            .unwrap_or_else(|_| calibration.calibration_time.clone());
+        let cgm = match calibration.cgm_glucose {
+            Some(cgm) => format!("CGM: {} ({})", unit.format(cgm), unit.format_delta(cgm - calibration.fingerstick_glucose)),
+            None => "CGM: none".to_string(),
+        };
+        let applied = match calibration.correction_factor {
+            Some(factor) => format!("calibrated ×{:.2}", factor),
+            None => "not applied".to_string(),
+        };
+        println!("* {} | Fingerstick: {} | {} | {}", time, unit.format(calibration.fingerstick_glucose), cgm, applied);
+    }
+
+    match get_sensor_accuracy(conn, patient_id) {
+        Ok(Some(accuracy)) => {
+            println!("\n--- Sensor Accuracy (last {} paired values) ---", accuracy.pairs);
+            println!("MARD: {:.1}%", accuracy.mard_percent);
+            for zone in ClarkeZone::ALL {
+                println!("Zone {:?} ({}): {:.0}%", zone, zone.description(), accuracy.zone_percent(zone));
+            }
+            if accuracy.is_drifting() {
+                println!("WARNING: the sensor appears to be drifting. Consider recalibrating or replacing it.");
+            }
+        }
+        Ok(None) => println!("No fingerstick has a CGM reading to compare against yet."),
+        Err(e) => eprintln!("Error computing sensor accuracy: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clarke_zones() {
+        assert_eq!(clarke_zone(100.0, 110.0), ClarkeZone::A);
+        assert_eq!(clarke_zone(50.0, 60.0), ClarkeZone::A);
+        assert_eq!(clarke_zone(100.0, 130.0), ClarkeZone::B);
+        assert_eq!(clarke_zone(100.0, 250.0), ClarkeZone::C);
+        assert_eq!(clarke_zone(300.0, 150.0), ClarkeZone::D);
+        assert_eq!(clarke_zone(200.0, 50.0), ClarkeZone::E);
+        assert_eq!(clarke_zone(50.0, 200.0), ClarkeZone::E);
+    }
+
+    #[test]
+    fn accuracy_from_pairs() {
+        assert!(calculate_sensor_accuracy(&[]).is_none());
+        // the pair without a usable reference is skipped
+        let accuracy = calculate_sensor_accuracy(&[(100.0, 110.0), (200.0, 180.0), (0.0, 90.0)]).unwrap();
+        assert_eq!(accuracy.pairs, 2);
+        assert!((accuracy.mard_percent - 10.0).abs() < 1e-3);
+        assert_eq!(accuracy.zone_counts, [2, 0, 0, 0, 0]);
+        assert!(!accuracy.is_drifting());
+    }
+}
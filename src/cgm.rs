@@ -1,2 +1,357 @@
 // Continuous Glucose Monitoring Simulation and parsing
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::time::Duration as StdDuration;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rand::Rng;
+use rusqlite::{Connection, Result, params};
+use crate::alerts::evaluate_reading_alerts;
+use crate::basal::{get_current_basal_rate, get_scheduled_basal_rate};
+use crate::calibration::get_active_calibration;
+use crate::config::env_or;
+use crate::controller::CONTROLLER_BASAL_ACTION;
+use crate::filter::{filter_new_reading, FilteredReading, GlucoseSource};
+use crate::insulin::{counts_toward_iob, get_extended_bolus_deliveries_since, get_patient_data_from_patient_table,
+                     get_recent_glucose_readings, PatientSafety};
+use crate::sensor::get_sensor_session_at;
+use crate::utils::DB_TIME_FORMAT;
 
+// status stored with readings produced by the simulator
+pub const SIMULATED_STATUS: &str = "simulated";
+// CGMs report readings inside this range (mg/dL) and clamp anything outside it
+pub const SENSOR_MIN_MG_DL: f32 = 40.0;
+pub const SENSOR_MAX_MG_DL: f32 = 400.0;
+
+// Bergman minimal model parameters for an adult with type 1 diabetes
+// glucose effectiveness (1/min); small because there is no endogenous insulin response
+const P1: f32 = 0.0005;
+// rate the remote insulin action decays (1/min)
+const P2: f32 = 0.025;
+// plasma insulin clearance (1/min)
+const INSULIN_CLEARANCE: f32 = 0.09;
+// plasma insulin distribution volume (mL)
+const INSULIN_VOLUME_ML: f32 = 8400.0;
+// time to peak of subcutaneous insulin absorption (min)
+const INSULIN_ABSORPTION_MINUTES: f32 = 55.0;
+// time to peak of carbohydrate absorption from the gut (min)
+const CARB_ABSORPTION_MINUTES: f32 = 40.0;
+// fraction of eaten carbohydrate that reaches the blood
+const CARB_BIOAVAILABILITY: f32 = 0.8;
+// used when the clinician has not prescribed a correction factor / carb ratio
+pub const DEFAULT_CORRECTION_FACTOR: f32 = 50.0;
+pub const DEFAULT_CARB_RATIO: f32 = 10.0;
+// controller decisions newer than this replace the programmed basal in the simulation
+const CONTROLLER_BASAL_MINUTES: i64 = 30;
+// a recent reading is used as the starting glucose for a new virtual patient
+const START_READING_MINUTES: i64 = 60;
+
+// simulator settings; every value can be overridden from the environment
+pub struct CgmSimulatorSettings {
+    pub enabled: bool,
+    // seconds between emitted readings
+    pub interval_secs: u64,
+    // standard deviation of the sensor noise (mg/dL)
+    pub noise_sd: f32,
+    // blood-to-interstitial lag of the sensor (min)
+    pub sensor_lag_minutes: f32,
+}
+
+impl CgmSimulatorSettings {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_or("GLUCOGUARD_CGM_SIM_ENABLED", false),
+            interval_secs: env_or("GLUCOGUARD_CGM_SIM_INTERVAL_SECS", 300),
+            noise_sd: env_or("GLUCOGUARD_CGM_SIM_NOISE_SD", 4.0),
+            sensor_lag_minutes: env_or("GLUCOGUARD_CGM_SIM_LAG_MINUTES", 10.0),
+        }
+    }
+}
+
+/// A virtual patient following the Bergman minimal model, with two-compartment subcutaneous
+/// insulin absorption, two-compartment gut absorption and a first-order sensor lag.
+/// Insulin sensitivity and glucose distribution volume are fitted to the patient's
+/// correction factor and carb ratio so 1 U and 1 g have roughly the effect the clinician prescribed.
+pub struct VirtualPatient {
+    // plasma glucose (mg/dL)
+    glucose: f32,
+    // interstitial glucose seen by the sensor (mg/dL)
+    sensor_glucose: f32,
+    // remote insulin action (1/min)
+    insulin_action: f32,
+    // plasma insulin (µU/mL)
+    plasma_insulin: f32,
+    // subcutaneous insulin depots (U)
+    insulin_depot: [f32; 2],
+    // carbohydrate in the gut (mg)
+    gut: [f32; 2],
+    // steady-state glucose and plasma insulin under the scheduled basal rate
+    basal_glucose: f32,
+    basal_insulin: f32,
+    // insulin sensitivity (mL/µU/min²) and glucose distribution volume (dL)
+    p3: f32,
+    glucose_volume_dl: f32,
+    // model time the state corresponds to
+    last_step: NaiveDateTime,
+}
+
+impl VirtualPatient {
+    // starts at steady state under the scheduled basal rate, at the given glucose (or the target)
+    pub fn new(patient: &PatientSafety, scheduled_rate: f32, start_glucose: Option<f32>, now: NaiveDateTime) -> Self {
+        let basal_glucose = patient
+            .target_glucose
+            .unwrap_or((patient.low_glucose_threshold + patient.high_glucose_threshold) / 2.0);
+        let correction_factor = patient.correction_factor.unwrap_or(DEFAULT_CORRECTION_FACTOR);
+        let carb_ratio = patient.carb_ratio.unwrap_or(DEFAULT_CARB_RATIO);
+
+        // 1 U gives a plasma insulin exposure of 1e6 / (V·n) µU·min/mL; choose p3 so that exposure
+        // lowers glucose at the basal level by the correction factor
+        let exposure = 1.0e6 / (INSULIN_VOLUME_ML * INSULIN_CLEARANCE);
+        let drop = (correction_factor / basal_glucose).min(0.9);
+        let p3 = -(1.0 - drop).ln() * P2 / exposure;
+        // 1 g of carbohydrate raises glucose by correction_factor / carb_ratio
+        let glucose_volume_dl = CARB_BIOAVAILABILITY * 1000.0 * carb_ratio / correction_factor;
+
+        let basal_per_minute = scheduled_rate / 60.0;
+        let basal_insulin = basal_per_minute * 1.0e6 / (INSULIN_VOLUME_ML * INSULIN_CLEARANCE);
+        let depot = basal_per_minute * INSULIN_ABSORPTION_MINUTES;
+        let start_glucose = start_glucose.unwrap_or(basal_glucose);
+
+        Self {
+            glucose: start_glucose,
+            sensor_glucose: start_glucose,
+            insulin_action: 0.0,
+            plasma_insulin: basal_insulin,
+            insulin_depot: [depot, depot],
+            gut: [0.0, 0.0],
+            basal_glucose,
+            basal_insulin,
+            p3,
+            glucose_volume_dl,
+            last_step: now,
+        }
+    }
+
+    pub fn add_insulin(&mut self, units: f32) {
+        self.insulin_depot[0] += units.max(0.0);
+    }
+
+    pub fn add_carbs(&mut self, grams: f32) {
+        self.gut[0] += grams.max(0.0) * 1000.0;
+    }
+
+    // advances the model by one minute with a continuous infusion of `basal_per_minute` U/min
+    pub fn step_minute(&mut self, basal_per_minute: f32, sensor_lag_minutes: f32) {
+        let absorbed_insulin = self.insulin_depot[1] / INSULIN_ABSORPTION_MINUTES;
+        self.insulin_depot[1] += self.insulin_depot[0] / INSULIN_ABSORPTION_MINUTES - absorbed_insulin;
+        self.insulin_depot[0] += basal_per_minute - self.insulin_depot[0] / INSULIN_ABSORPTION_MINUTES;
+
+        let absorbed_carbs = self.gut[1] / CARB_ABSORPTION_MINUTES;
+        self.gut[1] += self.gut[0] / CARB_ABSORPTION_MINUTES - absorbed_carbs;
+        self.gut[0] -= self.gut[0] / CARB_ABSORPTION_MINUTES;
+        let glucose_appearance = CARB_BIOAVAILABILITY * absorbed_carbs / self.glucose_volume_dl;
+
+        self.plasma_insulin += absorbed_insulin * 1.0e6 / INSULIN_VOLUME_ML - INSULIN_CLEARANCE * self.plasma_insulin;
+        self.insulin_action += -P2 * self.insulin_action + self.p3 * (self.plasma_insulin - self.basal_insulin);
+        self.glucose += -(P1 + self.insulin_action) * self.glucose + P1 * self.basal_glucose + glucose_appearance;
+        self.glucose = self.glucose.max(10.0);
+
+        self.sensor_glucose += (self.glucose - self.sensor_glucose) / sensor_lag_minutes.max(1.0);
+        self.last_step += Duration::minutes(1);
+    }
+
+    // the value a sensor would report now: lagged, noisy and clamped to the sensor range
+    pub fn sensor_reading(&self, noise_sd: f32) -> f32 {
+        let mut rng = rand::thread_rng();
+        // Box-Muller transform for a normally distributed noise sample
+        let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+        let u2: f32 = rng.gen_range(0.0..1.0);
+        let noise = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos() * noise_sd;
+        (self.sensor_glucose + noise).clamp(SENSOR_MIN_MG_DL, SENSOR_MAX_MG_DL)
+    }
+}
+
+// stores one CGM reading; reading_time is UTC in DB_TIME_FORMAT.
+// the sensor value is kept as raw_glucose_level and corrected by the calibration in effect at reading_time;
+// the corrected value is then filtered and stored with its smoothed value and outlier flag.
+// the reading is linked to the sensor worn at reading_time; readings during its warm-up or after its
+// wear time are stored as unusable and left out of filtering, trends and dosing
+pub fn insert_glucose_reading(conn: &Connection, patient_id: &str, glucose_level: f32, reading_time: &str, status: &str) -> Result<i64> {
+    let sensor = get_sensor_session_at(conn, patient_id, reading_time)?;
+    let sensor_session_id = sensor.as_ref().map(|s| s.sensor_session_id);
+    let unusable_reason = match (&sensor, NaiveDateTime::parse_from_str(reading_time, DB_TIME_FORMAT)) {
+        (Some(sensor), Ok(time)) => sensor.unusable_reason(&time),
+        _ => None,
+    };
+
+    let calibration = get_active_calibration(conn, patient_id, reading_time, sensor_session_id)?;
+    let calibrated_level = calibration.map_or(glucose_level, |(_, factor)| glucose_level * factor);
+    let filtered = match unusable_reason {
+        Some(_) => FilteredReading::unfiltered(calibrated_level),
+        None => filter_new_reading(conn, patient_id, reading_time, calibrated_level)?,
+    };
+    conn.execute(
+        "INSERT INTO glucose_readings (patient_id, glucose_level, reading_time, status, raw_glucose_level, calibration_id,
+                                       smoothed_glucose_level, reading_quality, sensor_session_id, unusable_reason)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            patient_id,
+            calibrated_level,
+            reading_time,
+            status,
+            glucose_level,
+            calibration.map(|(id, _)| id),
+            filtered.smoothed,
+            filtered.quality.as_db_value(),
+            sensor_session_id,
+            unusable_reason
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+// insulin deliveries logged after `since`, as (time, action_type, units)
+fn get_insulin_deliveries_since(conn: &Connection, patient_id: &str, since: &NaiveDateTime) -> Result<Vec<(NaiveDateTime, String, f32)>> {
+    let mut stmt = conn.prepare(
+        "SELECT dosage_time, action_type, dosage_units FROM insulin_logs
+         WHERE patient_id = ?1 AND datetime(dosage_time) > datetime(?2)
+         ORDER BY datetime(dosage_time) ASC",
+    )?;
+    let rows = stmt.query_map(params![patient_id, since.format(DB_TIME_FORMAT).to_string()], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, f32>(2)?))
+    })?;
+
+    let mut deliveries = Vec::new();
+    for row in rows {
+        let (time_str, action_type, units) = row?;
+        if !counts_toward_iob(&action_type) {
+            continue;
+        }
+        if let Ok(time) = NaiveDateTime::parse_from_str(&time_str, DB_TIME_FORMAT) {
+            deliveries.push((time, action_type, units));
+        }
+    }
+    Ok(deliveries)
+}
+
+// meals logged after `since`, as (time, grams of carbohydrate)
+fn get_meals_since(conn: &Connection, patient_id: &str, since: &NaiveDateTime) -> Result<Vec<(NaiveDateTime, f32)>> {
+    let mut stmt = conn.prepare(
+        "SELECT meal_time, carbohydrate_amount FROM meal_logs
+         WHERE patient_id = ?1 AND datetime(meal_time) > datetime(?2)
+         ORDER BY datetime(meal_time) ASC",
+    )?;
+    let rows = stmt.query_map(params![patient_id, since.format(DB_TIME_FORMAT).to_string()], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f32>(1)?))
+    })?;
+
+    let mut meals = Vec::new();
+    for row in rows {
+        let (time_str, grams) = row?;
+        if let Ok(time) = NaiveDateTime::parse_from_str(&time_str, DB_TIME_FORMAT) {
+            meals.push((time, grams));
+        }
+    }
+    Ok(meals)
+}
+
+/// Advances a patient's virtual model to now and stores one simulated reading.
+/// Logged insulin deliveries and meals are applied at the minute they happened, and the extended
+/// part of extended and dual-wave boluses minute by minute over its window; the programmed
+/// basal (schedule, temp basal or suspend) is infused continuously unless the closed-loop
+/// controller has recently logged its own basal decisions, which are then applied as deliveries.
+pub fn simulate_patient_reading(conn: &Connection, patient_id: &str, model: &mut VirtualPatient, settings: &CgmSimulatorSettings) -> Result<f32> {
+    let now = Utc::now().naive_utc();
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(model.sensor_reading(settings.noise_sd)),
+    };
+
+    let deliveries = get_insulin_deliveries_since(conn, patient_id, &model.last_step)?;
+    let extended_deliveries = get_extended_bolus_deliveries_since(conn, patient_id, &model.last_step)?;
+    let meals = get_meals_since(conn, patient_id, &model.last_step)?;
+    let controller_active = deliveries
+        .iter()
+        .any(|(time, action, _)| action == CONTROLLER_BASAL_ACTION && *time > now - Duration::minutes(CONTROLLER_BASAL_MINUTES));
+    let basal_per_minute = if controller_active { 0.0 } else { get_current_basal_rate(conn, &patient)? / 60.0 };
+
+    let mut pending_deliveries = deliveries.iter().peekable();
+    let mut pending_extended = extended_deliveries.iter().peekable();
+    let mut pending_meals = meals.iter().peekable();
+    while model.last_step + Duration::minutes(1) <= now {
+        let step_end = model.last_step + Duration::minutes(1);
+        while let Some((_, _, units)) = pending_deliveries.next_if(|(time, _, _)| *time <= step_end) {
+            model.add_insulin(*units);
+        }
+        while let Some((_, units)) = pending_extended.next_if(|(time, _)| *time <= step_end) {
+            model.add_insulin(*units);
+        }
+        while let Some((_, grams)) = pending_meals.next_if(|(time, _)| *time <= step_end) {
+            model.add_carbs(*grams);
+        }
+        model.step_minute(basal_per_minute, settings.sensor_lag_minutes);
+    }
+
+    let reading = model.sensor_reading(settings.noise_sd);
+    insert_glucose_reading(conn, patient_id, reading, &now.format(DB_TIME_FORMAT).to_string(), SIMULATED_STATUS)?;
+    evaluate_reading_alerts(conn, patient_id)?;
+    Ok(reading)
+}
+
+// creates a virtual patient starting from the latest recent reading (or the target glucose)
+fn new_virtual_patient(conn: &Connection, patient_id: &str) -> Result<Option<VirtualPatient>> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let scheduled_rate = get_scheduled_basal_rate(conn, &patient)?;
+    let start_glucose = get_recent_glucose_readings(conn, patient_id, START_READING_MINUTES, GlucoseSource::Smoothed)?
+        .last()
+        .map(|(_, glucose)| *glucose);
+    Ok(Some(VirtualPatient::new(&patient, scheduled_rate, start_glucose, Utc::now().naive_utc())))
+}
+
+// emits one simulated reading for every patient, creating virtual patients as they appear
+pub fn simulate_all_patients(conn: &Connection, models: &mut HashMap<String, VirtualPatient>, settings: &CgmSimulatorSettings) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT patient_id FROM patients")?;
+    let patient_ids = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?;
+
+    for patient_id in patient_ids {
+        let model = match models.entry(patient_id.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match new_virtual_patient(conn, &patient_id)? {
+                Some(model) => entry.insert(model),
+                None => continue,
+            },
+        };
+        if let Err(e) = simulate_patient_reading(conn, &patient_id, model, settings) {
+            eprintln!("CGM simulation failed for patient {}: {}", patient_id, e);
+        }
+    }
+    Ok(())
+}
+
+// Run the CGM simulator in a background thread every settings.interval_secs (when enabled)
+pub fn run_cgm_simulator(db_path: &str, settings: CgmSimulatorSettings) {
+    if !settings.enabled {
+        return;
+    }
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || {
+        let mut models: HashMap<String, VirtualPatient> = HashMap::new();
+        loop {
+            std::thread::sleep(StdDuration::from_secs(settings.interval_secs));
+            match Connection::open(&db_path) {
+                Ok(conn) => {
+                    // the menus share the database, so wait for their writes instead of failing
+                    let _ = conn.busy_timeout(StdDuration::from_secs(5));
+                    if let Err(e) = simulate_all_patients(&conn, &mut models, &settings) {
+                        eprintln!("CGM simulation failed: {:?}", e);
+                    }
+                }
+                Err(e) => eprintln!("Failed to open DB connection for CGM simulator: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,14 @@
+// Runtime settings read from the environment (or a .env file), with built-in defaults
+use std::env;
+use std::str::FromStr;
+
+// path of the SQLite database shared by the menus and the background workers
+pub const DB_PATH: &str = "./data/database.db";
+
+// returns the environment variable parsed as T, or the default when it is unset or invalid
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.trim().parse::<T>().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,273 @@
+// Closed-loop automated basal controller
+use std::time::Duration;
+use chrono::NaiveDateTime;
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use crate::basal::get_current_basal_rate;
+use crate::config::env_or;
+use crate::filter::{GlucoseSource, GlucoseSourceSettings};
+use crate::forecast::evaluate_forecast_low;
+use crate::insulin::{get_patient_data_from_patient_table, get_recent_glucose_readings,
+                    insert_insulin_log, PatientSafety};
+use crate::suspend::{evaluate_low_glucose_suspend, is_basal_suspended};
+use crate::signal::{is_glucose_data_stale, SignalSettings};
+use crate::trend::glucose_trend;
+use crate::utils::DB_TIME_FORMAT;
+
+// requested_by value for every decision the controller writes to insulin_logs
+pub const CONTROLLER_REQUESTER: &str = "controller";
+// action_type for a controller temp basal decision
+pub const CONTROLLER_BASAL_ACTION: &str = "ControllerBasal";
+
+// how far back the controller looks at glucose readings (also the integral window)
+const LOOKBACK_MINUTES: i64 = 60;
+
+// tuning for the PID controller; every value can be overridden from the environment
+pub struct ControllerSettings {
+    pub enabled: bool,
+    // seconds between controller cycles (one temp basal decision covers one cycle)
+    pub interval_secs: u64,
+    // units/hour added per mg/dL above target
+    pub kp: f32,
+    // units/hour added per mg/dL of average error over the lookback window
+    pub ki: f32,
+    // units/hour added per mg/dL/min of glucose rate of change
+    pub kd: f32,
+    // no decision is made when the latest reading is older than this (see signal::SignalSettings)
+    pub stale_after_minutes: i64,
+    // raw or smoothed readings (see filter::GlucoseSourceSettings)
+    pub glucose_source: GlucoseSource,
+}
+
+impl ControllerSettings {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_or("GLUCOGUARD_CONTROLLER_ENABLED", false),
+            interval_secs: env_or("GLUCOGUARD_CONTROLLER_INTERVAL_SECS", 300),
+            kp: env_or("GLUCOGUARD_CONTROLLER_KP", 0.01),
+            ki: env_or("GLUCOGUARD_CONTROLLER_KI", 0.005),
+            kd: env_or("GLUCOGUARD_CONTROLLER_KD", 0.2),
+            stale_after_minutes: SignalSettings::from_env().stale_after_minutes(),
+            glucose_source: GlucoseSourceSettings::from_env().dosing,
+        }
+    }
+}
+
+// a temp basal proposed for the next cycle
+pub struct ControllerDecision {
+    // units delivered over one cycle at that rate
+    pub units: f32,
+    pub reason: String,
+}
+
+// glucose the controller steers towards: the prescribed target, or the middle of the threshold range
+fn target_glucose(patient: &PatientSafety) -> f32 {
+    patient
+        .target_glucose
+        .unwrap_or((patient.low_glucose_threshold + patient.high_glucose_threshold) / 2.0)
+}
+
+/// Proposes a temp basal for one cycle from recent readings (oldest first).
+/// `scheduled_rate` is the baseline in effect (temp basal or schedule). The rate is bounded below
+/// by zero and above by that rate, the prescribed `basal_rate` and the hourly `max_dosage`, so the
+/// controller only ever reduces basal; it is zero at or below the low glucose threshold.
+pub fn decide_basal(patient: &PatientSafety, scheduled_rate: f32, readings: &[(NaiveDateTime, f32)], settings: &ControllerSettings) -> Option<ControllerDecision> {
+    let (_, latest) = *readings.last()?;
+    let target = target_glucose(patient);
+    let cycle_hours = settings.interval_secs as f32 / 3600.0;
+
+    if latest <= patient.low_glucose_threshold {
+        return Some(ControllerDecision {
+            units: 0.0,
+            reason: format!(
+                "glucose {:.1} mg/dL at or below low threshold {:.1} mg/dL: basal stopped",
+                latest, patient.low_glucose_threshold
+            ),
+        });
+    }
+
+    let error = latest - target;
+    let mean_error = readings.iter().map(|(_, g)| g - target).sum::<f32>() / readings.len() as f32;
+    let trend = glucose_trend(readings).map_or(0.0, |trend| trend.rate_per_minute);
+    let adjustment = settings.kp * error + settings.ki * mean_error + settings.kd * trend;
+
+    let upper = scheduled_rate.min(patient.basal_rate).min(patient.max_dosage);
+    let rate = (scheduled_rate + adjustment).clamp(0.0, upper.max(0.0));
+
+    Some(ControllerDecision {
+        units: rate * cycle_hours,
+        reason: format!(
+            "glucose {:.1} mg/dL (target {:.1} mg/dL), trend {:+.2} mg/dL/min: temp basal {:.2} U/h (scheduled {:.2} U/h)",
+            latest, target, trend, rate, scheduled_rate
+        ),
+    })
+}
+
+// time of the last decision the controller logged for the patient
+fn get_last_decision_time(conn: &Connection, patient_id: &str) -> Result<Option<NaiveDateTime>> {
+    let last: Option<String> = conn
+        .query_row(
+            "SELECT MAX(datetime(dosage_time)) FROM insulin_logs
+             WHERE patient_id = ?1 AND requested_by = ?2",
+            params![patient_id, CONTROLLER_REQUESTER],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    Ok(last.and_then(|t| NaiveDateTime::parse_from_str(&t, DB_TIME_FORMAT).ok()))
+}
+
+// runs one controller cycle for a patient; only acts when a reading arrived since the last decision
+pub fn run_controller_cycle(conn: &Connection, patient_id: &str, settings: &ControllerSettings) -> Result<Option<ControllerDecision>> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+
+    let readings = get_recent_glucose_readings(conn, patient_id, LOOKBACK_MINUTES, settings.glucose_source)?;
+    let newest_reading = match readings.last() {
+        Some((time, _)) => *time,
+        None => return Ok(None),
+    };
+    if get_last_decision_time(conn, patient_id)?.is_some_and(|last_decision| newest_reading <= last_decision) {
+        return Ok(None);
+    }
+    // automated dosing is refused while CGM data is stale; the signal monitor raises the alert
+    if is_glucose_data_stale(conn, patient_id, settings.stale_after_minutes)? {
+        return Ok(None);
+    }
+
+    let decision = if is_basal_suspended(conn, patient_id)? {
+        ControllerDecision {
+            units: 0.0,
+            reason: "basal suspended by predictive low-glucose suspend: no insulin delivered".to_string(),
+        }
+    } else {
+        // a running temp basal replaces the scheduled rate as the controller's baseline
+        let baseline_rate = get_current_basal_rate(conn, &patient)?;
+        match decide_basal(&patient, baseline_rate, &readings, settings) {
+            Some(d) => d,
+            None => return Ok(None),
+        }
+    };
+
+    insert_insulin_log(conn, patient_id, CONTROLLER_BASAL_ACTION, decision.units, CONTROLLER_REQUESTER, Some(&decision.reason))?;
+    Ok(Some(decision))
+}
+
+// runs the low-glucose suspend check and, when enabled, a controller cycle for every patient
+pub fn run_controller_for_all_patients(conn: &Connection, settings: &ControllerSettings) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT patient_id FROM patients")?;
+    let patient_ids = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?;
+
+    for patient_id in patient_ids {
+        // the low-glucose suspend check runs even when automated dosing is switched off
+        if let Err(e) = evaluate_low_glucose_suspend(conn, &patient_id) {
+            eprintln!("Low-glucose suspend check failed for patient {}: {}", patient_id, e);
+        }
+        // warn before a low happens, whether or not the controller is dosing
+        if let Err(e) = evaluate_forecast_low(conn, &patient_id) {
+            eprintln!("Forecast low check failed for patient {}: {}", patient_id, e);
+        }
+        if !settings.enabled {
+            continue;
+        }
+        if let Err(e) = run_controller_cycle(conn, &patient_id, settings) {
+            eprintln!("Controller cycle failed for patient {}: {}", patient_id, e);
+        }
+    }
+    Ok(())
+}
+
+// Run the controller in a background thread every settings.interval_secs
+pub fn run_controller(db_path: &str, settings: ControllerSettings) {
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || loop {
+        match Connection::open(&db_path) {
+            Ok(conn) => {
+                // the menus share the database, so wait for their writes instead of failing
+                let _ = conn.busy_timeout(Duration::from_secs(5));
+                if let Err(e) = run_controller_for_all_patients(&conn, &settings) {
+                    eprintln!("Controller cycle failed: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to open DB connection for controller: {:?}", e),
+        }
+        std::thread::sleep(Duration::from_secs(settings.interval_secs));
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    // prescribed basal 1.0 U/h, max 10 units per hour, target 100 mg/dL
+    fn patient() -> PatientSafety {
+        PatientSafety {
+            patient_id: "patient".to_string(),
+            first_name: "Test".to_string(),
+            last_name: "Patient".to_string(),
+            max_dosage: 10.0,
+            low_glucose_threshold: 70.0,
+            high_glucose_threshold: 180.0,
+            basal_rate: 1.0,
+            bolus_rate: 1.0,
+            iob_model: "exponential".to_string(),
+            insulin_action_minutes: 240.0,
+            carb_ratio: Some(10.0),
+            correction_factor: Some(50.0),
+            target_glucose: Some(100.0),
+        }
+    }
+
+    fn settings() -> ControllerSettings {
+        ControllerSettings {
+            enabled: true,
+            interval_secs: 300,
+            kp: 0.01,
+            ki: 0.005,
+            kd: 0.2,
+            stale_after_minutes: 15,
+            glucose_source: GlucoseSource::Smoothed,
+        }
+    }
+
+    // readings five minutes apart, oldest first
+    fn readings(values: &[f32]) -> Vec<(NaiveDateTime, f32)> {
+        let start = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap().and_hms_opt(8, 0, 0).unwrap();
+        values.iter().enumerate().map(|(i, g)| (start + Duration::minutes(5 * i as i64), *g)).collect()
+    }
+
+    #[test]
+    fn no_readings_means_no_decision() {
+        assert!(decide_basal(&patient(), 1.0, &[], &settings()).is_none());
+    }
+
+    #[test]
+    fn basal_stops_at_the_low_threshold() {
+        let decision = decide_basal(&patient(), 1.0, &readings(&[80.0, 75.0, 70.0]), &settings()).unwrap();
+        assert_eq!(decision.units, 0.0);
+    }
+
+    #[test]
+    fn high_glucose_never_exceeds_the_scheduled_rate() {
+        // far above target: still 0.8 U/h for a five minute cycle
+        let decision = decide_basal(&patient(), 0.8, &readings(&[300.0, 300.0, 300.0]), &settings()).unwrap();
+        assert!((decision.units - 0.8 / 12.0).abs() < 1e-6, "{}", decision.units);
+    }
+
+    #[test]
+    fn scheduled_rate_above_the_prescription_is_capped() {
+        let decision = decide_basal(&patient(), 1.5, &readings(&[300.0, 300.0, 300.0]), &settings()).unwrap();
+        assert!((decision.units - 1.0 / 12.0).abs() < 1e-6, "{}", decision.units);
+    }
+
+    #[test]
+    fn below_target_reduces_basal() {
+        let decision = decide_basal(&patient(), 1.0, &readings(&[90.0, 85.0, 80.0]), &settings()).unwrap();
+        assert!(decision.units < 1.0 / 12.0, "{}", decision.units);
+    }
+}
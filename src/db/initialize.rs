@@ -120,6 +120,286 @@ fn create_activation_codes_table(conn:&rusqlite::Connection)->rusqlite::Result<(
     Ok(())
 }
 
+fn create_basal_schedules_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // one row per time-of-day segment, grouped by schedule_id
+    let sql = "
+        CREATE TABLE IF NOT EXISTS basal_schedules (
+            segment_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            schedule_id TEXT NOT NULL,
+            patient_id TEXT NOT NULL,
+            start_time TEXT NOT NULL,
+            rate REAL NOT NULL,
+            submitted_by TEXT NOT NULL,
+            submitted_at TEXT NOT NULL,
+            effective_at TEXT NOT NULL
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+
+fn create_temp_basals_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // mode is 'percent' or 'absolute'; status is 'active', 'cancelled' or 'expired'
+    let sql = "
+        CREATE TABLE IF NOT EXISTS temp_basals (
+            temp_basal_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            patient_id TEXT NOT NULL,
+            mode TEXT NOT NULL,
+            value REAL NOT NULL,
+            rate REAL NOT NULL,
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL,
+            status TEXT NOT NULL,
+            requested_by TEXT NOT NULL
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+
+fn create_extended_boluses_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // bolus_type is 'extended' or 'dual_wave'; status is 'in_progress', 'completed' or 'cancelled'
+    let sql = "
+        CREATE TABLE IF NOT EXISTS extended_boluses (
+            extended_bolus_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            patient_id TEXT NOT NULL,
+            bolus_type TEXT NOT NULL,
+            total_units REAL NOT NULL,
+            immediate_units REAL NOT NULL,
+            duration_minutes INTEGER NOT NULL,
+            start_time TEXT NOT NULL,
+            status TEXT NOT NULL,
+            extended_delivered REAL,
+            requested_by TEXT NOT NULL
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+
+fn create_calibrations_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // cgm_glucose is the CGM reading closest to the fingerstick (NULL when none was close);
+    // correction_factor is NULL when the fingerstick was recorded without calibrating the sensor
+    let sql = "
+        CREATE TABLE IF NOT EXISTS calibrations (
+            calibration_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            patient_id TEXT NOT NULL,
+            fingerstick_glucose REAL NOT NULL,
+            cgm_glucose REAL,
+            correction_factor REAL,
+            calibration_time TEXT NOT NULL,
+            entered_by TEXT NOT NULL
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+
+fn create_cgm_devices_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // devices allowed to stream readings to the ingest service; only a hash of the key is kept
+    let sql = "
+        CREATE TABLE IF NOT EXISTS cgm_devices (
+            device_id TEXT PRIMARY KEY,
+            patient_id TEXT NOT NULL,
+            key_hash TEXT NOT NULL,
+            registered_at TEXT NOT NULL,
+            last_seen TEXT
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+
+fn create_sensor_sessions_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // one row per CGM sensor worn; ended_at is set when the sensor is replaced
+    let sql = "
+        CREATE TABLE IF NOT EXISTS sensor_sessions (
+            sensor_session_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            patient_id TEXT NOT NULL,
+            serial_number TEXT NOT NULL,
+            inserted_at TEXT NOT NULL,
+            warmup_minutes INTEGER NOT NULL,
+            expires_at TEXT NOT NULL,
+            ended_at TEXT,
+            started_by TEXT NOT NULL
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+
+fn create_escalation_policies_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // per-patient alert escalation timers; patients without a row use the defaults
+    let sql = "
+        CREATE TABLE IF NOT EXISTS escalation_policies (
+            patient_id TEXT PRIMARY KEY,
+            enabled BOOLEAN NOT NULL,
+            caretaker_minutes INTEGER NOT NULL,
+            clinician_minutes INTEGER NOT NULL,
+            updated_by TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+fn create_alert_escalations_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // one row per escalation step of an alert
+    let sql = "
+        CREATE TABLE IF NOT EXISTS alert_escalations (
+            escalation_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            alert_id INTEGER NOT NULL,
+            escalation_level TEXT NOT NULL,
+            escalated_to TEXT NOT NULL,
+            escalated_at TEXT NOT NULL
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+fn create_alert_snoozes_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // alert types silenced for a patient until snoozed_until
+    let sql = "
+        CREATE TABLE IF NOT EXISTS alert_snoozes (
+            patient_id TEXT NOT NULL,
+            alert_type TEXT NOT NULL,
+            snoozed_until TEXT NOT NULL,
+            snoozed_by TEXT NOT NULL,
+            snoozed_at TEXT NOT NULL,
+            PRIMARY KEY (patient_id, alert_type)
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+fn create_notification_preferences_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // channels a user receives alerts on; address is the webhook URL or email address
+    let sql = "
+        CREATE TABLE IF NOT EXISTS notification_preferences (
+            user_id TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            enabled BOOLEAN NOT NULL,
+            address TEXT,
+            updated_at TEXT NOT NULL,
+            enabled_at TEXT,
+            PRIMARY KEY (user_id, channel)
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+fn create_alert_deliveries_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // one row per attempt to deliver an alert to a user on a channel
+    let sql = "
+        CREATE TABLE IF NOT EXISTS alert_deliveries (
+            delivery_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            alert_id INTEGER NOT NULL,
+            user_id TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            status TEXT NOT NULL,
+            error TEXT,
+            attempted_at TEXT NOT NULL
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+fn create_missed_boluses_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // meals followed by a sharp rise with no bolus; meal_id is NULL when the meal was inferred
+    let sql = "
+        CREATE TABLE IF NOT EXISTS missed_boluses (
+            missed_bolus_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            patient_id TEXT NOT NULL,
+            meal_id INTEGER,
+            meal_time TEXT NOT NULL,
+            inferred BOOLEAN NOT NULL,
+            baseline_glucose REAL NOT NULL,
+            peak_glucose REAL NOT NULL,
+            detected_at TEXT NOT NULL,
+            alert_id INTEGER
+        )";
+    conn.execute(sql, [])?;
+    Ok(())
+}
+
+//-----------------------Database migrations-----------------------//
+
+// adds a column to an existing table when it is missing
+// (CREATE TABLE IF NOT EXISTS leaves databases created by older versions untouched)
+fn add_column_if_missing(conn:&rusqlite::Connection, table:&str, column:&str, definition:&str)->rusqlite::Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info('{}')", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    if !columns.iter().any(|name| name == column) {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+fn migrate_users_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // glucose display unit: 'mg/dL' or 'mmol/L' (values are always stored in mg/dL)
+    add_column_if_missing(conn, "users", "glucose_unit", "TEXT NOT NULL DEFAULT 'mg/dL'")?;
+    // local HH:MM window in which only urgent low alerts are notified (NULL when unset)
+    add_column_if_missing(conn, "users", "quiet_hours_start", "TEXT")?;
+    add_column_if_missing(conn, "users", "quiet_hours_end", "TEXT")?;
+    Ok(())
+}
+
+fn migrate_patients_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // insulin action curve used for insulin-on-board: 'exponential' or 'bilinear'
+    add_column_if_missing(conn, "patients", "iob_model", "TEXT NOT NULL DEFAULT 'exponential'")?;
+    // duration of insulin action in minutes
+    add_column_if_missing(conn, "patients", "insulin_action_minutes", "REAL NOT NULL DEFAULT 360")?;
+    // bolus wizard prescription, left NULL until the clinician sets it
+    add_column_if_missing(conn, "patients", "carb_ratio", "REAL")?;
+    add_column_if_missing(conn, "patients", "correction_factor", "REAL")?;
+    add_column_if_missing(conn, "patients", "target_glucose", "REAL")?;
+    Ok(())
+}
+
+fn migrate_insulin_logs_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // explanation recorded with automated decisions (e.g. the closed-loop controller)
+    add_column_if_missing(conn, "insulin_logs", "reason", "TEXT")?;
+    Ok(())
+}
+
+fn migrate_glucose_readings_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // uncalibrated sensor value and the calibration applied to it
+    add_column_if_missing(conn, "glucose_readings", "raw_glucose_level", "REAL")?;
+    add_column_if_missing(conn, "glucose_readings", "calibration_id", "INTEGER")?;
+    // filtered value and outlier flag (see filter.rs)
+    add_column_if_missing(conn, "glucose_readings", "smoothed_glucose_level", "REAL")?;
+    add_column_if_missing(conn, "glucose_readings", "reading_quality", "TEXT")?;
+    // sensor that produced the reading, and why it must not be used (NULL when usable)
+    add_column_if_missing(conn, "glucose_readings", "sensor_session_id", "INTEGER")?;
+    add_column_if_missing(conn, "glucose_readings", "unusable_reason", "TEXT")?;
+    Ok(())
+}
+
+fn migrate_calibrations_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // calibrations only apply to readings from the sensor they were made on
+    add_column_if_missing(conn, "calibrations", "sensor_session_id", "INTEGER")?;
+    Ok(())
+}
+
+fn migrate_notification_preferences_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // when the channel was last turned on; alerts raised before then are not sent on it
+    add_column_if_missing(conn, "notification_preferences", "enabled_at", "TEXT")?;
+    conn.execute(
+        "UPDATE notification_preferences SET enabled_at = updated_at WHERE enabled = 1 AND enabled_at IS NULL",
+        [],
+    )?;
+    Ok(())
+}
+
+fn migrate_alerts_table(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    // when an alert was acknowledged and the note left by resolved_by
+    add_column_if_missing(conn, "alerts", "resolved_at", "TEXT")?;
+    add_column_if_missing(conn, "alerts", "resolution_note", "TEXT")?;
+    // raised while the patient's alerts of that type were snoozed: kept, but not notified
+    add_column_if_missing(conn, "alerts", "snoozed", "INTEGER NOT NULL DEFAULT 0")?;
+    Ok(())
+}
+
+// glucose alerts are raised by alerts.rs, which knows each patient's thresholds; older databases
+// carry fixed-threshold triggers that would raise duplicates
+fn drop_legacy_alert_triggers(conn:&rusqlite::Connection)->rusqlite::Result<()> {
+    conn.execute("DROP TRIGGER IF EXISTS trg_glucose_low_alert", [])?;
+    conn.execute("DROP TRIGGER IF EXISTS trg_glucose_high_alert", [])?;
+    Ok(())
+}
+
 // generating all tables for the database
 pub fn initialize_database(conn:&rusqlite::Connection)->rusqlite::Result<()> {
     create_users_table(conn)?;
@@ -131,6 +411,26 @@ pub fn initialize_database(conn:&rusqlite::Connection)->rusqlite::Result<()> {
     create_meal_logs_table(conn)?;
     create_session_table(conn)?;
     create_activation_codes_table(conn)?;
+    create_basal_schedules_table(conn)?;
+    create_temp_basals_table(conn)?;
+    create_extended_boluses_table(conn)?;
+    create_calibrations_table(conn)?;
+    create_cgm_devices_table(conn)?;
+    create_sensor_sessions_table(conn)?;
+    create_escalation_policies_table(conn)?;
+    create_alert_escalations_table(conn)?;
+    create_alert_snoozes_table(conn)?;
+    create_notification_preferences_table(conn)?;
+    create_alert_deliveries_table(conn)?;
+    create_missed_boluses_table(conn)?;
+    migrate_users_table(conn)?;
+    migrate_patients_table(conn)?;
+    migrate_insulin_logs_table(conn)?;
+    migrate_glucose_readings_table(conn)?;
+    migrate_calibrations_table(conn)?;
+    migrate_alerts_table(conn)?;
+    migrate_notification_preferences_table(conn)?;
+    drop_legacy_alert_triggers(conn)?;
     println!("Successfully connected to database...");
     Ok(())
 }
@@ -140,7 +440,7 @@ pub fn initialize_database(conn:&rusqlite::Connection)->rusqlite::Result<()> {
 
 pub fn establish_connection() -> rusqlite::Result<rusqlite::Connection>{
      // Open the database connection
-    let connection = rusqlite::Connection::open("./data/database.db")?;
+    let connection = rusqlite::Connection::open(crate::config::DB_PATH)?;
     
   // Initialize database tables if they don't exist
     initialize_database(&connection)?;
@@ -20,6 +20,9 @@ pub struct Patient{
     pub max_dosage: f32,
     pub low_glucose_threshold: f32,
     pub high_glucose_threshold: f32,
+    pub carb_ratio: Option<f32>,
+    pub correction_factor: Option<f32>,
+    pub target_glucose: Option<f32>,
     pub clinician_id: String,
     pub caretaker_id: String
 }
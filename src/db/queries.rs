@@ -125,6 +125,13 @@ pub fn get_all_clinicians(conn: &rusqlite::Connection) -> Result<Vec<String>> {
     Ok(usernames)
 }
 
+/// Fetches the ids of all patients
+pub fn get_all_patient_ids(conn: &rusqlite::Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT patient_id FROM patients")?;
+    let id_iter = stmt.query_map([], |row| row.get(0))?;
+    id_iter.collect()
+}
+
 // create patient account from patient object
 pub fn insert_patient_account_details_in_db(
     conn: &rusqlite::Connection,
@@ -168,8 +175,11 @@ pub fn insert_patient_account_details_in_db(
             low_glucose_threshold,
             high_glucose_threshold,
             clinician_id,
-            caretaker_id
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            caretaker_id,
+            carb_ratio,
+            correction_factor,
+            target_glucose
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
     ";
 
     conn.execute(
@@ -185,7 +195,10 @@ pub fn insert_patient_account_details_in_db(
             patient.low_glucose_threshold,
             patient.high_glucose_threshold,
             patient.clinician_id,
-            patient.caretaker_id
+            patient.caretaker_id,
+            patient.carb_ratio,
+            patient.correction_factor,
+            patient.target_glucose
         ],
     )?;
 
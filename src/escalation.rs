@@ -0,0 +1,251 @@
+// Escalation of unacknowledged severe low alerts from the patient to their caretaker and clinician
+use std::time::Duration as StdDuration;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use crate::alerts::URGENT_LOW_ALERT;
+use crate::config::env_or;
+use crate::utils::{format_local_time, get_current_db_time_string, DB_TIME_FORMAT};
+
+// seconds between escalation checks
+const ESCALATION_CHECK_INTERVAL_SECS: u64 = 60;
+
+// who an alert has been escalated to, in the order of the chain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EscalationLevel {
+    Caretaker,
+    Clinician,
+}
+
+impl EscalationLevel {
+    pub fn as_db_value(&self) -> &'static str {
+        match self {
+            EscalationLevel::Caretaker => "caretaker",
+            EscalationLevel::Clinician => "clinician",
+        }
+    }
+}
+
+// policy for patients who have none of their own
+pub struct EscalationSettings {
+    pub caretaker_minutes: i64,
+    pub clinician_minutes: i64,
+}
+
+impl EscalationSettings {
+    pub fn from_env() -> Self {
+        Self {
+            caretaker_minutes: env_or("GLUCOGUARD_ESCALATION_CARETAKER_MINUTES", 15),
+            clinician_minutes: env_or("GLUCOGUARD_ESCALATION_CLINICIAN_MINUTES", 30),
+        }
+    }
+
+    pub fn default_policy(&self) -> EscalationPolicy {
+        EscalationPolicy {
+            enabled: true,
+            caretaker_minutes: self.caretaker_minutes,
+            clinician_minutes: self.clinician_minutes,
+        }
+    }
+}
+
+/// Minutes after an unacknowledged severe low alert was raised that it is escalated to the
+/// patient's caretaker and then to their clinician.
+pub struct EscalationPolicy {
+    pub enabled: bool,
+    pub caretaker_minutes: i64,
+    pub clinician_minutes: i64,
+}
+
+// the patient's own escalation policy, or the default one
+pub fn get_escalation_policy(conn: &Connection, patient_id: &str, settings: &EscalationSettings) -> Result<EscalationPolicy> {
+    let policy = conn
+        .query_row(
+            "SELECT enabled, caretaker_minutes, clinician_minutes FROM escalation_policies WHERE patient_id = ?1",
+            params![patient_id],
+            |row| {
+                Ok(EscalationPolicy {
+                    enabled: row.get(0)?,
+                    caretaker_minutes: row.get(1)?,
+                    clinician_minutes: row.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(policy.unwrap_or_else(|| settings.default_policy()))
+}
+
+pub fn set_escalation_policy(conn: &Connection, patient_id: &str, policy: &EscalationPolicy, updated_by: &str) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO escalation_policies (patient_id, enabled, caretaker_minutes, clinician_minutes, updated_by, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(patient_id) DO UPDATE SET
+             enabled = excluded.enabled,
+             caretaker_minutes = excluded.caretaker_minutes,
+             clinician_minutes = excluded.clinician_minutes,
+             updated_by = excluded.updated_by,
+             updated_at = excluded.updated_at",
+        params![
+            patient_id,
+            policy.enabled,
+            policy.caretaker_minutes,
+            policy.clinician_minutes,
+            updated_by,
+            get_current_db_time_string()
+        ],
+    )
+}
+
+// one recorded step of an alert's escalation
+pub struct AlertEscalation {
+    pub level: String,
+    pub escalated_to: String,
+    pub escalated_at: NaiveDateTime,
+}
+
+// escalation steps taken for an alert, in order
+pub fn get_alert_escalations(conn: &Connection, alert_id: i64) -> Result<Vec<AlertEscalation>> {
+    let mut stmt = conn.prepare(
+        "SELECT e.escalation_level, COALESCE(u.user_name, e.escalated_to), e.escalated_at
+         FROM alert_escalations e
+         LEFT JOIN users u ON u.id = e.escalated_to
+         WHERE e.alert_id = ?1
+         ORDER BY datetime(e.escalated_at) ASC, e.escalation_id ASC",
+    )?;
+    let rows = stmt.query_map(params![alert_id], |row| {
+        Ok(AlertEscalation {
+            level: row.get(0)?,
+            escalated_to: row.get(1)?,
+            escalated_at: NaiveDateTime::parse_from_str(&row.get::<_, String>(2)?, DB_TIME_FORMAT).unwrap_or_default(),
+        })
+    })?;
+    rows.collect()
+}
+
+fn record_escalation(conn: &Connection, alert_id: i64, level: EscalationLevel, escalated_to: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO alert_escalations (alert_id, escalation_level, escalated_to, escalated_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![alert_id, level.as_db_value(), escalated_to, get_current_db_time_string()],
+    )?;
+    Ok(())
+}
+
+fn is_escalated(conn: &Connection, alert_id: i64, level: EscalationLevel) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM alert_escalations WHERE alert_id = ?1 AND escalation_level = ?2",
+        params![alert_id, level.as_db_value()],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Escalates every open severe low alert that has gone unacknowledged past its patient's policy:
+/// first to the patient's caretaker, then to their clinician. A patient without a caretaker goes
+/// straight to the clinician step. Returns the number of steps recorded.
+pub fn evaluate_escalations(conn: &Connection, settings: &EscalationSettings) -> Result<usize> {
+    let mut stmt = conn.prepare(
+        "SELECT a.alert_id, a.patient_id, a.alert_time, p.caretaker_id, p.clinician_id
+         FROM alerts a
+         JOIN patients p ON p.patient_id = a.patient_id
+         WHERE a.alert_type = ?1 AND a.is_resolved = 0",
+    )?;
+    let open_alerts = stmt
+        .query_map(params![URGENT_LOW_ALERT], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    let now = Utc::now().naive_utc();
+    let mut escalated = 0;
+    for (alert_id, patient_id, alert_time, caretaker_id, clinician_id) in open_alerts {
+        let policy = get_escalation_policy(conn, &patient_id, settings)?;
+        let raised_at = match NaiveDateTime::parse_from_str(&alert_time, DB_TIME_FORMAT) {
+            Ok(time) => time,
+            Err(_) => continue,
+        };
+        if !policy.enabled {
+            continue;
+        }
+
+        let steps = [
+            (EscalationLevel::Caretaker, policy.caretaker_minutes, caretaker_id),
+            (EscalationLevel::Clinician, policy.clinician_minutes, clinician_id),
+        ];
+        for (level, minutes, recipient) in steps {
+            let recipient = match recipient.filter(|id| !id.trim().is_empty()) {
+                Some(id) => id,
+                None => continue,
+            };
+            if now - raised_at >= Duration::minutes(minutes) && !is_escalated(conn, alert_id, level)? {
+                record_escalation(conn, alert_id, level, &recipient)?;
+                escalated += 1;
+            }
+        }
+    }
+    Ok(escalated)
+}
+
+// Run the escalation timers in a background thread
+pub fn run_escalation_scheduler(db_path: &str, settings: EscalationSettings) {
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || loop {
+        match Connection::open(&db_path) {
+            Ok(conn) => {
+                let _ = conn.busy_timeout(StdDuration::from_secs(5));
+                if let Err(e) = evaluate_escalations(&conn, &settings) {
+                    eprintln!("Alert escalation check failed: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to open DB connection for alert escalation: {:?}", e),
+        }
+        std::thread::sleep(StdDuration::from_secs(ESCALATION_CHECK_INTERVAL_SECS));
+    });
+}
+
+// number of open alerts that have been escalated to the user
+pub fn count_open_escalations_for_user(conn: &Connection, user_id: &str) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(DISTINCT e.alert_id)
+         FROM alert_escalations e
+         JOIN alerts a ON a.alert_id = e.alert_id
+         WHERE e.escalated_to = ?1 AND a.is_resolved = 0",
+        params![user_id],
+        |row| row.get(0),
+    )
+}
+
+// warns a caretaker or clinician at the top of their menu about open alerts escalated to them
+pub fn display_escalation_notice(conn: &Connection, user_id: &str) {
+    match count_open_escalations_for_user(conn, user_id) {
+        Ok(0) => {}
+        Ok(count) => println!(
+            "\n!!! {} severe low alert(s) escalated to you are unacknowledged. Open the alert inbox. !!!\n",
+            count
+        ),
+        Err(e) => eprintln!("Error checking escalated alerts: {}", e),
+    }
+}
+
+// prints the patient's escalation policy
+pub fn display_escalation_policy(conn: &Connection, patient_id: &str, settings: &EscalationSettings) {
+    println!("\n--- Alert Escalation Policy ---");
+    match get_escalation_policy(conn, patient_id, settings) {
+        Ok(policy) if policy.enabled => println!(
+            "Unacknowledged urgent low alerts go to the caretaker after {} minutes and to the clinician after {} minutes.",
+            policy.caretaker_minutes, policy.clinician_minutes
+        ),
+        Ok(_) => println!("Escalation is turned off for this patient."),
+        Err(e) => eprintln!("Error retrieving escalation policy: {}", e),
+    }
+}
+
+// formats a recorded step for the alert inbox, e.g. "caretaker (jane) at Oct 18, 2026 11:30 AM"
+pub fn describe_escalation(escalation: &AlertEscalation) -> String {
+    format!("{} ({}) at {}", escalation.level, escalation.escalated_to, format_local_time(&escalation.escalated_at))
+}
@@ -0,0 +1,230 @@
+// CGM noise filtering and outlier rejection (spikes and compression lows)
+use std::str::FromStr;
+use chrono::{Duration, NaiveDateTime};
+use rusqlite::{Connection, Result, params};
+use crate::config::env_or;
+use crate::utils::DB_TIME_FORMAT;
+
+// accepted readings this far back from a new reading are used to filter it
+const FILTER_WINDOW_MINUTES: i64 = 30;
+// the smoothed value is the median of the new reading and up to this many accepted readings before it
+const MEDIAN_NEIGHBOURS: usize = 2;
+// glucose cannot physiologically change faster than this (mg/dL per minute)
+const MAX_PHYSIOLOGICAL_RATE: f32 = 5.0;
+// a reading this far from the recent median, reached faster than the rate above, is an outlier (mg/dL)
+const OUTLIER_DEVIATION_MG_DL: f32 = 30.0;
+
+// which stored value a reader of glucose_readings uses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GlucoseSource {
+    // the (calibrated) sensor value as received
+    Raw,
+    // the median-filtered value, which ignores outliers
+    Smoothed,
+}
+
+impl GlucoseSource {
+    // SQL expression selecting this value; readings stored before filtering fall back to the raw value
+    pub fn column(&self) -> &'static str {
+        match self {
+            GlucoseSource::Raw => "glucose_level",
+            GlucoseSource::Smoothed => "COALESCE(smoothed_glucose_level, glucose_level)",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GlucoseSource::Raw => "raw",
+            GlucoseSource::Smoothed => "smoothed",
+        }
+    }
+}
+
+impl FromStr for GlucoseSource {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "raw" => Ok(GlucoseSource::Raw),
+            "smoothed" => Ok(GlucoseSource::Smoothed),
+            _ => Err(()),
+        }
+    }
+}
+
+// which value is shown to users, which one dosing decisions are made on and which one raises alerts
+pub struct GlucoseSourceSettings {
+    pub display: GlucoseSource,
+    pub dosing: GlucoseSource,
+    pub alerts: GlucoseSource,
+}
+
+impl GlucoseSourceSettings {
+    pub fn from_env() -> Self {
+        Self {
+            display: env_or("GLUCOGUARD_DISPLAY_GLUCOSE_SOURCE", GlucoseSource::Raw),
+            dosing: env_or("GLUCOGUARD_DOSING_GLUCOSE_SOURCE", GlucoseSource::Smoothed),
+            alerts: env_or("GLUCOGUARD_ALERT_GLUCOSE_SOURCE", GlucoseSource::Smoothed),
+        }
+    }
+}
+
+// quality flag stored with every reading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadingQuality {
+    Valid,
+    // implausibly fast rise away from the recent readings
+    Spike,
+    // implausibly fast drop, typically from pressure on the sensor while lying on it
+    CompressionLow,
+}
+
+impl ReadingQuality {
+    pub fn as_db_value(&self) -> &'static str {
+        match self {
+            ReadingQuality::Valid => "valid",
+            ReadingQuality::Spike => "spike",
+            ReadingQuality::CompressionLow => "compression_low",
+        }
+    }
+
+    // readings stored before filtering have no flag and count as valid
+    pub fn from_db_value(value: Option<&str>) -> Self {
+        match value {
+            Some("spike") => ReadingQuality::Spike,
+            Some("compression_low") => ReadingQuality::CompressionLow,
+            _ => ReadingQuality::Valid,
+        }
+    }
+
+    pub fn is_outlier(&self) -> bool {
+        *self != ReadingQuality::Valid
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ReadingQuality::Valid => "valid",
+            ReadingQuality::Spike => "spike",
+            ReadingQuality::CompressionLow => "compression low",
+        }
+    }
+}
+
+pub struct FilteredReading {
+    pub smoothed: f32,
+    pub quality: ReadingQuality,
+}
+
+impl FilteredReading {
+    // a reading passed through as valid, with nothing to filter it against
+    pub fn unfiltered(value: f32) -> Self {
+        FilteredReading { smoothed: value, quality: ReadingQuality::Valid }
+    }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Filters a new reading against the accepted (non-outlier) readings before it, oldest first.
+/// A reading is an outlier when it moved away from the last accepted reading faster than
+/// `MAX_PHYSIOLOGICAL_RATE` and lies more than `OUTLIER_DEVIATION_MG_DL` from their median:
+/// a drop is flagged as a compression low, a rise as a spike. An outlier is smoothed to the
+/// median of the accepted readings; any other reading to the median of itself and the last
+/// `MEDIAN_NEIGHBOURS` accepted readings. A sustained change is eventually accepted because the
+/// rate is measured against an ever older accepted reading.
+pub fn filter_reading(accepted: &[(NaiveDateTime, f32)], reading_time: NaiveDateTime, value: f32) -> FilteredReading {
+    let (last_time, last_value) = match accepted.last() {
+        Some(last) => *last,
+        None => return FilteredReading::unfiltered(value),
+    };
+    let minutes = ((reading_time - last_time).num_seconds() as f32 / 60.0).max(1.0);
+    let rate = (value - last_value) / minutes;
+
+    let mut recent: Vec<f32> = accepted.iter().map(|(_, glucose)| *glucose).collect();
+    let reference = median(&mut recent.clone());
+
+    if rate.abs() > MAX_PHYSIOLOGICAL_RATE && (value - reference).abs() > OUTLIER_DEVIATION_MG_DL {
+        let quality = if value < reference { ReadingQuality::CompressionLow } else { ReadingQuality::Spike };
+        return FilteredReading { smoothed: reference, quality };
+    }
+
+    let mut window = recent.split_off(recent.len().saturating_sub(MEDIAN_NEIGHBOURS));
+    window.push(value);
+    FilteredReading { smoothed: median(&mut window), quality: ReadingQuality::Valid }
+}
+
+// accepted (usable, non-outlier) readings within FILTER_WINDOW_MINUTES before `reading_time`, oldest first
+fn get_accepted_readings_before(conn: &Connection, patient_id: &str, reading_time: &NaiveDateTime) -> Result<Vec<(NaiveDateTime, f32)>> {
+    let mut stmt = conn.prepare(
+        "SELECT reading_time, glucose_level FROM glucose_readings
+         WHERE patient_id = ?1
+           AND datetime(reading_time) >= datetime(?2)
+           AND datetime(reading_time) < datetime(?3)
+           AND COALESCE(reading_quality, 'valid') = 'valid'
+           AND unusable_reason IS NULL
+         ORDER BY datetime(reading_time) ASC",
+    )?;
+    let since = *reading_time - Duration::minutes(FILTER_WINDOW_MINUTES);
+    let rows = stmt.query_map(
+        params![patient_id, since.format(DB_TIME_FORMAT).to_string(), reading_time.format(DB_TIME_FORMAT).to_string()],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, f32>(1)?)),
+    )?;
+
+    let mut readings = Vec::new();
+    for row in rows {
+        let (time_str, glucose) = row?;
+        if let Ok(time) = NaiveDateTime::parse_from_str(&time_str, DB_TIME_FORMAT) {
+            readings.push((time, glucose));
+        }
+    }
+    Ok(readings)
+}
+
+/// Filters a reading about to be stored for the patient against the readings already stored.
+/// A reading with an unparseable time is passed through unfiltered.
+pub fn filter_new_reading(conn: &Connection, patient_id: &str, reading_time: &str, value: f32) -> Result<FilteredReading> {
+    let time = match NaiveDateTime::parse_from_str(reading_time, DB_TIME_FORMAT) {
+        Ok(time) => time,
+        Err(_) => return Ok(FilteredReading::unfiltered(value)),
+    };
+    let accepted = get_accepted_readings_before(conn, patient_id, &time)?;
+    Ok(filter_reading(&accepted, time, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(minutes: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 1).unwrap().and_hms_opt(8, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn first_reading_is_passed_through() {
+        let filtered = filter_reading(&[], at(0), 120.0);
+        assert_eq!((filtered.smoothed, filtered.quality), (120.0, ReadingQuality::Valid));
+    }
+
+    #[test]
+    fn plausible_reading_is_smoothed_with_its_neighbours() {
+        let filtered = filter_reading(&[(at(0), 100.0), (at(5), 110.0)], at(10), 104.0);
+        assert_eq!((filtered.smoothed, filtered.quality), (104.0, ReadingQuality::Valid));
+    }
+
+    #[test]
+    fn sudden_jumps_are_outliers() {
+        let accepted = [(at(0), 100.0), (at(5), 100.0)];
+        let spike = filter_reading(&accepted, at(10), 200.0);
+        assert_eq!((spike.smoothed, spike.quality), (100.0, ReadingQuality::Spike));
+        let compression = filter_reading(&accepted, at(10), 40.0);
+        assert_eq!((compression.smoothed, compression.quality), (100.0, ReadingQuality::CompressionLow));
+    }
+}
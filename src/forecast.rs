@@ -0,0 +1,237 @@
+// Short-term glucose forecasting from CGM trend, insulin on board and carbs on board
+use chrono::{Duration, NaiveDateTime, Utc};
+use rusqlite::{Connection, Result, params};
+use crate::alerts::insert_alert;
+use crate::filter::GlucoseSourceSettings;
+use crate::cgm::{DEFAULT_CARB_RATIO, DEFAULT_CORRECTION_FACTOR, SENSOR_MAX_MG_DL, SENSOR_MIN_MG_DL};
+use crate::insulin::{calculate_insulin_on_board, get_active_insulin_doses, get_patient_data_from_patient_table,
+                    get_recent_glucose_readings, InsulinActionCurve};
+use crate::signal::{is_glucose_data_stale, SignalSettings};
+use crate::trend::{glucose_trend, TREND_WINDOW_MINUTES};
+use crate::units::GlucoseUnit;
+use crate::utils::DB_TIME_FORMAT;
+
+// minutes ahead a forecast is made for
+pub const FORECAST_HORIZONS: [i64; 3] = [15, 30, 60];
+// alert_type raised when glucose is forecast to go low
+pub const FORECAST_LOW_ALERT: &str = "Forecast Low";
+// the forecast used for alerting
+pub const FORECAST_ALERT_HORIZON: i64 = 30;
+// a new forecast low alert is not raised within this many minutes of the previous one
+const FORECAST_ALERT_REPEAT_MINUTES: i64 = 30;
+
+// carbohydrate is taken as absorbed evenly over this many minutes
+const CARB_ABSORPTION_MINUTES: f32 = 180.0;
+// the current rate of change fades out with this time constant (min)
+const MOMENTUM_DECAY_MINUTES: f32 = 15.0;
+// lower bound on the sensor noise used for the confidence interval (mg/dL)
+const MIN_NOISE_SD: f32 = 5.0;
+// forecast uncertainty added per minute of horizon (mg/dL per minute)
+const SD_GROWTH_PER_MINUTE: f32 = 0.6;
+// z-score of the reported confidence interval (95%)
+const CONFIDENCE_Z: f32 = 1.96;
+
+// everything the forecast needs, gathered from the database
+pub struct ForecastInputs {
+    pub latest_glucose: f32,
+    // mg/dL per minute at the latest reading
+    pub rate_per_minute: f32,
+    // spread of recent readings around their trend (mg/dL)
+    pub noise_sd: f32,
+    // insulin doses as (minutes ago, units)
+    pub doses: Vec<(f32, f32)>,
+    pub curve: InsulinActionCurve,
+    // meals as (minutes ago, grams of carbohydrate)
+    pub carbs: Vec<(f32, f32)>,
+    pub correction_factor: f32,
+    pub carb_ratio: f32,
+}
+
+// predicted glucose with a 95% confidence interval
+#[derive(Debug, Clone, Copy)]
+pub struct GlucoseForecast {
+    pub horizon_minutes: i64,
+    pub predicted: f32,
+    pub lower: f32,
+    pub upper: f32,
+}
+
+// fraction of a meal eaten `minutes_ago` that has been absorbed
+fn carbs_absorbed_fraction(minutes_ago: f32) -> f32 {
+    (minutes_ago / CARB_ABSORPTION_MINUTES).clamp(0.0, 1.0)
+}
+
+/// Predicts glucose `horizon_minutes` ahead as the sum of three effects:
+/// - momentum: the current rate of change, fading out over `MOMENTUM_DECAY_MINUTES`;
+/// - insulin: insulin on board that acts within the horizon × correction factor (lowers);
+/// - carbs: carbohydrate absorbed within the horizon × correction factor / carb ratio (raises).
+///
+/// The confidence interval widens with the horizon from the recent sensor noise.
+pub fn predict_glucose(inputs: &ForecastInputs, horizon_minutes: i64) -> GlucoseForecast {
+    let h = horizon_minutes as f32;
+
+    let momentum = inputs.rate_per_minute * MOMENTUM_DECAY_MINUTES * (1.0 - (-h / MOMENTUM_DECAY_MINUTES).exp());
+
+    let iob_now = calculate_insulin_on_board(&inputs.doses, &inputs.curve);
+    let future_doses: Vec<(f32, f32)> = inputs.doses.iter().map(|(ago, units)| (ago + h, *units)).collect();
+    let iob_later = calculate_insulin_on_board(&future_doses, &inputs.curve);
+    let insulin_effect = -(iob_now - iob_later).max(0.0) * inputs.correction_factor;
+
+    let carbs_absorbed: f32 = inputs
+        .carbs
+        .iter()
+        .map(|(ago, grams)| grams * (carbs_absorbed_fraction(ago + h) - carbs_absorbed_fraction(*ago)))
+        .sum();
+    let carb_effect = carbs_absorbed * inputs.correction_factor / inputs.carb_ratio;
+
+    let predicted = (inputs.latest_glucose + momentum + insulin_effect + carb_effect).clamp(SENSOR_MIN_MG_DL, SENSOR_MAX_MG_DL);
+    let sd = (inputs.noise_sd.max(MIN_NOISE_SD).powi(2) + (SD_GROWTH_PER_MINUTE * h).powi(2)).sqrt();
+
+    GlucoseForecast {
+        horizon_minutes,
+        predicted,
+        lower: (predicted - CONFIDENCE_Z * sd).max(SENSOR_MIN_MG_DL),
+        upper: (predicted + CONFIDENCE_Z * sd).min(SENSOR_MAX_MG_DL),
+    }
+}
+
+// standard deviation of reading-to-reading changes around their mean change, per reading
+fn reading_noise(readings: &[(NaiveDateTime, f32)]) -> f32 {
+    let diffs: Vec<f32> = readings.windows(2).map(|pair| pair[1].1 - pair[0].1).collect();
+    if diffs.len() < 2 {
+        return MIN_NOISE_SD;
+    }
+    let mean = diffs.iter().sum::<f32>() / diffs.len() as f32;
+    let variance = diffs.iter().map(|d| (d - mean).powi(2)).sum::<f32>() / (diffs.len() - 1) as f32;
+    // each difference carries the noise of two readings
+    (variance / 2.0).sqrt()
+}
+
+// meals that are still being absorbed, as (minutes ago, grams)
+fn get_carbs_on_board(conn: &Connection, patient_id: &str) -> Result<Vec<(f32, f32)>> {
+    let mut stmt = conn.prepare(
+        "SELECT meal_time, carbohydrate_amount FROM meal_logs
+         WHERE patient_id = ?1 AND datetime(meal_time) >= datetime('now', ?2)",
+    )?;
+    let window = format!("-{} minutes", CARB_ABSORPTION_MINUTES as i64);
+    let rows = stmt.query_map(params![patient_id, window], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f32>(1)?))
+    })?;
+
+    let now = Utc::now().naive_utc();
+    let mut carbs = Vec::new();
+    for row in rows {
+        let (time_str, grams) = row?;
+        if let Ok(meal_time) = NaiveDateTime::parse_from_str(&time_str, DB_TIME_FORMAT) {
+            carbs.push(((now - meal_time).num_seconds() as f32 / 60.0, grams));
+        }
+    }
+    Ok(carbs)
+}
+
+/// Forecasts the patient's glucose at each of `FORECAST_HORIZONS`.
+/// Returns `Ok(None)` when there is no recent CGM data to forecast from.
+pub fn forecast_glucose(conn: &Connection, patient_id: &str) -> Result<Option<Vec<GlucoseForecast>>> {
+    let signal = SignalSettings::from_env();
+    if is_glucose_data_stale(conn, patient_id, signal.stale_after_minutes())? {
+        return Ok(None);
+    }
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let readings = get_recent_glucose_readings(
+        conn,
+        patient_id,
+        TREND_WINDOW_MINUTES + signal.stale_after_minutes(),
+        GlucoseSourceSettings::from_env().dosing,
+    )?;
+    let latest_glucose = match readings.last() {
+        Some((_, glucose)) => *glucose,
+        None => return Ok(None),
+    };
+
+    let inputs = ForecastInputs {
+        latest_glucose,
+        rate_per_minute: glucose_trend(&readings).map_or(0.0, |trend| trend.rate_per_minute),
+        noise_sd: reading_noise(&readings),
+        doses: get_active_insulin_doses(conn, &patient)?,
+        curve: patient.action_curve(),
+        carbs: get_carbs_on_board(conn, patient_id)?,
+        correction_factor: patient.correction_factor.unwrap_or(DEFAULT_CORRECTION_FACTOR),
+        carb_ratio: patient.carb_ratio.unwrap_or(DEFAULT_CARB_RATIO),
+    };
+
+    Ok(Some(FORECAST_HORIZONS.iter().map(|h| predict_glucose(&inputs, *h)).collect()))
+}
+
+// prints the forecast for each horizon, flagging predictions below the patient's low threshold
+pub fn display_glucose_forecast(conn: &Connection, patient_id: &str, unit: GlucoseUnit) {
+    let low_threshold = match get_patient_data_from_patient_table(conn, patient_id) {
+        Ok(Some(patient)) => patient.low_glucose_threshold,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Error retrieving patient: {}", e);
+            return;
+        }
+    };
+
+    println!("\n--- Glucose Forecast ---");
+    match forecast_glucose(conn, patient_id) {
+        Ok(Some(forecasts)) => {
+            for forecast in forecasts {
+                let warning = if forecast.predicted < low_threshold { " | LOW predicted" } else { "" };
+                println!(
+                    "* In {} min: {} (95% range {}–{}){}",
+                    forecast.horizon_minutes,
+                    unit.format(forecast.predicted),
+                    unit.format_value(forecast.lower),
+                    unit.format_value(forecast.upper),
+                    warning
+                );
+            }
+        }
+        Ok(None) => println!("No recent CGM data to forecast from."),
+        Err(e) => eprintln!("Error forecasting glucose: {}", e),
+    }
+}
+
+/// Raises a "Forecast Low" alert when glucose is predicted to fall below the patient's low
+/// threshold within `FORECAST_ALERT_HORIZON` minutes. Returns true when an alert was raised.
+pub fn evaluate_forecast_low(conn: &Connection, patient_id: &str) -> Result<bool> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(false),
+    };
+    let forecast = match forecast_glucose(conn, patient_id)?
+        .and_then(|forecasts| forecasts.into_iter().find(|f| f.horizon_minutes == FORECAST_ALERT_HORIZON))
+    {
+        Some(f) => f,
+        None => return Ok(false),
+    };
+    if forecast.predicted >= patient.low_glucose_threshold {
+        return Ok(false);
+    }
+
+    let since = (Utc::now().naive_utc() - Duration::minutes(FORECAST_ALERT_REPEAT_MINUTES)).format(DB_TIME_FORMAT).to_string();
+    let recent_alerts: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM alerts
+         WHERE patient_id = ?1 AND alert_type = ?2 AND datetime(alert_time) >= datetime(?3)",
+        params![patient_id, FORECAST_LOW_ALERT, since],
+        |row| row.get(0),
+    )?;
+    if recent_alerts > 0 {
+        return Ok(false);
+    }
+
+    insert_alert(
+        conn,
+        patient_id,
+        FORECAST_LOW_ALERT,
+        &format!(
+            "Glucose forecast to reach {:.0} mg/dL (95% range {:.0} mg/dL – {:.0} mg/dL) in {} minutes, below the low threshold of {:.0} mg/dL",
+            forecast.predicted, forecast.lower, forecast.upper, forecast.horizon_minutes, patient.low_glucose_threshold
+        ),
+    )?;
+    Ok(true)
+}
@@ -0,0 +1,245 @@
+// Import of pump_simm CSV files (CGM readings and insulin injections) into the database
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::{Connection, Result};
+use crate::alerts::evaluate_reading_alerts;
+use crate::cgm::insert_glucose_reading;
+use crate::insulin::insert_insulin_log_at;
+use crate::utils::{get_current_db_time_string, DB_TIME_FORMAT};
+
+// glucose values outside this range (mg/dL) cannot come from a living patient or a working sensor
+pub const MIN_GLUCOSE_MG_DL: f32 = 20.0;
+pub const MAX_GLUCOSE_MG_DL: f32 = 600.0;
+// largest single insulin delivery accepted from a device file (matches the max dosage limit)
+pub const MAX_IMPORTED_DOSE_UNITS: f32 = 200.0;
+
+// a row that was rejected, with its line number in the file (header is line 1)
+pub struct RowError {
+    pub line: usize,
+    pub message: String,
+}
+
+// summary of one import run
+pub struct ImportReport {
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+impl ImportReport {
+    pub fn print(&self, label: &str) {
+        println!("\n--- {} import ---", label);
+        println!("Imported rows: {}", self.imported);
+        println!("Rejected rows: {}", self.errors.len());
+        for error in &self.errors {
+            println!("* line {}: {}", error.line, error.message);
+        }
+    }
+}
+
+// all patient ids currently in the patients table
+fn get_known_patient_ids(conn: &Connection) -> Result<HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT patient_id FROM patients")?;
+    stmt.query_map([], |row| row.get::<_, String>(0))?.collect()
+}
+
+// maps header names to column positions so column order in the file does not matter
+fn header_positions(headers: &csv::StringRecord) -> HashMap<String, usize> {
+    headers
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (name.trim().to_lowercase(), index))
+        .collect()
+}
+
+// returns the trimmed value for a named column, or None when the column is missing or empty
+fn field<'a>(record: &'a csv::StringRecord, positions: &HashMap<String, usize>, name: &str) -> Option<&'a str> {
+    positions
+        .get(name)
+        .and_then(|index| record.get(*index))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Parses a timestamp from a device file and returns it in the database's UTC format.
+/// Accepts `YYYY-MM-DD HH:MM:SS` (taken as UTC), `YYYY-MM-DDTHH:MM:SS` and RFC 3339 with an offset.
+/// Timestamps more than five minutes in the future are rejected.
+pub fn parse_device_timestamp(value: &str) -> std::result::Result<String, String> {
+    let parsed = DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc).naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, DB_TIME_FORMAT))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| format!("unrecognised timestamp '{}'", value))?;
+
+    if parsed > Utc::now().naive_utc() + chrono::Duration::minutes(5) {
+        return Err(format!("timestamp '{}' is in the future", value));
+    }
+    Ok(parsed.format(DB_TIME_FORMAT).to_string())
+}
+
+// checks that a patient id is present and belongs to a known patient
+fn validate_patient_id<'a>(patient_id: Option<&'a str>, known: &HashSet<String>) -> std::result::Result<&'a str, String> {
+    match patient_id {
+        None => Err("missing patient_id".to_string()),
+        Some(id) if !known.contains(id) => Err(format!("unknown patient_id '{}'", id)),
+        Some(id) => Ok(id),
+    }
+}
+
+// validates one gcm_reader.csv row; returns (patient_id, glucose_level, status, reading_time)
+fn parse_glucose_row(record: &csv::StringRecord, positions: &HashMap<String, usize>, known: &HashSet<String>)
+    -> std::result::Result<(String, f32, String, String), String> {
+    let patient_id = validate_patient_id(field(record, positions, "patient_id"), known)?;
+
+    let raw_level = field(record, positions, "glucose_level").ok_or("missing glucose_level")?;
+    let glucose_level: f32 = raw_level
+        .parse()
+        .map_err(|_| format!("glucose_level '{}' is not a number", raw_level))?;
+    if !glucose_level.is_finite() || !(MIN_GLUCOSE_MG_DL..=MAX_GLUCOSE_MG_DL).contains(&glucose_level) {
+        return Err(format!(
+            "glucose_level {} is outside the possible range {}–{} mg/dL",
+            raw_level, MIN_GLUCOSE_MG_DL, MAX_GLUCOSE_MG_DL
+        ));
+    }
+
+    let status = field(record, positions, "status").unwrap_or("complete");
+    let reading_time = match field(record, positions, "reading_time") {
+        Some(value) => parse_device_timestamp(value)?,
+        None => get_current_db_time_string(),
+    };
+
+    Ok((patient_id.to_string(), glucose_level, status.to_string(), reading_time))
+}
+
+// validates one insulin_injections.csv row; returns (patient_id, action_type, dosage_units, requested_by, dosage_time)
+fn parse_insulin_row(record: &csv::StringRecord, positions: &HashMap<String, usize>, known: &HashSet<String>)
+    -> std::result::Result<(String, String, f32, String, String), String> {
+    let patient_id = validate_patient_id(field(record, positions, "patient_id"), known)?;
+    let action_type = field(record, positions, "action_type").ok_or("missing action_type")?;
+
+    let raw_units = field(record, positions, "dosage_units").ok_or("missing dosage_units")?;
+    let dosage_units: f32 = raw_units
+        .parse()
+        .map_err(|_| format!("dosage_units '{}' is not a number", raw_units))?;
+    if !dosage_units.is_finite() || !(0.0..=MAX_IMPORTED_DOSE_UNITS).contains(&dosage_units) {
+        return Err(format!(
+            "dosage_units {} is outside the possible range 0–{} units",
+            raw_units, MAX_IMPORTED_DOSE_UNITS
+        ));
+    }
+
+    let requested_by = field(record, positions, "requested_by").unwrap_or("device");
+    let dosage_time = match field(record, positions, "dosage_time") {
+        Some(value) => parse_device_timestamp(value)?,
+        None => get_current_db_time_string(),
+    };
+
+    Ok((patient_id.to_string(), action_type.to_string(), dosage_units, requested_by.to_string(), dosage_time))
+}
+
+/// Imports a gcm_reader.csv file (`patient_id,glucose_level,status[,reading_time]`).
+/// Invalid rows are reported in the returned `ImportReport` and skipped; valid rows are stored.
+pub fn import_glucose_csv(conn: &Connection, path: &Path) -> std::result::Result<ImportReport, Box<dyn Error>> {
+    let known = get_known_patient_ids(conn)?;
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+    let positions = header_positions(reader.headers()?);
+    let mut report = ImportReport { imported: 0, errors: Vec::new() };
+
+    let mut imported_patients = HashSet::new();
+    let tx = conn.unchecked_transaction()?;
+    for (index, record) in reader.records().enumerate() {
+        let line = index + 2;
+        let result = record
+            .map_err(|e| format!("unreadable row: {}", e))
+            .and_then(|record| parse_glucose_row(&record, &positions, &known))
+            .and_then(|(patient_id, glucose_level, status, reading_time)| {
+                insert_glucose_reading(&tx, &patient_id, glucose_level, &reading_time, &status)
+                    .map_err(|e| format!("database error: {}", e))?;
+                Ok(patient_id)
+            });
+
+        match result {
+            Ok(patient_id) => {
+                report.imported += 1;
+                imported_patients.insert(patient_id);
+            }
+            Err(message) => report.errors.push(RowError { line, message }),
+        }
+    }
+    tx.commit()?;
+
+    // alerts are checked once per patient against their latest reading; old history raises none
+    for patient_id in imported_patients {
+        if let Err(e) = evaluate_reading_alerts(conn, &patient_id) {
+            eprintln!("Alert evaluation failed for patient {}: {}", patient_id, e);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Imports an insulin_injections.csv file (`patient_id,action_type,dosage_units[,requested_by][,dosage_time]`).
+/// Invalid rows are reported in the returned `ImportReport` and skipped; valid rows are stored.
+pub fn import_insulin_csv(conn: &Connection, path: &Path) -> std::result::Result<ImportReport, Box<dyn Error>> {
+    let known = get_known_patient_ids(conn)?;
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+    let positions = header_positions(reader.headers()?);
+    let mut report = ImportReport { imported: 0, errors: Vec::new() };
+
+    let tx = conn.unchecked_transaction()?;
+    for (index, record) in reader.records().enumerate() {
+        let line = index + 2;
+        let result = record
+            .map_err(|e| format!("unreadable row: {}", e))
+            .and_then(|record| parse_insulin_row(&record, &positions, &known))
+            .and_then(|(patient_id, action_type, dosage_units, requested_by, dosage_time)| {
+                insert_insulin_log_at(&tx, &patient_id, &action_type, dosage_units, &requested_by, &dosage_time, None)
+                    .map_err(|e| format!("database error: {}", e))
+            });
+
+        match result {
+            Ok(_) => report.imported += 1,
+            Err(message) => report.errors.push(RowError { line, message }),
+        }
+    }
+    tx.commit()?;
+
+    Ok(report)
+}
+
+/// Entry point for `glucoguard import <glucose|insulin> <csv_path>`.
+pub fn run_import_command(conn: &Connection, kind: &str, path: &str) {
+    let path = Path::new(path);
+    let result = match kind {
+        "glucose" => import_glucose_csv(conn, path).map(|report| report.print("Glucose reading")),
+        "insulin" => import_insulin_csv(conn, path).map(|report| report.print("Insulin log")),
+        _ => {
+            eprintln!("Unknown import type '{}'. Use 'glucose' or 'insulin'.", kind);
+            return;
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("Import failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_timestamps_are_stored_as_utc() {
+        assert_eq!(parse_device_timestamp("2026-01-01 08:00:00"), Ok("2026-01-01 08:00:00".to_string()));
+        assert_eq!(parse_device_timestamp("2026-01-01T08:00:00"), Ok("2026-01-01 08:00:00".to_string()));
+        assert_eq!(parse_device_timestamp("2026-01-01T10:00:00+02:00"), Ok("2026-01-01 08:00:00".to_string()));
+    }
+
+    #[test]
+    fn unparseable_and_future_timestamps_are_rejected() {
+        assert!(parse_device_timestamp("01/01/2026 08:00").is_err());
+        let tomorrow = (Utc::now().naive_utc() + chrono::Duration::days(1)).format(DB_TIME_FORMAT).to_string();
+        assert!(parse_device_timestamp(&tomorrow).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+// Streaming CGM ingest: devices send newline-delimited JSON readings over TCP or a Unix socket
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+use rand::Rng;
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use crate::alerts::evaluate_reading_alerts;
+use crate::cgm::insert_glucose_reading;
+use crate::config::env_or;
+use crate::import::{parse_device_timestamp, MAX_GLUCOSE_MG_DL, MIN_GLUCOSE_MG_DL};
+use crate::insulin::get_patient_data_from_patient_table;
+use crate::utils::get_current_db_time_string;
+
+// status stored with readings that arrived over the ingest socket
+pub const STREAMED_STATUS: &str = "streamed";
+// longest accepted message line; a longer line closes the connection
+const MAX_LINE_BYTES: u64 = 4096;
+// pause after a failed accept before accepting again
+const ACCEPT_RETRY_DELAY: StdDuration = StdDuration::from_millis(100);
+
+// where the ingest service listens; it only runs when enabled
+pub struct IngestSettings {
+    pub enabled: bool,
+    // "host:port" for TCP or "unix:/path/to/socket"
+    pub address: String,
+    // readings waiting for the database writer; senders wait (and stop reading their socket) when it is full
+    pub queue_capacity: usize,
+}
+
+impl IngestSettings {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_or("GLUCOGUARD_INGEST_ENABLED", false),
+            address: env_or("GLUCOGUARD_INGEST_ADDR", "127.0.0.1:7878".to_string()),
+            queue_capacity: env_or("GLUCOGUARD_INGEST_QUEUE", 64usize).max(1),
+        }
+    }
+}
+
+// first line of every connection
+#[derive(Deserialize)]
+struct DeviceHello {
+    device_id: String,
+    key: String,
+}
+
+// every following line; reading_time defaults to the time of arrival
+#[derive(Deserialize)]
+struct StreamedReading {
+    glucose_level: f32,
+    reading_time: Option<String>,
+    status: Option<String>,
+}
+
+// one line written back for every line received
+#[derive(Serialize, Default)]
+struct IngestReply {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reading_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alerts_raised: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl IngestReply {
+    fn accepted() -> Self {
+        IngestReply { ok: true, ..Default::default() }
+    }
+
+    fn rejected(error: impl Into<String>) -> Self {
+        IngestReply { ok: false, error: Some(error.into()), ..Default::default() }
+    }
+}
+
+// a validated reading waiting for the database writer, with the channel its reply goes back on
+struct IngestJob {
+    device_id: String,
+    patient_id: String,
+    glucose_level: f32,
+    reading_time: String,
+    status: String,
+    reply: oneshot::Sender<IngestReply>,
+}
+
+//-----------------------Device keys-----------------------//
+
+// keys are 256 random bits, so a plain SHA-256 is enough to avoid storing them
+fn hash_device_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Registers a device for a patient (or re-registers it with a new key) and returns the new key.
+/// The key is only shown once; the database keeps its hash.
+pub fn register_device(conn: &Connection, device_id: &str, patient_id: &str) -> Result<String> {
+    let key = hex::encode(rand::thread_rng().r#gen::<[u8; 32]>());
+    conn.execute(
+        "INSERT INTO cgm_devices (device_id, patient_id, key_hash, registered_at, last_seen)
+         VALUES (?1, ?2, ?3, ?4, NULL)
+         ON CONFLICT(device_id) DO UPDATE SET
+             patient_id = excluded.patient_id,
+             key_hash = excluded.key_hash,
+             registered_at = excluded.registered_at,
+             last_seen = NULL",
+        params![device_id, patient_id, hash_device_key(&key), get_current_db_time_string()],
+    )?;
+    Ok(key)
+}
+
+/// Patient the device streams for, or `None` when the device is unknown or the key is wrong.
+pub fn authenticate_device(conn: &Connection, device_id: &str, key: &str) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT patient_id FROM cgm_devices WHERE device_id = ?1 AND key_hash = ?2",
+        params![device_id, hash_device_key(key)],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+// `glucoguard register-device <patient_id> <device_id>` prints the key the device must send
+pub fn run_register_device_command(conn: &Connection, patient_id: &str, device_id: &str) {
+    match get_patient_data_from_patient_table(conn, patient_id) {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            eprintln!("Unknown patient_id '{}'.", patient_id);
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error retrieving patient: {}", e);
+            return;
+        }
+    }
+    match register_device(conn, device_id, patient_id) {
+        Ok(key) => {
+            println!("Device '{}' registered for patient {}.", device_id, patient_id);
+            println!("Device key (shown only once): {}", key);
+        }
+        Err(e) => eprintln!("Error registering device: {}", e),
+    }
+}
+
+//-----------------------Database writer-----------------------//
+
+// checks the range and timestamp of a streamed reading; returns (glucose_level, reading_time, status)
+fn validate_reading(reading: StreamedReading) -> std::result::Result<(f32, String, String), String> {
+    if !reading.glucose_level.is_finite() || !(MIN_GLUCOSE_MG_DL..=MAX_GLUCOSE_MG_DL).contains(&reading.glucose_level) {
+        return Err(format!(
+            "glucose_level {} is outside the possible range {}–{} mg/dL",
+            reading.glucose_level, MIN_GLUCOSE_MG_DL, MAX_GLUCOSE_MG_DL
+        ));
+    }
+    let reading_time = match reading.reading_time.as_deref() {
+        Some(value) => parse_device_timestamp(value)?,
+        None => get_current_db_time_string(),
+    };
+    let status = reading.status.unwrap_or_else(|| STREAMED_STATUS.to_string());
+    Ok((reading.glucose_level, reading_time, status))
+}
+
+// stores one reading and runs the per-reading alert checks
+fn store_reading(conn: &Connection, job: &IngestJob) -> Result<IngestReply> {
+    let reading_id = insert_glucose_reading(conn, &job.patient_id, job.glucose_level, &job.reading_time, &job.status)?;
+    conn.execute(
+        "UPDATE cgm_devices SET last_seen = ?1 WHERE device_id = ?2",
+        params![get_current_db_time_string(), job.device_id],
+    )?;
+    let alerts_raised = match evaluate_reading_alerts(conn, &job.patient_id) {
+        Ok(count) => count,
+        Err(e) => {
+            // the reading is stored either way; a failed check must not make the device resend it
+            eprintln!("Alert evaluation failed for patient {}: {}", job.patient_id, e);
+            0
+        }
+    };
+    Ok(IngestReply { reading_id: Some(reading_id), alerts_raised: Some(alerts_raised), ..IngestReply::accepted() })
+}
+
+// the only thread that writes streamed readings, one at a time in arrival order
+fn run_writer(db_path: String, mut jobs: mpsc::Receiver<IngestJob>) {
+    std::thread::spawn(move || {
+        let conn = match Connection::open(&db_path) {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Failed to open DB connection for CGM ingest: {:?}", e);
+                return;
+            }
+        };
+        let _ = conn.busy_timeout(StdDuration::from_secs(5));
+        while let Some(job) = jobs.blocking_recv() {
+            let reply = store_reading(&conn, &job).unwrap_or_else(|e| IngestReply::rejected(format!("database error: {}", e)));
+            // the device may have disconnected while waiting; the reading is stored regardless
+            let _ = job.reply.send(reply);
+        }
+    });
+}
+
+//-----------------------Socket server-----------------------//
+
+// reads one line of at most MAX_LINE_BYTES; Ok(None) at end of stream
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    let read = reader.take(MAX_LINE_BYTES).read_line(&mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && read as u64 == MAX_LINE_BYTES {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "message line too long"));
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+async fn write_reply<W: AsyncWrite + Unpin>(writer: &mut W, reply: &IngestReply) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(reply).unwrap_or_else(|_| "{\"ok\":false}".to_string());
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+// checks the hello line against cgm_devices; returns the device and its patient
+async fn authenticate<R: AsyncRead + Unpin>(reader: &mut BufReader<R>, db_path: Arc<String>) -> std::result::Result<(String, String), String> {
+    let line = match read_message(reader).await {
+        Ok(Some(line)) => line,
+        Ok(None) => return Err("connection closed before authentication".to_string()),
+        Err(e) => return Err(e.to_string()),
+    };
+    let hello: DeviceHello = serde_json::from_str(&line)
+        .map_err(|_| "first message must be {\"device_id\": ..., \"key\": ...}".to_string())?;
+
+    let device_id = hello.device_id.clone();
+    let patient_id = tokio::task::spawn_blocking(move || {
+        let conn = Connection::open(db_path.as_str())?;
+        conn.busy_timeout(StdDuration::from_secs(5))?;
+        authenticate_device(&conn, &hello.device_id, &hello.key)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("database error: {}", e))?;
+
+    match patient_id {
+        Some(patient_id) => Ok((device_id, patient_id)),
+        None => Err("unknown device or wrong key".to_string()),
+    }
+}
+
+/// Serves one device connection: an authentication line, then one reading per line.
+/// Every line gets a JSON reply. A reading is only acknowledged once it is stored, and the next
+/// line is not read until then, so a device that outpaces the database is slowed down.
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(stream: S, db_path: Arc<String>, jobs: mpsc::Sender<IngestJob>) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let (device_id, patient_id) = match authenticate(&mut reader, db_path).await {
+        Ok(device) => device,
+        Err(error) => {
+            let _ = write_reply(&mut writer, &IngestReply::rejected(error)).await;
+            return;
+        }
+    };
+    if write_reply(&mut writer, &IngestReply::accepted()).await.is_err() {
+        return;
+    }
+
+    loop {
+        let line = match read_message(&mut reader).await {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(e) => {
+                let _ = write_reply(&mut writer, &IngestReply::rejected(e.to_string())).await;
+                return;
+            }
+        };
+        if line.is_empty() {
+            continue;
+        }
+
+        let reply = match serde_json::from_str::<StreamedReading>(&line)
+            .map_err(|e| format!("invalid reading: {}", e))
+            .and_then(validate_reading)
+        {
+            Err(error) => IngestReply::rejected(error),
+            Ok((glucose_level, reading_time, status)) => {
+                let (reply_sender, reply_receiver) = oneshot::channel();
+                let job = IngestJob {
+                    device_id: device_id.clone(),
+                    patient_id: patient_id.clone(),
+                    glucose_level,
+                    reading_time,
+                    status,
+                    reply: reply_sender,
+                };
+                // waits while the queue is full
+                if jobs.send(job).await.is_err() {
+                    let _ = write_reply(&mut writer, &IngestReply::rejected("ingest service is shutting down")).await;
+                    return;
+                }
+                reply_receiver
+                    .await
+                    .unwrap_or_else(|_| IngestReply::rejected("reading was not stored"))
+            }
+        };
+        if write_reply(&mut writer, &reply).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn serve(address: String, db_path: Arc<String>, jobs: mpsc::Sender<IngestJob>) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(path) = address.strip_prefix("unix:") {
+        // a socket file left behind by a previous run would make bind fail
+        let _ = std::fs::remove_file(path);
+        let listener = tokio::net::UnixListener::bind(path)?;
+        eprintln!("CGM ingest listening on {}", address);
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, db_path.clone(), jobs.clone()));
+                }
+                Err(e) => accept_failed(e).await,
+            }
+        }
+    }
+
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+    eprintln!("CGM ingest listening on {}", address);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, db_path.clone(), jobs.clone()));
+            }
+            Err(e) => accept_failed(e).await,
+        }
+    }
+}
+
+// a failed accept (e.g. a reset connection or running out of file descriptors) only loses that
+// connection; the pause keeps a persistent error from spinning the loop
+async fn accept_failed(e: std::io::Error) {
+    eprintln!("CGM ingest failed to accept a connection: {:?}", e);
+    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+}
+
+// Run the ingest service on its own tokio runtime in a background thread, when enabled
+pub fn run_ingest_server(db_path: &str, settings: IngestSettings) {
+    if !settings.enabled {
+        return;
+    }
+    let db_path = Arc::new(db_path.to_string());
+    let (jobs, receiver) = mpsc::channel(settings.queue_capacity);
+    run_writer(db_path.to_string(), receiver);
+
+    std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to start CGM ingest runtime: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = runtime.block_on(serve(settings.address, db_path, jobs)) {
+            eprintln!("CGM ingest stopped: {:?}", e);
+        }
+    });
+}
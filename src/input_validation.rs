@@ -1,6 +1,7 @@
 //input validation helper functions
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveTime};
 use std::io::{self, Write};
+use crate::units::GlucoseUnit;
 
 // Secure input reader (loops until valid input)
 pub fn read_non_empty_input(prompt: &str) -> String {
@@ -41,4 +42,35 @@ pub fn read_valid_float(prompt: &str, min: f32, max: f32) -> f32 {
             _ => println!(" Invalid number. Please enter a value between {} and {}.", min, max),
         }
     }
-}
\ No newline at end of file
+}
+// Read and validate a whole number
+pub fn read_valid_int(prompt: &str, min: i32, max: i32) -> i32 {
+    loop {
+        let input = read_non_empty_input(prompt);
+        match input.parse::<i32>() {
+            Ok(value) if value >= min && value <= max => return value,
+            _ => println!(" Invalid number. Please enter a whole number between {} and {}.", min, max),
+        }
+    }
+}
+
+// validate time of day in 24-hour HH:MM format
+pub fn read_valid_time_hh_mm(prompt: &str) -> NaiveTime {
+    loop {
+        let input = read_non_empty_input(prompt);
+        match NaiveTime::parse_from_str(&input, "%H:%M") {
+            Ok(time) => return time,
+            Err(_) => println!("Invalid time format. Please use HH:MM (24-hour)."),
+        }
+    }
+}
+// Read a glucose value in the user's display unit; the range is given and returned in mg/dL
+pub fn read_valid_glucose(label: &str, min_mg_dl: f32, max_mg_dl: f32, unit: GlucoseUnit) -> f32 {
+    let prompt = format!(
+        "{} ({}–{} {}): ",
+        label, unit.format_value(min_mg_dl), unit.format_value(max_mg_dl), unit.label()
+    );
+    let value = read_valid_float(&prompt, unit.display_value(min_mg_dl), unit.display_value(max_mg_dl));
+    // rounding in the converted bounds must not let a value slip outside the canonical range
+    unit.to_mg_dl(value).clamp(min_mg_dl, max_mg_dl)
+}
@@ -1,5 +1,14 @@
 use rusqlite::{Connection, Result, OptionalExtension,params};
-use chrono::{NaiveDateTime, Local,TimeZone};
+use chrono::{NaiveDateTime, Local,TimeZone, Duration, Utc};
+use crate::basal::{get_scheduled_basal_rate, get_active_temp_basal};
+use crate::suspend::is_basal_suspended;
+use crate::trend::get_glucose_trend;
+use crate::forecast::display_glucose_forecast;
+use crate::sensor::display_sensor_status;
+use crate::units::GlucoseUnit;
+use crate::filter::{GlucoseSource, GlucoseSourceSettings, ReadingQuality};
+use crate::signal::{find_gaps, minutes_since_last_reading, SignalSettings};
+use crate::utils::{get_current_db_time_string, format_local_time, DB_TIME_FORMAT};
 
 // Fetch patient with safety limits
     pub struct PatientSafety {
@@ -11,13 +20,35 @@ use chrono::{NaiveDateTime, Local,TimeZone};
         pub high_glucose_threshold: f32,
         pub basal_rate:f32,
         pub bolus_rate:f32,
+        pub iob_model: String,
+        pub insulin_action_minutes: f32,
+        pub carb_ratio: Option<f32>,
+        pub correction_factor: Option<f32>,
+        pub target_glucose: Option<f32>,
     }
     // hold glucose reading data
     #[derive(Debug)]
     pub struct GlucoseReading {
+        // calibrated sensor value as received
         pub glucose_level: f32,
+        // median-filtered value; None for readings stored before filtering was added
+        pub smoothed_glucose_level: Option<f32>,
         pub reading_time: String,
         pub status: String,
+        pub quality: ReadingQuality,
+        // serial number of the sensor that produced the reading, when sensor sessions are in use
+        pub sensor_serial: Option<String>,
+        // set for readings taken during sensor warm-up or after its wear time; they are never used
+        pub unusable_reason: Option<String>,
+    }
+
+    impl GlucoseReading {
+        pub fn value(&self, source: GlucoseSource) -> f32 {
+            match source {
+                GlucoseSource::Raw => self.glucose_level,
+                GlucoseSource::Smoothed => self.smoothed_glucose_level.unwrap_or(self.glucose_level),
+            }
+        }
     }
 
 /// # Return Type
@@ -61,7 +92,8 @@ pub fn get_one_patient_by_caretaker_id(conn: &Connection, caretaker_id: &str) ->
 pub fn get_patient_data_from_patient_table(conn: &Connection, patient_id: &str) -> Result<Option<PatientSafety>> {
     let patient = conn
         .query_row(
-            "SELECT patient_id, first_name, last_name, max_dosage,basal_rate,bolus_rate, low_glucose_threshold, high_glucose_threshold
+            "SELECT patient_id, first_name, last_name, max_dosage,basal_rate,bolus_rate, low_glucose_threshold, high_glucose_threshold,
+                    iob_model, insulin_action_minutes, carb_ratio, correction_factor, target_glucose
             FROM patients
             WHERE patient_id = ?1",
             rusqlite::params![patient_id],
@@ -75,7 +107,11 @@ pub fn get_patient_data_from_patient_table(conn: &Connection, patient_id: &str)
                     bolus_rate:row.get(5)?,
                     low_glucose_threshold: row.get(6)?,
                     high_glucose_threshold: row.get(7)?,
-                    
+                    iob_model: row.get(8)?,
+                    insulin_action_minutes: row.get(9)?,
+                    carb_ratio: row.get(10)?,
+                    correction_factor: row.get(11)?,
+                    target_glucose: row.get(12)?,
                 })
             },
         )
@@ -86,30 +122,38 @@ pub fn get_patient_data_from_patient_table(conn: &Connection, patient_id: &str)
 }
 
 
-// returns patient glucose history data as a vector
-pub fn get_patient_glucose_history(conn: &Connection,patient_id: &str, display_just_latest_one: bool,) -> Result<Vec<(String, f32, String)>> {
+// returns patient glucose history data as a vector, newest first
+pub fn get_patient_glucose_history(conn: &Connection,patient_id: &str, display_just_latest_one: bool,) -> Result<Vec<GlucoseReading>> {
     // Build query based on flag
     let query = if display_just_latest_one {
-        "SELECT reading_time, glucose_level, status
-         FROM glucose_readings
-         WHERE patient_id = ?1
-         ORDER BY datetime(reading_time) DESC
+        "SELECT r.reading_time, r.glucose_level, r.smoothed_glucose_level, r.status, r.reading_quality,
+                s.serial_number, r.unusable_reason
+         FROM glucose_readings r
+         LEFT JOIN sensor_sessions s ON s.sensor_session_id = r.sensor_session_id
+         WHERE r.patient_id = ?1
+         ORDER BY datetime(r.reading_time) DESC
          LIMIT 1"
     } else {
-        "SELECT reading_time, glucose_level, status
-         FROM glucose_readings
-         WHERE patient_id = ?1
-         ORDER BY datetime(reading_time) DESC"
+        "SELECT r.reading_time, r.glucose_level, r.smoothed_glucose_level, r.status, r.reading_quality,
+                s.serial_number, r.unusable_reason
+         FROM glucose_readings r
+         LEFT JOIN sensor_sessions s ON s.sensor_session_id = r.sensor_session_id
+         WHERE r.patient_id = ?1
+         ORDER BY datetime(r.reading_time) DESC"
     };
 
     let mut stmt = conn.prepare(query)?;
     let readings = stmt
         .query_map(params![patient_id], |row| {
-            Ok((
-                row.get::<_, String>(0)?, // reading_time
-                row.get::<_, f32>(1)?,    // glucose_level
-                row.get::<_, String>(2)?, // status
-            ))
+            Ok(GlucoseReading {
+                reading_time: row.get(0)?,
+                glucose_level: row.get(1)?,
+                smoothed_glucose_level: row.get(2)?,
+                status: row.get(3)?,
+                quality: ReadingQuality::from_db_value(row.get::<_, Option<String>>(4)?.as_deref()),
+                sensor_serial: row.get(5)?,
+                unusable_reason: row.get(6)?,
+            })
         })?
         .collect::<Result<Vec<_>, _>>()?;
 
@@ -117,16 +161,41 @@ pub fn get_patient_glucose_history(conn: &Connection,patient_id: &str, display_j
 }
 
 
-pub fn get_patient_insulin_data(conn: &Connection, patient_id: &str, display_just_latest_one: bool) -> Result<()> {
+// returns (reading_time, glucose) for usable readings in the last `minutes`, oldest first, using the given source
+pub fn get_recent_glucose_readings(conn: &Connection, patient_id: &str, minutes: i64, source: GlucoseSource) -> Result<Vec<(NaiveDateTime, f32)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT reading_time, {}
+         FROM glucose_readings
+         WHERE patient_id = ?1 AND datetime(reading_time) >= datetime('now', ?2)
+           AND unusable_reason IS NULL
+         ORDER BY datetime(reading_time) ASC",
+        source.column()
+    ))?;
+    let rows = stmt.query_map(params![patient_id, format!("-{} minutes", minutes)], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f32>(1)?))
+    })?;
+
+    let mut readings = Vec::new();
+    for row in rows {
+        let (time_str, glucose) = row?;
+        if let Ok(reading_time) = NaiveDateTime::parse_from_str(&time_str, DB_TIME_FORMAT) {
+            readings.push((reading_time, glucose));
+        }
+    }
+    Ok(readings)
+}
+
+
+pub fn get_patient_insulin_data(conn: &Connection, patient_id: &str, display_just_latest_one: bool, unit: GlucoseUnit) -> Result<()> {
     // Build query depending on whether only the latest record should be shown
     let query = if display_just_latest_one {
-        "SELECT dosage_id, action_type, dosage_units, requested_by, dosage_time
+        "SELECT dosage_id, action_type, dosage_units, requested_by, dosage_time, reason
          FROM insulin_logs
          WHERE patient_id = ?1
          ORDER BY datetime(dosage_time) DESC
          LIMIT 1"
     } else {
-        "SELECT dosage_id, action_type, dosage_units, requested_by, dosage_time
+        "SELECT dosage_id, action_type, dosage_units, requested_by, dosage_time, reason
          FROM insulin_logs
          WHERE patient_id = ?1
          ORDER BY datetime(dosage_time) DESC"
@@ -140,6 +209,7 @@ pub fn get_patient_insulin_data(conn: &Connection, patient_id: &str, display_jus
             row.get::<_, f32>(2)?,    // dosage_units
             row.get::<_, String>(3)?, // requested_by
             row.get::<_, String>(4)?, // dosage_time
+            row.get::<_, Option<String>>(5)?, // reason
         ))
     })?;
 
@@ -153,7 +223,7 @@ pub fn get_patient_insulin_data(conn: &Connection, patient_id: &str, display_jus
 
     for entry in insulin_iter {
         found = true;
-        let (dosage_id, action_type, dosage_units, requested_by, time_str) = entry?;
+        let (dosage_id, action_type, dosage_units, requested_by, time_str, reason) = entry?;
 
         // Convert timestamp to readable format
         let formatted_time = if let Ok(parsed_time) =
@@ -168,6 +238,9 @@ pub fn get_patient_insulin_data(conn: &Connection, patient_id: &str, display_jus
         println!(
             "* {}| {} | {:.1} units |",
             formatted_time, action_type, dosage_units);
+        if let Some(reason) = reason {
+            println!("    reason: {}", unit.format_text(&reason));
+        }
     }
 
     if !found {
@@ -178,7 +251,7 @@ pub fn get_patient_insulin_data(conn: &Connection, patient_id: &str, display_jus
 }
 
 // displays patient glucose readings and if display_just_latest_one is true then just display just one latest reading
-pub fn display_patient_glucose_readings(conn: &Connection, patient_id: &str, display_just_latest_glucose: bool) {
+pub fn display_patient_glucose_readings(conn: &Connection, patient_id: &str, display_just_latest_glucose: bool, unit: GlucoseUnit) {
     match get_patient_glucose_history(conn, patient_id,display_just_latest_glucose) {
         Ok(readings) => {
             if readings.is_empty() {
@@ -186,19 +259,70 @@ pub fn display_patient_glucose_readings(conn: &Connection, patient_id: &str, dis
             } else {
                 println!("\n--- Glucose Reading History ---");
 
+                // readings are shown using the configured source, with the other value alongside when they differ
+                let source = GlucoseSourceSettings::from_env().display;
+
+                // the trend arrow is shown next to the latest reading only
+                let trend = match get_glucose_trend(conn, patient_id, source) {
+                    Ok(Some(trend)) => format!(" | Trend: {}", trend.describe(unit)),
+                    Ok(None) => String::new(),
+                    Err(e) => {
+                        eprintln!("Error computing glucose trend: {}", e);
+                        String::new()
+                    }
+                };
 
-                for (time_str, glucose, status) in readings {
-                    if let Ok(parsed_time) = NaiveDateTime::parse_from_str(&time_str, "%Y-%m-%d %H:%M:%S") {
+                // missing readings since the latest one, and between the listed readings
+                let signal = SignalSettings::from_env();
+                if let Ok(Some(minutes)) = minutes_since_last_reading(conn, patient_id) {
+                    if minutes >= signal.signal_loss_minutes {
+                        println!("!! Signal loss: no CGM reading for {} minutes", minutes);
+                    } else if Duration::minutes(minutes) > signal.gap_threshold() {
+                        println!("-- No CGM reading for {} minutes --", minutes);
+                    }
+                }
+                let reading_times: Vec<NaiveDateTime> = readings
+                    .iter()
+                    .filter_map(|reading| NaiveDateTime::parse_from_str(&reading.reading_time, DB_TIME_FORMAT).ok())
+                    .collect();
+                let gaps = find_gaps(&reading_times, &signal);
+
+                for (index, reading) in readings.iter().enumerate() {
+                    let trend = if index == 0 { trend.as_str() } else { "" };
+                    let glucose = reading.value(source);
+                    let other_source = match source {
+                        GlucoseSource::Raw => GlucoseSource::Smoothed,
+                        GlucoseSource::Smoothed => GlucoseSource::Raw,
+                    };
+                    let other = reading.value(other_source);
+                    let mut detail = String::new();
+                    if (other - glucose).abs() >= 0.05 {
+                        detail.push_str(&format!(" ({} {})", other_source.label(), unit.format_value(other)));
+                    }
+                    if reading.quality.is_outlier() {
+                        detail.push_str(&format!(" | Outlier: {}", reading.quality.description()));
+                    }
+                    if let Some(reason) = &reading.unusable_reason {
+                        detail.push_str(&format!(" | Not used: sensor {}", reason));
+                    }
+                    if let Some(serial) = &reading.sensor_serial {
+                        detail.push_str(&format!(" | Sensor: {}", serial));
+                    }
+                    if let Ok(parsed_time) = NaiveDateTime::parse_from_str(&reading.reading_time, "%Y-%m-%d %H:%M:%S") {
                         let local_time = Local.from_utc_datetime(&parsed_time);
                         let formatted_time = local_time.format("%b %d, %Y %I:%M %p");
                         println!(
-                            "* {} | Glucose: {:.1} mg/dL | Status: {}",
-                            formatted_time, glucose, status
+                            "* {} | Glucose: {}{}{} | Status: {}",
+                            formatted_time, unit.format(glucose), detail, trend, reading.status
                         );
+                        // readings are newest first, so a gap ending here lies before this reading
+                        if let Some(gap) = gaps.iter().find(|gap| gap.end == parsed_time) {
+                            println!("  -- gap: no CGM data for {} minutes --", gap.minutes());
+                        }
                     } else {
                         println!(
-                            "* {} | Glucose: {:.1} mg/dL | Status: {} (unparsed time)",
-                            time_str, glucose, status
+                            "* {} | Glucose: {}{} | Status: {} (unparsed time)",
+                            reading.reading_time, unit.format(glucose), detail, reading.status
                         );
                     }
                 }
@@ -208,14 +332,17 @@ pub fn display_patient_glucose_readings(conn: &Connection, patient_id: &str, dis
     }
 }
 
-pub fn display_patient_complete_glucose_insulin_history(conn: &Connection, patient_id: &str){
+pub fn display_patient_complete_glucose_insulin_history(conn: &Connection, patient_id: &str, unit: GlucoseUnit){
     println!("running display patient data:--------");
     // retrieve patient info from patient table in database
     match get_patient_data_from_patient_table(&conn, patient_id) {
         Ok(Some(patient)) => {
             // display all glucose data for patient
-            display_patient_glucose_readings(&conn, patient_id, false);
-            get_patient_insulin_data(&conn, patient_id, false);
+            display_sensor_status(conn, patient_id);
+            display_patient_glucose_readings(conn, patient_id, false, unit);
+            display_glucose_forecast(conn, patient_id, unit);
+            get_patient_insulin_data(&conn, patient_id, false, unit);
+            display_insulin_on_board(conn, &patient);
         },
         Ok(None) => println!("No patient found."),
         Err(e) => eprintln!("Error: {}", e),
@@ -230,8 +357,21 @@ pub fn show_patient_current_basal_bolus_limits(conn:&rusqlite::Connection, patie
             println!("\n--------Patient dosage info --------");
             println!("Name: {} {}", patient.first_name, patient.last_name);
             println!("Max Dosage: {:.2} units", patient.max_dosage);
-            println!("Basal rate: {:.1}, Bolus rate: {:.1} \n",
+            println!("Basal rate: {:.1}, Bolus rate: {:.1}",
                     patient.basal_rate, patient.bolus_rate);
+            display_insulin_on_board(conn, &patient);
+            match get_scheduled_basal_rate(conn, &patient) {
+                Ok(rate) => println!("Scheduled basal right now: {:.2} units/hour", rate),
+                Err(e) => eprintln!("Error fetching basal schedule: {}", e),
+            }
+            if let Ok(true) = is_basal_suspended(conn, patient_id) {
+                println!("Basal delivery: SUSPENDED (predicted low glucose)");
+            }
+            match get_active_temp_basal(conn, patient_id) {
+                Ok(Some(temp)) => println!("Active temp basal: {} \n", temp.describe()),
+                Ok(None) => println!("Active temp basal: none \n"),
+                Err(e) => eprintln!("Error fetching temp basal: {}", e),
+            }
         }
         Ok(None) => {
             println!("No patient found with ID: {}", patient_id);
@@ -240,4 +380,903 @@ pub fn show_patient_current_basal_bolus_limits(conn:&rusqlite::Connection, patie
             eprintln!("Error fetching patient data: {}", e);
         }
     }
-}
\ No newline at end of file
+}
+
+// action_type written to insulin_logs for a delivered bolus (matches the pump_simm data)
+pub const BOLUS_ACTION: &str = "Bolus";
+
+// result of a bolus request after the safety limits have been checked
+pub enum BolusOutcome {
+    Delivered(f32),
+    Refused(String),
+}
+
+// returns the most recent usable glucose level for the patient, if any such reading exists
+pub fn get_latest_glucose_level(conn: &Connection, patient_id: &str, source: GlucoseSource) -> Result<Option<f32>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM glucose_readings
+             WHERE patient_id = ?1 AND unusable_reason IS NULL
+             ORDER BY datetime(reading_time) DESC
+             LIMIT 1",
+            source.column()
+        ),
+        params![patient_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+// records an insulin delivery or event in insulin_logs, with an optional reason for automated decisions
+pub fn insert_insulin_log(conn: &Connection, patient_id: &str, action_type: &str, dosage_units: f32, requested_by: &str, reason: Option<&str>) -> Result<()> {
+    insert_insulin_log_at(conn, patient_id, action_type, dosage_units, requested_by, &get_current_db_time_string(), reason)
+}
+
+// records an insulin log row with an explicit UTC dosage_time (e.g. imported device data)
+pub fn insert_insulin_log_at(conn: &Connection, patient_id: &str, action_type: &str, dosage_units: f32, requested_by: &str, dosage_time: &str, reason: Option<&str>) -> Result<()> {
+    conn.execute(
+        "INSERT INTO insulin_logs (patient_id, action_type, dosage_units, requested_by, dosage_time, reason)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![patient_id, action_type, dosage_units, requested_by, dosage_time, reason],
+    )?;
+    Ok(())
+}
+
+/// Checks a requested bolus against the patient's prescribed safety limits.
+/// `insulin_on_board` is bolus insulin only (basal does not use up the bolus maximum) and
+/// `in_progress_units` is insulin still to be delivered by running extended boluses.
+/// Returns `Some(reason)` naming the limit that was hit, with glucose values in `unit`, or `None`
+/// if the dose is allowed.
+pub fn check_bolus_safety(patient: &PatientSafety, latest_glucose: Option<f32>, insulin_on_board: f32, in_progress_units: f32, units: f32, unit: GlucoseUnit) -> Option<String> {
+    if units <= 0.0 {
+        return Some("Bolus dose must be greater than 0 units.".to_string());
+    }
+
+    if units > patient.max_dosage {
+        return Some(format!(
+            "Requested {:.2} units exceeds the prescribed maximum dose of {:.2} units.",
+            units, patient.max_dosage
+        ));
+    }
+
+    // insulin still active from earlier doses, and insulin already committed to running extended
+    // boluses, counts against the maximum so doses are not stacked
+    if units + insulin_on_board + in_progress_units > patient.max_dosage {
+        let in_progress = if in_progress_units > 0.0 {
+            format!(" and {:.2} units still to be delivered by in-progress extended boluses", in_progress_units)
+        } else {
+            String::new()
+        };
+        return Some(format!(
+            "Requested {:.2} units plus {:.2} units of bolus insulin on board{} exceeds the prescribed maximum dose of {:.2} units.",
+            units, insulin_on_board, in_progress, patient.max_dosage
+        ));
+    }
+
+    match latest_glucose {
+        None => Some("No glucose reading on record, so glucose cannot be confirmed above the low threshold.".to_string()),
+        Some(glucose) if glucose < patient.low_glucose_threshold => Some(format!(
+            "Latest glucose {} is below the low glucose threshold of {}.",
+            unit.format(glucose), unit.format(patient.low_glucose_threshold)
+        )),
+        Some(_) => None,
+    }
+}
+
+/// Runs the safety checks for a bolus and, if they pass, logs the delivery in insulin_logs.
+/// # Return Type
+/// - `Ok(BolusOutcome::Delivered(units))` → the dose passed every check and was logged.
+/// - `Ok(BolusOutcome::Refused(reason))` → a safety limit was hit, nothing was logged.
+/// - `Err(e)` → a database error occurred.
+pub fn request_bolus_dose(conn: &Connection, patient_id: &str, units: f32, requested_by: &str, unit: GlucoseUnit) -> Result<BolusOutcome> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(BolusOutcome::Refused("No patient record found.".to_string())),
+    };
+
+    let latest_glucose = get_latest_glucose_level(conn, patient_id, GlucoseSourceSettings::from_env().dosing)?;
+    let insulin_on_board = get_bolus_insulin_on_board(conn, &patient)?;
+    let remaining = get_in_progress_remaining_units(conn, patient_id)?;
+    if let Some(reason) = check_bolus_safety(&patient, latest_glucose, insulin_on_board, remaining, units, unit) {
+        return Ok(BolusOutcome::Refused(reason));
+    }
+
+    insert_insulin_log(conn, patient_id, BOLUS_ACTION, units, requested_by, None)?;
+    Ok(BolusOutcome::Delivered(units))
+}
+
+// caretakers may request at most one bolus within this many hours (three meals a day)
+pub const CARETAKER_BOLUS_LOCKOUT_HOURS: i64 = 4;
+
+// returns the UTC time of the most recent bolus requested by a caretaker for the patient
+pub fn get_last_caretaker_bolus_time(conn: &Connection, patient_id: &str) -> Result<Option<NaiveDateTime>> {
+    let last_time: Option<String> = conn
+        .query_row(
+            "SELECT l.dosage_time
+             FROM insulin_logs l
+             JOIN users u ON u.id = l.requested_by
+             WHERE l.patient_id = ?1 AND u.role = 'caretaker' AND UPPER(l.action_type) = 'BOLUS'
+             ORDER BY datetime(l.dosage_time) DESC
+             LIMIT 1",
+            params![patient_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(last_time.and_then(|t| NaiveDateTime::parse_from_str(&t, DB_TIME_FORMAT).ok()))
+}
+
+// returns the UTC time the next caretaker bolus becomes allowed, or None if one is allowed now
+pub fn get_next_caretaker_bolus_time(conn: &Connection, patient_id: &str) -> Result<Option<NaiveDateTime>> {
+    let next_allowed = get_last_caretaker_bolus_time(conn, patient_id)?
+        .map(|last| last + Duration::hours(CARETAKER_BOLUS_LOCKOUT_HOURS));
+
+    Ok(next_allowed.filter(|next| *next > Utc::now().naive_utc()))
+}
+
+/// Caretaker bolus path: enforces the four-hour caretaker lockout before the regular safety checks.
+/// # Return Type
+/// - `Ok(BolusOutcome::Delivered(units))` → the dose passed every check and was logged.
+/// - `Ok(BolusOutcome::Refused(reason))` → the lockout or a safety limit was hit, nothing was logged.
+/// - `Err(e)` → a database error occurred.
+pub fn request_caretaker_bolus_dose(conn: &Connection, patient_id: &str, units: f32, caretaker_id: &str, unit: GlucoseUnit) -> Result<BolusOutcome> {
+    if let Some(next_allowed) = get_next_caretaker_bolus_time(conn, patient_id)? {
+        return Ok(BolusOutcome::Refused(format!(
+            "Caretakers may request only one dose every {} hours. Next dose allowed at {}.",
+            CARETAKER_BOLUS_LOCKOUT_HOURS,
+            format_local_time(&next_allowed)
+        )));
+    }
+
+    request_bolus_dose(conn, patient_id, units, caretaker_id, unit)
+}
+
+
+//-----------------------Insulin on board-----------------------//
+
+// time to peak activity for rapid-acting insulin, used by the exponential curve
+pub const EXPONENTIAL_PEAK_MINUTES: f32 = 75.0;
+
+// insulin action curve used to work out how much of a past dose is still active
+pub enum InsulinActionCurve {
+    // exponential activity curve with a peak (the rapid-acting model used by Loop/oref0)
+    Exponential { duration_minutes: f32, peak_minutes: f32 },
+    // activity rises linearly to a peak and falls linearly to zero, scaled to the duration of action
+    Bilinear { duration_minutes: f32 },
+}
+
+impl InsulinActionCurve {
+    // builds the curve from the iob_model / insulin_action_minutes settings stored on the patient
+    pub fn from_settings(model: &str, duration_minutes: f32) -> Self {
+        match model.to_lowercase().as_str() {
+            "bilinear" => InsulinActionCurve::Bilinear { duration_minutes },
+            // the exponential formula needs the peak to sit well before half the duration
+            _ => InsulinActionCurve::Exponential {
+                duration_minutes,
+                peak_minutes: EXPONENTIAL_PEAK_MINUTES.min(duration_minutes * 0.4),
+            },
+        }
+    }
+
+    // fraction (1.0 → 0.0) of a dose still active `minutes_ago` after it was delivered
+    pub fn fraction_remaining(&self, minutes_ago: f32) -> f32 {
+        if minutes_ago <= 0.0 {
+            return 1.0;
+        }
+
+        match *self {
+            InsulinActionCurve::Exponential { duration_minutes: td, peak_minutes: tp } => {
+                if minutes_ago >= td {
+                    return 0.0;
+                }
+                let t = minutes_ago;
+                let tau = tp * (1.0 - tp / td) / (1.0 - 2.0 * tp / td);
+                let a = 2.0 * tau / td;
+                let s = 1.0 / (1.0 - a + (1.0 + a) * (-td / tau).exp());
+                let remaining = 1.0 - s * (1.0 - a) * ((t * t / (tau * td * (1.0 - a)) - t / tau - 1.0) * (-t / tau).exp() + 1.0);
+                remaining.clamp(0.0, 1.0)
+            }
+            InsulinActionCurve::Bilinear { duration_minutes } => {
+                // the bilinear curve is defined on a 3 hour action time with a 75 minute peak
+                let scaled = minutes_ago * 180.0 / duration_minutes;
+                let remaining = if scaled < 75.0 {
+                    let x = scaled / 5.0 + 1.0;
+                    1.0 - 0.001852 * x * x + 0.001852 * x
+                } else if scaled < 180.0 {
+                    let x = (scaled - 75.0) / 5.0;
+                    0.001323 * x * x - 0.054233 * x + 0.55556
+                } else {
+                    0.0
+                };
+                remaining.clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    pub fn duration_minutes(&self) -> f32 {
+        match *self {
+            InsulinActionCurve::Exponential { duration_minutes, .. } => duration_minutes,
+            InsulinActionCurve::Bilinear { duration_minutes } => duration_minutes,
+        }
+    }
+}
+
+impl PatientSafety {
+    pub fn action_curve(&self) -> InsulinActionCurve {
+        InsulinActionCurve::from_settings(&self.iob_model, self.insulin_action_minutes)
+    }
+}
+
+// insulin_logs action types that represent insulin actually delivered into the body
+pub fn counts_toward_iob(action_type: &str) -> bool {
+    // extended bolus completion rows are excluded: their insulin is counted from extended_boluses
+    matches!(
+        action_type.to_uppercase().as_str(),
+        "BOLUS" | "BASAL" | "CONTROLLERBASAL" | "EXTENDEDBOLUSSTART" | "DUALWAVEBOLUSSTART"
+    )
+}
+
+// sums the still-active part of each dose; doses are (minutes ago, units)
+pub fn calculate_insulin_on_board(doses: &[(f32, f32)], curve: &InsulinActionCurve) -> f32 {
+    doses
+        .iter()
+        .map(|(minutes_ago, units)| units * curve.fraction_remaining(*minutes_ago))
+        .sum()
+}
+
+// the insulin_logs action types of those that are boluses rather than basal
+pub fn is_bolus_action(action_type: &str) -> bool {
+    [BOLUS_ACTION, EXTENDED_BOLUS_START_ACTION, DUAL_WAVE_BOLUS_START_ACTION]
+        .iter()
+        .any(|action| action.eq_ignore_ascii_case(action_type))
+}
+
+/// Returns the patient's current insulin on board (units) from the insulin_logs history,
+/// using the action curve configured for the patient.
+pub fn get_insulin_on_board(conn: &Connection, patient: &PatientSafety) -> Result<f32> {
+    let doses = get_active_insulin_doses(conn, patient)?;
+    Ok(calculate_insulin_on_board(&doses, &patient.action_curve()))
+}
+
+/// Bolus insulin on board (units): like `get_insulin_on_board` but without basal deliveries,
+/// for comparison with the bolus `max_dosage`.
+pub fn get_bolus_insulin_on_board(conn: &Connection, patient: &PatientSafety) -> Result<f32> {
+    let doses = get_active_doses_matching(conn, patient, is_bolus_action)?;
+    Ok(calculate_insulin_on_board(&doses, &patient.action_curve()))
+}
+
+// doses still inside the patient's insulin action window, as (minutes ago, units)
+pub fn get_active_insulin_doses(conn: &Connection, patient: &PatientSafety) -> Result<Vec<(f32, f32)>> {
+    get_active_doses_matching(conn, patient, counts_toward_iob)
+}
+
+// doses still inside the patient's insulin action window whose insulin_logs action type is
+// accepted by `include`, plus the extended portions, as (minutes ago, units)
+fn get_active_doses_matching(conn: &Connection, patient: &PatientSafety, include: fn(&str) -> bool) -> Result<Vec<(f32, f32)>> {
+    let curve = patient.action_curve();
+    let window = format!("-{} minutes", curve.duration_minutes().ceil() as i64);
+
+    let mut stmt = conn.prepare(
+        "SELECT action_type, dosage_units, dosage_time
+         FROM insulin_logs
+         WHERE patient_id = ?1 AND datetime(dosage_time) >= datetime('now', ?2)",
+    )?;
+    let rows = stmt.query_map(params![patient.patient_id, window], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, f32>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    })?;
+
+    let now = Utc::now().naive_utc();
+    let mut doses = Vec::new();
+    for row in rows {
+        let (action_type, units, time_str) = row?;
+        if !include(&action_type) {
+            continue;
+        }
+        if let Ok(dosage_time) = NaiveDateTime::parse_from_str(&time_str, DB_TIME_FORMAT) {
+            let minutes_ago = (now - dosage_time).num_seconds() as f32 / 60.0;
+            doses.push((minutes_ago, units));
+        }
+    }
+
+    // extended portions are spread over their delivery window rather than logged as one dose
+    doses.extend(get_extended_bolus_doses(conn, &patient.patient_id, curve.duration_minutes())?);
+
+    Ok(doses)
+}
+
+// prints the patient's current insulin on board
+pub fn display_insulin_on_board(conn: &Connection, patient: &PatientSafety) {
+    match get_insulin_on_board(conn, patient) {
+        Ok(iob) => println!(
+            "Insulin on board: {:.2} units ({} curve, {:.0} min action)",
+            iob, patient.iob_model, patient.insulin_action_minutes
+        ),
+        Err(e) => eprintln!("Error calculating insulin on board: {}", e),
+    }
+}
+
+
+//-----------------------Bolus wizard-----------------------//
+
+// meals logged within this many minutes (and after the last bolus) are covered by the wizard
+pub const WIZARD_MEAL_WINDOW_MINUTES: i64 = 60;
+
+// breakdown of a suggested meal/correction bolus
+pub struct BolusSuggestion {
+    pub carbs: f32,
+    pub glucose: f32,
+    pub carb_dose: f32,
+    pub correction_dose: f32,
+    pub insulin_on_board: f32,
+    // still to be delivered by in-progress extended boluses
+    pub in_progress_units: f32,
+    pub suggested_units: f32,
+    // true when the suggestion was cut down to what max_dosage still allows
+    pub capped: bool,
+}
+
+// result of running the bolus wizard
+pub enum BolusWizardOutcome {
+    Suggestion(BolusSuggestion),
+    Unavailable(String),
+}
+
+// records a meal's carbohydrates in meal_logs
+pub fn insert_meal_log(conn: &Connection, patient_id: &str, carbohydrate_amount: f32) -> Result<()> {
+    conn.execute(
+        "INSERT INTO meal_logs (patient_id, carbohydrate_amount, meal_time) VALUES (?1, ?2, ?3)",
+        params![patient_id, carbohydrate_amount, get_current_db_time_string()],
+    )?;
+    Ok(())
+}
+
+// total carbs logged in the meal window that no bolus has been given for yet
+pub fn get_unbolused_carbs(conn: &Connection, patient_id: &str) -> Result<f32> {
+    let window = format!("-{} minutes", WIZARD_MEAL_WINDOW_MINUTES);
+    conn.query_row(
+        "SELECT COALESCE(SUM(carbohydrate_amount), 0)
+         FROM meal_logs
+         WHERE patient_id = ?1
+           AND datetime(meal_time) >= datetime('now', ?2)
+           AND datetime(meal_time) > COALESCE(
+                (SELECT MAX(datetime(dosage_time)) FROM insulin_logs
+                 WHERE patient_id = ?1 AND UPPER(action_type) = 'BOLUS'),
+                '0000-00-00 00:00:00')",
+        params![patient_id, window],
+        |row| row.get(0),
+    )
+}
+
+/// Works out a suggested bolus: carbs / carb ratio + (glucose - target) / correction factor - IOB.
+/// The result is never negative and is capped at what `max_dosage` still allows after the insulin
+/// on board and the in-progress extended units, so the suggestion passes `check_bolus_safety`.
+pub fn calculate_bolus_suggestion(patient: &PatientSafety, carbs: f32, glucose: f32, insulin_on_board: f32, in_progress_units: f32) -> BolusWizardOutcome {
+    let (carb_ratio, correction_factor, target_glucose) =
+        match (patient.carb_ratio, patient.correction_factor, patient.target_glucose) {
+            (Some(cr), Some(cf), Some(target)) if cr > 0.0 && cf > 0.0 => (cr, cf, target),
+            _ => return BolusWizardOutcome::Unavailable(
+                "Carb ratio, correction factor and target glucose have not been prescribed by the clinician.".to_string()
+            ),
+        };
+
+    let carb_dose = carbs / carb_ratio;
+    let correction_dose = (glucose - target_glucose) / correction_factor;
+    let uncapped = (carb_dose + correction_dose - insulin_on_board).max(0.0);
+    let allowed = (patient.max_dosage - insulin_on_board - in_progress_units).max(0.0);
+    let suggested_units = uncapped.min(allowed);
+
+    BolusWizardOutcome::Suggestion(BolusSuggestion {
+        carbs,
+        glucose,
+        carb_dose,
+        correction_dose,
+        insulin_on_board,
+        in_progress_units,
+        suggested_units,
+        capped: uncapped > allowed,
+    })
+}
+
+/// Runs the bolus wizard for the patient using un-bolused carbs from meal_logs,
+/// the latest glucose reading, the current bolus insulin on board and in-progress extended units.
+pub fn suggest_meal_bolus(conn: &Connection, patient_id: &str) -> Result<BolusWizardOutcome> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(BolusWizardOutcome::Unavailable("No patient record found.".to_string())),
+    };
+
+    let glucose = match get_latest_glucose_level(conn, patient_id, GlucoseSourceSettings::from_env().dosing)? {
+        Some(g) => g,
+        None => return Ok(BolusWizardOutcome::Unavailable("No glucose reading on record.".to_string())),
+    };
+
+    let carbs = get_unbolused_carbs(conn, patient_id)?;
+    let insulin_on_board = get_bolus_insulin_on_board(conn, &patient)?;
+    let in_progress_units = get_in_progress_remaining_units(conn, patient_id)?;
+
+    Ok(calculate_bolus_suggestion(&patient, carbs, glucose, insulin_on_board, in_progress_units))
+}
+
+// prints the breakdown of a bolus suggestion
+pub fn display_bolus_suggestion(suggestion: &BolusSuggestion, unit: GlucoseUnit) {
+    println!("\n--- Bolus Wizard ---");
+    println!("Carbs: {:.0} g -> {:.2} units", suggestion.carbs, suggestion.carb_dose);
+    println!("Glucose: {} -> correction {:.2} units", unit.format(suggestion.glucose), suggestion.correction_dose);
+    println!("Insulin on board: -{:.2} units", suggestion.insulin_on_board);
+    if suggestion.in_progress_units > 0.0 {
+        println!("Still to come from extended boluses: {:.2} units", suggestion.in_progress_units);
+    }
+    println!("Suggested bolus: {:.2} units", suggestion.suggested_units);
+    if suggestion.capped {
+        println!("(Suggestion capped so it stays within the prescribed maximum dose.)");
+    }
+}
+
+
+//-----------------------Extended and dual-wave boluses-----------------------//
+
+// insulin_logs action types; the start row carries the units delivered immediately (zero for a
+// square-wave bolus) and the finish row carries the units delivered over the extended window
+pub const EXTENDED_BOLUS_START_ACTION: &str = "ExtendedBolusStart";
+pub const DUAL_WAVE_BOLUS_START_ACTION: &str = "DualWaveBolusStart";
+pub const EXTENDED_BOLUS_COMPLETE_ACTION: &str = "ExtendedBolusComplete";
+pub const EXTENDED_BOLUS_CANCEL_ACTION: &str = "ExtendedBolusCancel";
+
+// step used to spread an extended delivery over time when working out insulin on board
+const EXTENDED_IOB_STEP_MINUTES: f32 = 5.0;
+
+// kind of extended delivery being requested
+pub enum ExtendedBolusKind {
+    // whole dose delivered evenly over the duration
+    SquareWave,
+    // part delivered now (the percentage), the rest evenly over the duration
+    DualWave { immediate_percent: f32 },
+}
+
+pub struct ExtendedBolus {
+    pub extended_bolus_id: i64,
+    pub bolus_type: String,
+    pub total_units: f32,
+    pub immediate_units: f32,
+    pub duration_minutes: i64,
+    pub start_time: NaiveDateTime,
+    pub status: String,
+    // units delivered over the extended window, fixed once the bolus completes or is cancelled
+    pub extended_delivered: Option<f32>,
+}
+
+// result of requesting an extended or dual-wave bolus
+pub enum ExtendedBolusOutcome {
+    Started(ExtendedBolus),
+    Refused(String),
+}
+
+impl ExtendedBolus {
+    pub fn extended_units(&self) -> f32 {
+        self.total_units - self.immediate_units
+    }
+
+    // fraction of the extended window that has elapsed at `now`
+    fn elapsed_fraction(&self, now: NaiveDateTime) -> f32 {
+        let elapsed_minutes = (now - self.start_time).num_seconds() as f32 / 60.0;
+        (elapsed_minutes / self.duration_minutes as f32).clamp(0.0, 1.0)
+    }
+
+    // total units delivered so far (immediate part plus the extended part delivered up to now)
+    pub fn delivered_units(&self, now: NaiveDateTime) -> f32 {
+        let extended = match self.extended_delivered {
+            Some(units) => units,
+            None => self.extended_units() * self.elapsed_fraction(now),
+        };
+        self.immediate_units + extended
+    }
+
+    // units of an in-progress bolus that still have to be delivered
+    pub fn remaining_units(&self, now: NaiveDateTime) -> f32 {
+        if self.status == "in_progress" {
+            (self.total_units - self.delivered_units(now)).max(0.0)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn describe(&self, now: NaiveDateTime) -> String {
+        format!(
+            "#{} {} | {:.2} of {:.2} units delivered | {:.2} now + {:.2} over {} min from {} | {}",
+            self.extended_bolus_id,
+            self.bolus_type,
+            self.delivered_units(now),
+            self.total_units,
+            self.immediate_units,
+            self.extended_units(),
+            self.duration_minutes,
+            format_local_time(&self.start_time),
+            self.status
+        )
+    }
+}
+
+fn map_extended_bolus(row: &rusqlite::Row) -> Result<ExtendedBolus> {
+    Ok(ExtendedBolus {
+        extended_bolus_id: row.get(0)?,
+        bolus_type: row.get(1)?,
+        total_units: row.get(2)?,
+        immediate_units: row.get(3)?,
+        duration_minutes: row.get(4)?,
+        start_time: NaiveDateTime::parse_from_str(&row.get::<_, String>(5)?, DB_TIME_FORMAT).unwrap_or_default(),
+        status: row.get(6)?,
+        extended_delivered: row.get(7)?,
+    })
+}
+
+/// Marks in-progress extended boluses whose window has ended as completed and logs the extended
+/// portion delivered. Pass `None` to process every patient.
+pub fn complete_finished_extended_boluses(conn: &Connection, patient_id: Option<&str>) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT extended_bolus_id, bolus_type, total_units, immediate_units, duration_minutes,
+                start_time, status, extended_delivered, patient_id, requested_by
+         FROM extended_boluses
+         WHERE status = 'in_progress'
+           AND datetime(start_time, '+' || duration_minutes || ' minutes') <= datetime('now')
+           AND (?1 IS NULL OR patient_id = ?1)",
+    )?;
+    let finished = stmt
+        .query_map(params![patient_id], |row| {
+            Ok((map_extended_bolus(row)?, row.get::<_, String>(8)?, row.get::<_, String>(9)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (bolus, patient_id, requested_by) in finished {
+        let extended_units = bolus.extended_units();
+        conn.execute(
+            "UPDATE extended_boluses SET status = 'completed', extended_delivered = ?1 WHERE extended_bolus_id = ?2",
+            params![extended_units, bolus.extended_bolus_id],
+        )?;
+        let reason = format!(
+            "{} #{} finished: {:.2} units delivered over {} min",
+            bolus.bolus_type, bolus.extended_bolus_id, extended_units, bolus.duration_minutes
+        );
+        insert_insulin_log(conn, &patient_id, EXTENDED_BOLUS_COMPLETE_ACTION, extended_units, &requested_by, Some(&reason))?;
+    }
+    Ok(())
+}
+
+// returns the patient's extended boluses started within the last `minutes`, newest first
+fn get_recent_extended_boluses(conn: &Connection, patient_id: &str, minutes: i64) -> Result<Vec<ExtendedBolus>> {
+    complete_finished_extended_boluses(conn, Some(patient_id))?;
+
+    let mut stmt = conn.prepare(
+        "SELECT extended_bolus_id, bolus_type, total_units, immediate_units, duration_minutes,
+                start_time, status, extended_delivered
+         FROM extended_boluses
+         WHERE patient_id = ?1 AND datetime(start_time) >= datetime('now', ?2)
+         ORDER BY datetime(start_time) DESC",
+    )?;
+    stmt.query_map(params![patient_id, format!("-{} minutes", minutes)], map_extended_bolus)?
+        .collect()
+}
+
+// returns the patient's extended boluses that are still delivering
+pub fn get_in_progress_extended_boluses(conn: &Connection, patient_id: &str) -> Result<Vec<ExtendedBolus>> {
+    // an extended bolus can run for at most a day
+    let boluses = get_recent_extended_boluses(conn, patient_id, 24 * 60)?;
+    Ok(boluses.into_iter().filter(|b| b.status == "in_progress").collect())
+}
+
+// units still to be delivered by the patient's in-progress extended boluses
+pub fn get_in_progress_remaining_units(conn: &Connection, patient_id: &str) -> Result<f32> {
+    let now = Utc::now().naive_utc();
+    let boluses = get_in_progress_extended_boluses(conn, patient_id)?;
+    Ok(boluses.iter().map(|b| b.remaining_units(now)).sum())
+}
+
+// splits delivered extended portions into (minutes ago, units) steps for the insulin-on-board sum
+fn get_extended_bolus_doses(conn: &Connection, patient_id: &str, action_minutes: f32) -> Result<Vec<(f32, f32)>> {
+    let now = Utc::now().naive_utc();
+    let lookback = action_minutes.ceil() as i64 + 24 * 60;
+    let mut doses = Vec::new();
+
+    for bolus in get_recent_extended_boluses(conn, patient_id, lookback)? {
+        let delivered = bolus.delivered_units(now) - bolus.immediate_units;
+        let per_minute = bolus.extended_units() / bolus.duration_minutes as f32;
+        if delivered <= 0.0 || per_minute <= 0.0 {
+            continue;
+        }
+
+        // walk the window that has actually been delivered in fixed steps
+        let delivered_minutes = delivered / per_minute;
+        let minutes_since_start = (now - bolus.start_time).num_seconds() as f32 / 60.0;
+        let mut offset = 0.0;
+        while offset < delivered_minutes {
+            let step = EXTENDED_IOB_STEP_MINUTES.min(delivered_minutes - offset);
+            let minutes_ago = minutes_since_start - (offset + step / 2.0);
+            doses.push((minutes_ago, per_minute * step));
+            offset += step;
+        }
+    }
+    Ok(doses)
+}
+
+/// Extended portions delivered after `since`, as (time, units) in steps of at most a minute, for
+/// models that apply insulin at the time it was infused. Cancelled boluses stop at the units they
+/// delivered.
+pub fn get_extended_bolus_deliveries_since(conn: &Connection, patient_id: &str, since: &NaiveDateTime) -> Result<Vec<(NaiveDateTime, f32)>> {
+    let now = Utc::now().naive_utc();
+    let lookback = (now - *since).num_minutes().max(0) + 24 * 60;
+    let mut deliveries = Vec::new();
+
+    for bolus in get_recent_extended_boluses(conn, patient_id, lookback)? {
+        let delivered = bolus.delivered_units(now) - bolus.immediate_units;
+        let per_minute = bolus.extended_units() / bolus.duration_minutes as f32;
+        if delivered <= 0.0 || per_minute <= 0.0 {
+            continue;
+        }
+
+        let delivered_end = bolus.start_time + Duration::seconds((delivered / per_minute * 60.0).round() as i64);
+        let mut step_start = bolus.start_time.max(*since);
+        while step_start < delivered_end {
+            let step_end = (step_start + Duration::minutes(1)).min(delivered_end);
+            let minutes = (step_end - step_start).num_seconds() as f32 / 60.0;
+            deliveries.push((step_end, per_minute * minutes));
+            step_start = step_end;
+        }
+    }
+    deliveries.sort_by_key(|(time, _)| *time);
+    Ok(deliveries)
+}
+
+/// Starts a square-wave or dual-wave bolus delivered over `duration_minutes`.
+/// The whole dose (immediate and extended parts) is checked against the same limits as a normal
+/// bolus, including insulin on board and other in-progress extended boluses.
+pub fn request_extended_bolus(conn: &Connection, patient_id: &str, kind: ExtendedBolusKind, total_units: f32, duration_minutes: i64, requested_by: &str, unit: GlucoseUnit) -> Result<ExtendedBolusOutcome> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(ExtendedBolusOutcome::Refused("No patient record found.".to_string())),
+    };
+
+    if !(15..=720).contains(&duration_minutes) {
+        return Ok(ExtendedBolusOutcome::Refused("Extended duration must be between 15 minutes and 12 hours.".to_string()));
+    }
+
+    let latest_glucose = get_latest_glucose_level(conn, patient_id, GlucoseSourceSettings::from_env().dosing)?;
+    let insulin_on_board = get_bolus_insulin_on_board(conn, &patient)?;
+    let remaining = get_in_progress_remaining_units(conn, patient_id)?;
+    if let Some(reason) = check_bolus_safety(&patient, latest_glucose, insulin_on_board, remaining, total_units, unit) {
+        return Ok(ExtendedBolusOutcome::Refused(reason));
+    }
+
+    let (bolus_type, immediate_units, start_action) = match kind {
+        ExtendedBolusKind::SquareWave => ("extended", 0.0, EXTENDED_BOLUS_START_ACTION),
+        ExtendedBolusKind::DualWave { immediate_percent } => (
+            "dual_wave",
+            total_units * immediate_percent.clamp(0.0, 100.0) / 100.0,
+            DUAL_WAVE_BOLUS_START_ACTION,
+        ),
+    };
+
+    let start_time = Utc::now().naive_utc();
+    conn.execute(
+        "INSERT INTO extended_boluses (patient_id, bolus_type, total_units, immediate_units, duration_minutes,
+                                       start_time, status, requested_by)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'in_progress', ?7)",
+        params![
+            patient_id,
+            bolus_type,
+            total_units,
+            immediate_units,
+            duration_minutes,
+            start_time.format(DB_TIME_FORMAT).to_string(),
+            requested_by
+        ],
+    )?;
+
+    let bolus = ExtendedBolus {
+        extended_bolus_id: conn.last_insert_rowid(),
+        bolus_type: bolus_type.to_string(),
+        total_units,
+        immediate_units,
+        duration_minutes,
+        start_time,
+        status: "in_progress".to_string(),
+        extended_delivered: None,
+    };
+    let reason = format!(
+        "{} #{}: {:.2} units now, {:.2} units over {} min",
+        bolus_type, bolus.extended_bolus_id, immediate_units, bolus.extended_units(), duration_minutes
+    );
+    insert_insulin_log(conn, patient_id, start_action, immediate_units, requested_by, Some(&reason))?;
+
+    Ok(ExtendedBolusOutcome::Started(bolus))
+}
+
+/// Cancels an in-progress extended bolus, recording what was delivered up to now.
+/// Returns `Ok(Some(delivered_extended_units))`, or `Ok(None)` if no such in-progress bolus exists.
+pub fn cancel_extended_bolus(conn: &Connection, patient_id: &str, extended_bolus_id: i64, requested_by: &str) -> Result<Option<f32>> {
+    let now = Utc::now().naive_utc();
+    let bolus = match get_in_progress_extended_boluses(conn, patient_id)?
+        .into_iter()
+        .find(|b| b.extended_bolus_id == extended_bolus_id)
+    {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+
+    let delivered_extended = bolus.delivered_units(now) - bolus.immediate_units;
+    conn.execute(
+        "UPDATE extended_boluses SET status = 'cancelled', extended_delivered = ?1 WHERE extended_bolus_id = ?2",
+        params![delivered_extended, extended_bolus_id],
+    )?;
+    let reason = format!(
+        "{} #{} cancelled: {:.2} of {:.2} extended units delivered",
+        bolus.bolus_type, extended_bolus_id, delivered_extended, bolus.extended_units()
+    );
+    insert_insulin_log(conn, patient_id, EXTENDED_BOLUS_CANCEL_ACTION, delivered_extended, requested_by, Some(&reason))?;
+
+    Ok(Some(delivered_extended))
+}
+
+// prints the patient's in-progress extended and dual-wave boluses
+pub fn display_in_progress_extended_boluses(conn: &Connection, patient_id: &str) {
+    let now = Utc::now().naive_utc();
+    println!("\n--- In-progress Extended Boluses ---");
+    match get_in_progress_extended_boluses(conn, patient_id) {
+        Ok(boluses) if boluses.is_empty() => println!("No extended boluses are in progress."),
+        Ok(boluses) => {
+            for bolus in boluses {
+                println!("* {}", bolus.describe(now));
+            }
+        }
+        Err(e) => eprintln!("Error retrieving extended boluses: {}", e),
+    }
+}
+
+// Complete finished extended boluses in a background thread every 60 seconds
+pub fn run_extended_bolus_monitor(db_path: &str) {
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || loop {
+        match Connection::open(&db_path) {
+            Ok(conn) => {
+                let _ = conn.busy_timeout(std::time::Duration::from_secs(5));
+                if let Err(e) = complete_finished_extended_boluses(&conn, None) {
+                    eprintln!("Failed to complete extended boluses: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to open DB connection for extended bolus monitor: {:?}", e),
+        }
+        std::thread::sleep(std::time::Duration::from_secs(60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // max 10 units, thresholds 70–180 mg/dL, exponential curve over 4 hours,
+    // wizard settings 10 g/U, 50 mg/dL/U and target 100 mg/dL
+    fn patient() -> PatientSafety {
+        PatientSafety {
+            patient_id: "patient".to_string(),
+            first_name: "Test".to_string(),
+            last_name: "Patient".to_string(),
+            max_dosage: 10.0,
+            low_glucose_threshold: 70.0,
+            high_glucose_threshold: 180.0,
+            basal_rate: 1.0,
+            bolus_rate: 1.0,
+            iob_model: "exponential".to_string(),
+            insulin_action_minutes: 240.0,
+            carb_ratio: Some(10.0),
+            correction_factor: Some(50.0),
+            target_glucose: Some(100.0),
+        }
+    }
+
+    fn suggestion(outcome: BolusWizardOutcome) -> BolusSuggestion {
+        match outcome {
+            BolusWizardOutcome::Suggestion(suggestion) => suggestion,
+            BolusWizardOutcome::Unavailable(reason) => panic!("wizard unavailable: {}", reason),
+        }
+    }
+
+    #[test]
+    fn bolus_within_limits_is_allowed() {
+        assert_eq!(check_bolus_safety(&patient(), Some(120.0), 2.0, 0.0, 5.0, GlucoseUnit::MgDl), None);
+    }
+
+    #[test]
+    fn bolus_of_zero_or_over_max_is_refused() {
+        let patient = patient();
+        assert!(check_bolus_safety(&patient, Some(120.0), 0.0, 0.0, 0.0, GlucoseUnit::MgDl).is_some());
+        assert!(check_bolus_safety(&patient, Some(120.0), 0.0, 0.0, 10.5, GlucoseUnit::MgDl).is_some());
+    }
+
+    #[test]
+    fn bolus_counts_insulin_on_board() {
+        let patient = patient();
+        assert_eq!(check_bolus_safety(&patient, Some(120.0), 5.0, 0.0, 5.0, GlucoseUnit::MgDl), None);
+        assert!(check_bolus_safety(&patient, Some(120.0), 6.0, 0.0, 5.0, GlucoseUnit::MgDl).is_some());
+    }
+
+    #[test]
+    fn bolus_counts_iob_and_in_progress_units_together() {
+        // each of IOB and in-progress units fits on its own, but not both together
+        let patient = patient();
+        assert_eq!(check_bolus_safety(&patient, Some(120.0), 0.0, 4.0, 5.0, GlucoseUnit::MgDl), None);
+        let reason = check_bolus_safety(&patient, Some(120.0), 5.0, 4.0, 5.0, GlucoseUnit::MgDl).expect("refused");
+        assert!(reason.contains("in-progress extended boluses"), "{}", reason);
+    }
+
+    #[test]
+    fn bolus_needs_a_reading_above_the_low_threshold() {
+        let patient = patient();
+        assert!(check_bolus_safety(&patient, None, 0.0, 0.0, 1.0, GlucoseUnit::MgDl).is_some());
+        assert!(check_bolus_safety(&patient, Some(65.0), 0.0, 0.0, 1.0, GlucoseUnit::MgDl).is_some());
+        assert_eq!(check_bolus_safety(&patient, Some(70.0), 0.0, 0.0, 1.0, GlucoseUnit::MgDl), None);
+    }
+
+    #[test]
+    fn low_glucose_refusal_is_in_the_requested_unit() {
+        let reason = check_bolus_safety(&patient(), Some(54.0), 0.0, 0.0, 1.0, GlucoseUnit::MmolL).expect("refused");
+        assert_eq!(reason, "Latest glucose 3.0 mmol/L is below the low glucose threshold of 3.9 mmol/L.");
+    }
+
+    #[test]
+    fn action_curves_decay_from_one_to_zero() {
+        for curve in [InsulinActionCurve::from_settings("exponential", 240.0), InsulinActionCurve::from_settings("bilinear", 240.0)] {
+            assert_eq!(curve.fraction_remaining(0.0), 1.0);
+            assert_eq!(curve.fraction_remaining(240.0), 0.0);
+            let mut previous = 1.0;
+            for minutes in (10..240).step_by(10) {
+                let remaining = curve.fraction_remaining(minutes as f32);
+                assert!(remaining <= previous && remaining >= 0.0, "{} at {} min", remaining, minutes);
+                previous = remaining;
+            }
+        }
+    }
+
+    #[test]
+    fn insulin_on_board_ignores_expired_doses() {
+        let curve = InsulinActionCurve::from_settings("exponential", 240.0);
+        assert_eq!(calculate_insulin_on_board(&[(0.0, 2.0), (300.0, 3.0)], &curve), 2.0);
+        let partly_used = calculate_insulin_on_board(&[(120.0, 4.0)], &curve);
+        assert!(partly_used > 0.0 && partly_used < 4.0);
+    }
+
+    #[test]
+    fn only_boluses_count_toward_bolus_insulin_on_board() {
+        assert!(is_bolus_action("Bolus"));
+        assert!(is_bolus_action("BOLUS"));
+        assert!(is_bolus_action(EXTENDED_BOLUS_START_ACTION));
+        assert!(is_bolus_action(DUAL_WAVE_BOLUS_START_ACTION));
+        assert!(!is_bolus_action("Basal"));
+    }
+
+    #[test]
+    fn wizard_adds_carb_and_correction_doses_less_iob() {
+        // 60 g / 10 + (150 - 100) / 50 - 1 = 6 units
+        let s = suggestion(calculate_bolus_suggestion(&patient(), 60.0, 150.0, 1.0, 0.0));
+        assert!((s.suggested_units - 6.0).abs() < 1e-4);
+        assert!(!s.capped);
+    }
+
+    #[test]
+    fn wizard_is_capped_at_what_max_dosage_still_allows() {
+        // 6 + 1 - 2 = 5 units uncapped, but only 10 - 2 - 4 = 4 units are left under the maximum
+        let patient = patient();
+        let s = suggestion(calculate_bolus_suggestion(&patient, 60.0, 150.0, 2.0, 4.0));
+        assert!((s.suggested_units - 4.0).abs() < 1e-4);
+        assert!(s.capped);
+        assert_eq!(check_bolus_safety(&patient, Some(150.0), 2.0, 4.0, s.suggested_units, GlucoseUnit::MgDl), None);
+    }
+
+    #[test]
+    fn wizard_needs_prescribed_settings() {
+        let mut patient = patient();
+        patient.carb_ratio = None;
+        assert!(matches!(calculate_bolus_suggestion(&patient, 60.0, 150.0, 0.0, 0.0), BolusWizardOutcome::Unavailable(_)));
+    }
+}
@@ -5,12 +5,39 @@ mod utils;
 mod access_control;
 mod input_validation;
 mod insulin;
+mod basal;
+mod config;
+mod controller;
+mod alerts;
+mod suspend;
+mod cgm;
+mod import;
+mod trend;
+mod calibration;
+mod signal;
+mod forecast;
+mod units;
+mod filter;
+mod ingest;
+mod sensor;
+mod escalation;
+mod suppression;
+mod notify;
+mod missed_bolus;
 use crate::db::db_utils;
 use crate::db::initialize;
 use crate::menus::{login_menu,admin_menu,patient_menu,
                 caretaker_menu,clinician_menu,home_menu,signup_menu};
 mod session;
 use crate::session::SessionManager;
+use crate::controller::ControllerSettings;
+use crate::cgm::CgmSimulatorSettings;
+use crate::signal::SignalSettings;
+use crate::ingest::IngestSettings;
+use crate::sensor::SensorSettings;
+use crate::escalation::EscalationSettings;
+use crate::notify::NotifySettings;
+use crate::missed_bolus::MissedBolusSettings;
 
 
 
@@ -27,9 +54,45 @@ let logo = r#"
                                                         
 println!("{}", logo);
 
+    // load optional settings from a .env file
+    dotenv::dotenv().ok();
+
     // Initialize the database connection
     let db_connection = initialize::establish_connection().unwrap();
 
+    // `glucoguard import <glucose|insulin> <csv_path>` loads a pump_simm CSV file and exits
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() == 4 && args[1] == "import" {
+        import::run_import_command(&db_connection, &args[2], &args[3]);
+        return;
+    }
+    // `glucoguard register-device <patient_id> <device_id>` creates a key for the streaming ingest service
+    if args.len() == 4 && args[1] == "register-device" {
+        ingest::run_register_device_command(&db_connection, &args[2], &args[3]);
+        return;
+    }
+
+    // start the closed-loop basal controller and low-glucose suspend checks in the background
+    controller::run_controller(config::DB_PATH, ControllerSettings::from_env());
+    // end temp basals whose duration has run out
+    basal::run_temp_basal_expiry(config::DB_PATH);
+    // finish extended boluses whose delivery window has ended
+    insulin::run_extended_bolus_monitor(config::DB_PATH);
+    // generate virtual-patient CGM readings when GLUCOGUARD_CGM_SIM_ENABLED is set
+    cgm::run_cgm_simulator(config::DB_PATH, CgmSimulatorSettings::from_env());
+    // raise signal loss alerts when CGM readings stop arriving
+    signal::run_signal_monitor(config::DB_PATH, SignalSettings::from_env());
+    // remind patients to replace CGM sensors that are about to expire
+    sensor::run_sensor_monitor(config::DB_PATH, SensorSettings::from_env());
+    // accept streamed readings from CGM devices when GLUCOGUARD_INGEST_ENABLED is set
+    ingest::run_ingest_server(config::DB_PATH, IngestSettings::from_env());
+    // escalate unacknowledged urgent low alerts to caretakers and clinicians
+    escalation::run_escalation_scheduler(config::DB_PATH, EscalationSettings::from_env());
+    // deliver alerts on each user's chosen channels (file, webhook, email)
+    notify::run_notification_dispatcher(config::DB_PATH, NotifySettings::from_env());
+    // flag sharp post-meal rises that had no bolus
+    missed_bolus::run_missed_bolus_monitor(config::DB_PATH, MissedBolusSettings::from_env());
+
 
    // db_utils::print_table_info(&db_connection.unwrap()).unwrap();
 
@@ -47,7 +110,7 @@ println!("{}", logo);
                     let role = access_control::Role::new(&login_result.role, &login_result.user_id);
                     //create session manager
                     let session_manager = SessionManager::new();
-                    session_manager.run_cleanup("./data/database.db");
+                    session_manager.run_cleanup(config::DB_PATH);
 
                     match role.name.as_str() {
                         "admin" => admin_menu::show_admin_menu(&db_connection, &role, &login_result.session_id),
@@ -1,7 +1,7 @@
 use crate::utils;
-use crate::access_control::Role;
+use crate::access_control::{Role, Permission};
 use crate::db::queries;
-use crate::menus::menu_utils::get_new_account_credentials;
+use crate::menus::menu_utils::{get_new_account_credentials, manage_alert_inbox};
 use crate::session::SessionManager;
 use rusqlite::Connection;
 
@@ -35,6 +35,7 @@ pub fn show_admin_menu(conn: &rusqlite::Connection,role:&Role,session_id: &str)
         println!("1. Create Clinician Account");
         println!("2. View Clinician Account List");
         println!("3. Logout");
+        println!("4. View and acknowledge alerts");
         print!("Enter your choice: ");
         let choice = utils::get_user_choice();
 
@@ -78,6 +79,18 @@ pub fn show_admin_menu(conn: &rusqlite::Connection,role:&Role,session_id: &str)
                 }
                 return;
             },
+            4 => {
+                if !session_manager.check_permissions(conn, session_id, role, Permission::ViewAlerts) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                // administrators oversee the alerts of every patient
+                match queries::get_all_patient_ids(conn) {
+                    Ok(patient_ids) => manage_alert_inbox(conn, &patient_ids, &session.user_id),
+                    Err(e) => println!("Failed to fetch patients: {}", e),
+                }
+            },
+
             _ => println!("Invalid choice"),
         }
     }
@@ -1,5 +1,7 @@
 use crate::utils;
-use crate::access_control::Role; 
+use crate::menus::menu_utils::{configure_basal_schedule, prompt_bolus_dose, choose_glucose_unit, manage_alert_inbox,
+                        display_open_alert_notice};
+use crate::access_control::{Role, Permission};
 use crate::session::SessionManager;
 use rusqlite::Connection;
 use crate::insulin::{display_patient_glucose_readings,
@@ -7,8 +9,15 @@ use crate::insulin::{display_patient_glucose_readings,
         get_patient_insulin_data,
         get_one_patient_by_caretaker_id,
         display_patient_complete_glucose_insulin_history,
-        show_patient_current_basal_bolus_limits
+        show_patient_current_basal_bolus_limits,
+        request_caretaker_bolus_dose,
+        get_next_caretaker_bolus_time,
+        BolusOutcome,
+        CARETAKER_BOLUS_LOCKOUT_HOURS
 };
+use crate::units::{get_user_glucose_unit, GlucoseUnit};
+use crate::utils::format_local_time;
+use crate::escalation::display_escalation_notice;
 
 
 pub fn show_caretaker_menu(conn: &rusqlite::Connection,role:&Role,session_id: &str) {
@@ -40,6 +49,14 @@ pub fn show_caretaker_menu(conn: &rusqlite::Connection,role:&Role,session_id: &s
             return;
         }
 
+        // glucose values are shown and entered in the user's preferred unit
+        let unit = get_user_glucose_unit(conn, &session.user_id).unwrap_or_default();
+
+        // get patient being treated by caretaker
+        let current_patient_id:String =  get_one_patient_by_caretaker_id(&conn,&session.user_id ).expect("REASON");
+
+        display_escalation_notice(conn, &session.user_id);
+        display_open_alert_notice(conn, std::slice::from_ref(&current_patient_id), &session.user_id);
         println!("=== CareTaker Menu ===");
 
         println!("1) View most recent glucose readings.");
@@ -48,18 +65,16 @@ pub fn show_caretaker_menu(conn: &rusqlite::Connection,role:&Role,session_id: &s
         println!("4) Configure basal insulin dose time.");
         println!("5) View patient insulin history.");
         println!("6. Logout");
+        println!("7) Change glucose display units.");
+        println!("8) View and acknowledge alerts.");
         println!("Enter your choice: ");
         let choice = utils::get_user_choice();
 
-
-        // get patient being treated by caretaker
-        let current_patient_id:String =  get_one_patient_by_caretaker_id(&conn,&session.user_id ).expect("REASON");
-
         match choice {
 
             1 => {
                 //View the patient’s most recent glucose readings.
-                display_patient_glucose_readings(&conn, &current_patient_id, true);
+                display_patient_glucose_readings(&conn, &current_patient_id, true, unit);
             },
             2 => {
                 // View the patient’s current basal rate and bolus insulin options.
@@ -70,18 +85,34 @@ pub fn show_caretaker_menu(conn: &rusqlite::Connection,role:&Role,session_id: &s
                 // – Caretakers cannot request more than the prescribed maximum dose or violate safety limits.
                 // – Caretakers cannot request more than one dose per every four hours (corresponding to
                 // three meals a day).
-
+                if current_patient_id.is_empty(){
+                    println!("Cannot perform this action because no patient is assigned.");
+                    continue;
+                }
+                if !session_manager.check_permissions(conn, session_id, role, Permission::AddGlucose) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                handle_caretaker_bolus_request(conn, &current_patient_id, &session.user_id, unit);
             }, 
             4 => {
                 //Configure basal insulin dose time.
                 // – Caretakers can adjust the basal insulin dose, which will be effective within 24 hours, so as
                 // not to overlap a previous dose.
                 // – Caretakers cannot request more than the prescribed maximum dose or violate safety limits.
-
+                if current_patient_id.is_empty(){
+                    println!("Cannot perform this action because no patient is assigned.");
+                    continue;
+                }
+                if !session_manager.check_permissions(conn, session_id, role, Permission::AddGlucose) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                configure_basal_schedule(conn, &current_patient_id, &session.user_id);
             }, 
             5 => {
                 //Review historical insulin delivery and glucose data.
-                display_patient_complete_glucose_insulin_history(conn,&current_patient_id);
+                display_patient_complete_glucose_insulin_history(conn,&current_patient_id, unit);
             }, 
             6 => {
                 println!("Logging out...");
@@ -92,8 +123,51 @@ pub fn show_caretaker_menu(conn: &rusqlite::Connection,role:&Role,session_id: &s
                 }
                 return;
             },
+            7 => {
+                choose_glucose_unit(conn, &session.user_id, unit);
+            },
+            8 => {
+                if !session_manager.check_permissions(conn, session_id, role, Permission::ViewAlerts) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                manage_alert_inbox(conn, std::slice::from_ref(&current_patient_id), &session.user_id);
+            },
             _ => println!("Invalid choice"),
         }
     }
 }
 
+
+// prompts the caretaker for a bolus amount, enforcing the caretaker lockout window before asking
+fn handle_caretaker_bolus_request(conn: &rusqlite::Connection, patient_id: &str, caretaker_id: &str, unit: GlucoseUnit) {
+    // refuse up front so the caretaker is not asked for a dose that cannot be given
+    match get_next_caretaker_bolus_time(conn, patient_id) {
+        Ok(Some(next_allowed)) => {
+            println!("Bolus refused: a caretaker dose was already given in the last {} hours. Next dose allowed at {}.",
+                CARETAKER_BOLUS_LOCKOUT_HOURS, format_local_time(&next_allowed));
+            return;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Error checking caretaker dose history: {}", e);
+            return;
+        }
+    }
+
+    let units = match prompt_bolus_dose(conn, patient_id, unit) {
+        Some(units) => units,
+        None => return,
+    };
+
+    match request_caretaker_bolus_dose(conn, patient_id, units, caretaker_id, unit) {
+        Ok(BolusOutcome::Delivered(units)) => {
+            println!("Bolus of {:.2} units delivered and logged.", units);
+            if let Ok(Some(next_allowed)) = get_next_caretaker_bolus_time(conn, patient_id) {
+                println!("Next caretaker dose allowed at {}.", format_local_time(&next_allowed));
+            }
+        }
+        Ok(BolusOutcome::Refused(reason)) => println!("Bolus refused: {}", reason),
+        Err(e) => eprintln!("Error requesting bolus: {}", e),
+    }
+}
@@ -1,6 +1,7 @@
 use crate::utils;
-use crate::menus::menu_utils;
-use crate::access_control::Role;
+use crate::menus::menu_utils::{self, manage_temp_basal, choose_glucose_unit, manage_alert_inbox,
+                        display_open_alert_notice};
+use crate::access_control::{Role, Permission};
 use crate::auth::{generate_one_time_code};
 use crate::db::queries::{insert_activation_code,
                         insert_patient_account_details_in_db,
@@ -9,8 +10,13 @@ use rusqlite::{Connection,Result};
 use crate::session::SessionManager;
 use crate::insulin::{get_one_patient_by_clinician_id,display_patient_complete_glucose_insulin_history,
                         get_patient_data_from_patient_table};
+use crate::calibration::display_sensor_accuracy;
+use crate::missed_bolus::display_missed_bolus_report;
 use std::io::{self, Write};
-use crate::input_validation::{read_non_empty_input,read_valid_date_dd_mm_yyyy,read_valid_float};
+use crate::input_validation::{read_non_empty_input,read_valid_date_dd_mm_yyyy,read_valid_float,read_valid_int,read_valid_glucose};
+use crate::units::{get_user_glucose_unit, GlucoseUnit};
+use crate::escalation::{display_escalation_notice, display_escalation_policy, set_escalation_policy,
+                        EscalationPolicy, EscalationSettings};
 
 //Takes in db connection and role struct:
     // Role{
@@ -46,6 +52,14 @@ pub fn show_clinician_menu(conn: &rusqlite::Connection,role: &Role,session_id: &
             return;
         }
 
+        // glucose values are shown and entered in the user's preferred unit
+        let unit = get_user_glucose_unit(conn, &session.user_id).unwrap_or_default();
+
+        // alerts of every patient under the clinician's care
+        let patient_ids = get_clinician_patient_ids(conn, &session.user_id, session_id);
+
+        display_escalation_notice(conn, &session.user_id);
+        display_open_alert_notice(conn, &patient_ids, &session.user_id);
         println!("=== Clinician Menu ===");
         println!("1. View patient insulin history.");
         println!("2. Edit patient insulin parameters.");// 
@@ -53,6 +67,12 @@ pub fn show_clinician_menu(conn: &rusqlite::Connection,role: &Role,session_id: &
         println!("3. View patient info");
         println!("4. Create Patient Account");
         println!("5. Logout");
+        println!("6. Set or cancel temporary basal");
+        println!("7. View sensor accuracy");
+        println!("8. Change glucose display units");
+        println!("9. View and acknowledge patient alerts");
+        println!("10. Alert escalation policy");
+        println!("11. Missed meal bolus report");
         println!("Enter your choice: ");
 
         let choice = utils::get_user_choice();
@@ -71,7 +91,7 @@ pub fn show_clinician_menu(conn: &rusqlite::Connection,role: &Role,session_id: &
 
                     //View logs of all insulin deliveries and glucose readings.
                     // request_insulin_flow(conn,&session.user_id);
-                    display_patient_complete_glucose_insulin_history(conn,&current_patient_id);
+                    display_patient_complete_glucose_insulin_history(conn,&current_patient_id, unit);
                 }, 
                 2 =>{
                     // requres that we have a valid patient_id for clinician 
@@ -90,6 +110,22 @@ pub fn show_clinician_menu(conn: &rusqlite::Connection,role: &Role,session_id: &
                                 Err(e) => eprintln!("Error updating patient limits"),
                             }
                         }
+
+                        // insulin action curve used for insulin-on-board calculations
+                        let (iob_model, action_minutes) = prompt_insulin_action_curve();
+                        match update_patient_action_curve(conn, &current_patient_id, &iob_model, action_minutes) {
+                            Ok(rows_updated) if rows_updated > 0 => println!("Insulin action curve updated successfully."),
+                            Ok(_) => println!("No patient found with that ID."),
+                            Err(e) => eprintln!("Error updating insulin action curve: {}", e),
+                        }
+
+                        // bolus wizard prescription
+                        let (carb_ratio, correction_factor, target_glucose) = prompt_bolus_wizard_settings(unit);
+                        match update_patient_bolus_wizard_settings(conn, &current_patient_id, carb_ratio, correction_factor, target_glucose) {
+                            Ok(rows_updated) if rows_updated > 0 => println!("Bolus wizard settings updated successfully."),
+                            Ok(_) => println!("No patient found with that ID."),
+                            Err(e) => eprintln!("Error updating bolus wizard settings: {}", e),
+                        }
                 },
                 // 3=>{
                 //     // requres that we have a valid patient_id for clinician 
@@ -108,11 +144,11 @@ pub fn show_clinician_menu(conn: &rusqlite::Connection,role: &Role,session_id: &
                 // },
                 3=>{
                     // view patient info 
-                    show_patient_data(conn, &current_patient_id)
+                    show_patient_data(conn, &current_patient_id, unit)
                 },
                 4=>{
                     // get patient data and create patient account 
-                    handle_patient_account_creation(&conn,role, &session_id);
+                    handle_patient_account_creation(&conn,role, &session_id, unit);
                 },
                 5 => {
                     println!("Logging out...");
@@ -123,6 +159,58 @@ pub fn show_clinician_menu(conn: &rusqlite::Connection,role: &Role,session_id: &
                     }
                     return;
                 },
+                6 => {
+                    // requres that we have a valid patient_id for clinician 
+                    if current_patient_id.is_empty(){
+                        println!("Cannot perform this action because no patient is assigned.");
+                        continue;
+                    }
+                    if !session_manager.check_permissions(conn, session_id, role, Permission::EditPatientData) {
+                        println!("Access denied: insufficient permissions.");
+                        continue;
+                    }
+                    manage_temp_basal(conn, &current_patient_id, &session.user_id);
+                },
+                7 => {
+                    // requres that we have a valid patient_id for clinician 
+                    if current_patient_id.is_empty(){
+                        println!("Cannot perform this action because no patient is assigned.");
+                        continue;
+                    }
+                    // fingersticks against the CGM: MARD and Clarke error grid, to spot a drifting sensor
+                    display_sensor_accuracy(conn, &current_patient_id, unit);
+                },
+                8 => {
+                    choose_glucose_unit(conn, &session.user_id, unit);
+                },
+                9 => {
+                    if !session_manager.check_permissions(conn, session_id, role, Permission::ViewAlerts) {
+                        println!("Access denied: insufficient permissions.");
+                        continue;
+                    }
+                    // the inbox covers every patient under the clinician's care
+                    manage_alert_inbox(conn, &patient_ids, &session.user_id);
+                },
+                10 => {
+                    // requres that we have a valid patient_id for clinician 
+                    if current_patient_id.is_empty(){
+                        println!("Cannot perform this action because no patient is assigned.");
+                        continue;
+                    }
+                    if !session_manager.check_permissions(conn, session_id, role, Permission::EditPatientData) {
+                        println!("Access denied: insufficient permissions.");
+                        continue;
+                    }
+                    configure_escalation_policy(conn, &current_patient_id, &session.user_id);
+                },
+                11 => {
+                    if !session_manager.check_permissions(conn, session_id, role, Permission::ViewPatient) {
+                        println!("Access denied: insufficient permissions.");
+                        continue;
+                    }
+                    // how often each patient under care skipped a meal bolus
+                    display_missed_bolus_report(conn, &patient_ids);
+                },
                 _ => println!("Invalid choice"),
             }
         }
@@ -130,8 +218,8 @@ pub fn show_clinician_menu(conn: &rusqlite::Connection,role: &Role,session_id: &
 
 }
 
-fn handle_patient_account_creation(conn:&rusqlite::Connection, role:&Role, session_id: &str){
-    let patient = menu_utils::get_new_patient_input(role.id.clone());
+fn handle_patient_account_creation(conn:&rusqlite::Connection, role:&Role, session_id: &str, unit: GlucoseUnit){
+    let patient = menu_utils::get_new_patient_input(role.id.clone(), unit);
 
     //insert patient data in db and check if successfully inserted
     match insert_patient_account_details_in_db(&conn, &patient, &session_id){
@@ -159,6 +247,17 @@ fn handle_patient_account_creation(conn:&rusqlite::Connection, role:&Role, sessi
     }
 }
 
+// ids of the patients under the clinician's care (empty when they cannot be read)
+fn get_clinician_patient_ids(conn: &Connection, clinician_id: &String, session_id: &str) -> Vec<String> {
+    match get_patients_by_clinician_id(conn, clinician_id, session_id) {
+        Ok(patients) => patients.into_iter().map(|p| p.patient_id).collect(),
+        Err(e) => {
+            eprintln!("Error retrieving patients: {}", e);
+            Vec::new()
+        }
+    }
+}
+
 fn show_patients_menu(conn: &Connection, clinician_id: &String, session_id: &str) {
     match get_patients_by_clinician_id(conn, clinician_id, &session_id) {
         Ok(patients) => {
@@ -204,6 +303,55 @@ pub fn update_patient_bolus_basal(conn: &Connection,patient_id: &str,bolus: f32,
     )
 }
 
+/// Prompts for the insulin action curve model and duration of insulin action.
+/// Returns a tuple: (iob_model, insulin_action_minutes)
+pub fn prompt_insulin_action_curve() -> (String, f32) {
+    println!("Insulin action curve: 1) Exponential  2) Bilinear");
+    let model = match read_valid_int("Choose curve (1–2): ", 1, 2) {
+        2 => "bilinear",
+        _ => "exponential",
+    };
+    let action_minutes = read_valid_float("Duration of insulin action in minutes (120–480): ", 120.0, 480.0);
+
+    (model.to_string(), action_minutes)
+}
+
+///// Updates the insulin action curve used for a patient's insulin-on-board.
+pub fn update_patient_action_curve(conn: &Connection,patient_id: &str,iob_model: &str,action_minutes: f32,
+) -> Result<usize> {
+    conn.execute(
+        "UPDATE patients
+         SET iob_model = ?1,
+             insulin_action_minutes = ?2
+         WHERE patient_id = ?3",
+        rusqlite::params![iob_model, action_minutes, patient_id],
+    )
+}
+
+/// Prompts for the bolus wizard prescription.
+/// Returns a tuple: (carb_ratio, correction_factor, target_glucose)
+/// Correction factor and target are entered in `unit` and returned in mg/dL.
+pub fn prompt_bolus_wizard_settings(unit: GlucoseUnit) -> (f32, f32, f32) {
+    let carb_ratio = read_valid_float("Insulin-to-Carb Ratio (grams per unit, 1–150): ", 1.0, 150.0);
+    let correction_factor = read_valid_glucose("Correction Factor (per unit)", 1.0, 400.0, unit);
+    let target_glucose = read_valid_glucose("Target Glucose", 70.0, 180.0, unit);
+
+    (carb_ratio, correction_factor, target_glucose)
+}
+
+///// Updates the bolus wizard prescription for a given patient.
+pub fn update_patient_bolus_wizard_settings(conn: &Connection,patient_id: &str,carb_ratio: f32,correction_factor: f32,target_glucose: f32,
+) -> Result<usize> {
+    conn.execute(
+        "UPDATE patients
+         SET carb_ratio = ?1,
+             correction_factor = ?2,
+             target_glucose = ?3
+         WHERE patient_id = ?4",
+        rusqlite::params![carb_ratio, correction_factor, target_glucose, patient_id],
+    )
+}
+
 pub fn update_patient_max_min_glucose(conn: &Connection,patient_id: &str) -> Result<usize> {
 
     let low_glucose_threshold = read_valid_float("Low Glucose Threshold (0–70): ", 0.0, 69.0);
@@ -219,14 +367,20 @@ pub fn update_patient_max_min_glucose(conn: &Connection,patient_id: &str) -> Res
 }
 
 
-fn show_patient_data(conn: &rusqlite::Connection, patient_id: &str) {
+fn show_patient_data(conn: &rusqlite::Connection, patient_id: &str, unit: GlucoseUnit) {
     match get_patient_data_from_patient_table(conn, patient_id) {
         Ok(Some(patient)) => {
             println!("\n--------Patient Info--------");
             println!("Name: {} {}", patient.first_name, patient.last_name);
             println!("Max Dosage: {:.2} units", patient.max_dosage);
-            println!("Glucose Thresholds: low {:.1}, high {:.1} \n",
-                     patient.low_glucose_threshold, patient.high_glucose_threshold);
+            println!("Glucose Thresholds: low {}, high {}",
+                     unit.format(patient.low_glucose_threshold), unit.format(patient.high_glucose_threshold));
+            match (patient.carb_ratio, patient.correction_factor, patient.target_glucose) {
+                (Some(cr), Some(cf), Some(target)) => println!(
+                    "Bolus wizard: carb ratio {:.1} g/unit, correction {}/unit, target {} \n",
+                    cr, unit.format(cf), unit.format(target)),
+                _ => println!("Bolus wizard: not prescribed \n"),
+            }
         }
         Ok(None) => {
             println!("No patient data found ");
@@ -236,3 +390,35 @@ fn show_patient_data(conn: &rusqlite::Connection, patient_id: &str) {
         }
     }
 }
+
+// shows and updates how long an urgent low alert waits for acknowledgement before it is escalated
+fn configure_escalation_policy(conn: &Connection, patient_id: &str, clinician_id: &str) {
+    let settings = EscalationSettings::from_env();
+    display_escalation_policy(conn, patient_id, &settings);
+
+    println!("1. Set escalation timers");
+    println!("2. Turn escalation off");
+    println!("3. Back");
+    println!("Enter your choice: ");
+    let policy = match utils::get_user_choice() {
+        1 => {
+            let caretaker_minutes = read_valid_int("Minutes before escalating to the caretaker (1–240): ", 1, 240);
+            let clinician_minutes = read_valid_int(
+                &format!("Minutes before escalating to the clinician ({}–480): ", caretaker_minutes),
+                caretaker_minutes,
+                480,
+            );
+            EscalationPolicy { enabled: true, caretaker_minutes: caretaker_minutes as i64, clinician_minutes: clinician_minutes as i64 }
+        }
+        2 => EscalationPolicy { enabled: false, ..settings.default_policy() },
+        3 => return,
+        _ => {
+            println!("Invalid choice");
+            return;
+        }
+    };
+    match set_escalation_policy(conn, patient_id, &policy, clinician_id) {
+        Ok(_) => println!("Escalation policy updated."),
+        Err(e) => eprintln!("Error saving escalation policy: {}", e),
+    }
+}
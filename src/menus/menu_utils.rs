@@ -2,7 +2,23 @@
 use std::io::{self, Write};
 use uuid::Uuid;
 use crate::db::models::{Patient};
-use crate::input_validation::{read_non_empty_input,read_valid_date_dd_mm_yyyy,read_valid_float};
+use crate::input_validation::{read_non_empty_input,read_valid_date_dd_mm_yyyy,read_valid_float,
+                            read_valid_int,read_valid_time_hh_mm,read_valid_glucose};
+use crate::basal::{BasalSegment, BasalScheduleOutcome, BASAL_CHANGE_DELAY_HOURS,
+                    display_basal_schedules, submit_basal_schedule,
+                    TempBasalMode, TempBasalOutcome, MAX_TEMP_BASAL_PERCENT,
+                    get_active_temp_basal, start_temp_basal, cancel_temp_basal};
+use crate::insulin::{show_patient_current_basal_bolus_limits, insert_meal_log, suggest_meal_bolus,
+                    display_bolus_suggestion, BolusWizardOutcome};
+use crate::units::{GlucoseUnit, get_user_glucose_unit, update_user_glucose_unit};
+use crate::alerts::{AlertFilter, AlertStatusFilter, get_alerts, get_alert_types, acknowledge_alert};
+use crate::escalation::{get_alert_escalations, describe_escalation};
+use crate::suppression::{QuietHours, SnoozeSettings, snooze_alert_type, get_active_snoozes,
+                        get_user_quiet_hours, set_user_quiet_hours, should_notify_user};
+use crate::notify::{NotificationChannel, get_channel_preferences, set_channel_preference, get_alert_deliveries,
+                    parse_http_url};
+use crate::utils::{self, format_local_time};
+use chrono::NaiveTime;
 
 /// Prompts the user to create a new account (username + password)
 pub fn get_new_account_credentials() -> io::Result<(String, String)> {
@@ -43,7 +59,7 @@ pub fn get_new_account_credentials() -> io::Result<(String, String)> {
 
 
 // collect input to create a patient 
-pub fn get_new_patient_input(clinician_id: String) -> Patient {
+pub fn get_new_patient_input(clinician_id: String, unit: GlucoseUnit) -> Patient {
     loop {
         println!("\n Enter new patient details:");
         println!("-----------------------------------");
@@ -54,8 +70,12 @@ pub fn get_new_patient_input(clinician_id: String) -> Patient {
         let basal_rate = read_valid_float("Basal Rate (0–100): ", 0.0, 100.0);
         let bolus_rate = read_valid_float("Bolus Rate (0–100): ", 0.0, 100.0);
         let max_dosage = read_valid_float("Max Dosage (0–200): ", 0.0, 200.0);
-        let low_glucose_threshold = read_valid_float("Low Glucose Threshold (0–100): ", 0.0, 100.0);
-        let high_glucose_threshold = read_valid_float("High Glucose Threshold (100–1000): ", 100.0, 1000.0);
+        // glucose values are entered in the clinician's display unit and stored in mg/dL
+        let low_glucose_threshold = read_valid_glucose("Low Glucose Threshold", 0.0, 100.0, unit);
+        let high_glucose_threshold = read_valid_glucose("High Glucose Threshold", 100.0, 1000.0, unit);
+        let carb_ratio = read_valid_float("Insulin-to-Carb Ratio (grams per unit, 1–150): ", 1.0, 150.0);
+        let correction_factor = read_valid_glucose("Correction Factor (per unit)", 1.0, 400.0, unit);
+        let target_glucose = read_valid_glucose("Target Glucose", 70.0, 180.0, unit);
 
         let patient = Patient {
             patient_id: Uuid::new_v4().to_string(),
@@ -67,6 +87,9 @@ pub fn get_new_patient_input(clinician_id: String) -> Patient {
             max_dosage,
             low_glucose_threshold,
             high_glucose_threshold,
+            carb_ratio: Some(carb_ratio),
+            correction_factor: Some(correction_factor),
+            target_glucose: Some(target_glucose),
             clinician_id: clinician_id.clone(),
             caretaker_id: String::new(), // assigned later
         };
@@ -76,3 +99,462 @@ pub fn get_new_patient_input(clinician_id: String) -> Patient {
     }
 }
 
+
+
+// basal schedule menu shared by patients and caretakers
+pub fn configure_basal_schedule(conn: &rusqlite::Connection, patient_id: &str, user_id: &str) {
+    loop {
+        println!("\n--- Basal Schedule ---");
+        println!("1. View active and pending basal schedules");
+        println!("2. Submit a new basal schedule");
+        println!("3. Back");
+        println!("Enter your choice: ");
+
+        match utils::get_user_choice() {
+            1 => display_basal_schedules(conn, patient_id),
+            2 => {
+                show_patient_current_basal_bolus_limits(conn, &patient_id.to_string());
+                let segments = get_new_basal_segments();
+
+                match submit_basal_schedule(conn, patient_id, &segments, user_id) {
+                    Ok(BasalScheduleOutcome::Scheduled(effective_at)) => println!(
+                        "Basal schedule saved. It takes effect in {} hours, at {}.",
+                        BASAL_CHANGE_DELAY_HOURS,
+                        format_local_time(&effective_at)
+                    ),
+                    Ok(BasalScheduleOutcome::Refused(reason)) => println!("Basal schedule refused: {}", reason),
+                    Err(e) => eprintln!("Error saving basal schedule: {}", e),
+                }
+            }
+            3 => return,
+            _ => println!("Invalid choice"),
+        }
+    }
+}
+
+// collect time-of-day segments for a basal schedule; the first segment always starts at 00:00
+fn get_new_basal_segments() -> Vec<BasalSegment> {
+    let count = read_valid_int("Number of segments in the day (1–24): ", 1, 24);
+    let mut segments = Vec::new();
+
+    for index in 0..count {
+        let start_time = if index == 0 {
+            println!("Segment 1 starts at 00:00");
+            NaiveTime::MIN
+        } else {
+            read_valid_time_hh_mm(&format!("Segment {} start time (HH:MM): ", index + 1))
+        };
+        let rate = read_valid_float(&format!("Segment {} rate (units/hour, 0–100): ", index + 1), 0.0, 100.0);
+        segments.push(BasalSegment { start_time, rate });
+    }
+
+    segments
+}
+
+// asks how the bolus amount should be chosen and returns the dose to request, if any
+pub fn prompt_bolus_dose(conn: &rusqlite::Connection, patient_id: &str, unit: GlucoseUnit) -> Option<f32> {
+    show_patient_current_basal_bolus_limits(conn, &patient_id.to_string());
+    println!("1. Enter bolus dose manually");
+    println!("2. Use bolus wizard (log meal carbs)");
+    println!("Enter your choice: ");
+
+    match utils::get_user_choice() {
+        1 => Some(read_valid_float("Enter bolus dose (units): ", 0.0, 200.0)),
+        2 => run_bolus_wizard(conn, patient_id, unit),
+        _ => {
+            println!("Invalid choice");
+            None
+        }
+    }
+}
+
+// logs a meal in meal_logs and walks through the bolus wizard; returns the dose the user accepted
+fn run_bolus_wizard(conn: &rusqlite::Connection, patient_id: &str, unit: GlucoseUnit) -> Option<f32> {
+    let carbs = read_valid_float("Carbohydrates in this meal (grams, 0–300): ", 0.0, 300.0);
+    if let Err(e) = insert_meal_log(conn, patient_id, carbs) {
+        eprintln!("Error logging meal: {}", e);
+        return None;
+    }
+
+    match suggest_meal_bolus(conn, patient_id) {
+        Ok(BolusWizardOutcome::Suggestion(suggestion)) => {
+            display_bolus_suggestion(&suggestion, unit);
+            if suggestion.suggested_units <= 0.0 {
+                println!("No bolus is needed right now.");
+                return None;
+            }
+
+            let answer = read_non_empty_input("Deliver the suggested bolus? (y/n): ");
+            if answer.eq_ignore_ascii_case("y") {
+                Some(suggestion.suggested_units)
+            } else {
+                println!("Bolus not delivered.");
+                None
+            }
+        }
+        Ok(BolusWizardOutcome::Unavailable(reason)) => {
+            println!("Bolus wizard unavailable: {}", reason);
+            None
+        }
+        Err(e) => {
+            eprintln!("Error running bolus wizard: {}", e);
+            None
+        }
+    }
+}
+
+// temp basal menu shared by patients and clinicians
+pub fn manage_temp_basal(conn: &rusqlite::Connection, patient_id: &str, user_id: &str) {
+    loop {
+        println!("\n--- Temporary Basal ---");
+        println!("1. View active temp basal");
+        println!("2. Start temp basal as a percentage of the scheduled rate");
+        println!("3. Start temp basal at an absolute rate");
+        println!("4. Cancel active temp basal");
+        println!("5. Back");
+        println!("Enter your choice: ");
+
+        let mode = match utils::get_user_choice() {
+            1 => {
+                match get_active_temp_basal(conn, patient_id) {
+                    Ok(Some(temp)) => println!("Active temp basal: {}", temp.describe()),
+                    Ok(None) => println!("No temp basal is running; the scheduled basal applies."),
+                    Err(e) => eprintln!("Error fetching temp basal: {}", e),
+                }
+                continue;
+            }
+            2 => TempBasalMode::Percent(read_valid_float(
+                &format!("Percentage of scheduled rate (0–{:.0}): ", MAX_TEMP_BASAL_PERCENT),
+                0.0,
+                MAX_TEMP_BASAL_PERCENT,
+            )),
+            3 => TempBasalMode::Absolute(read_valid_float("Rate (units/hour, 0–100): ", 0.0, 100.0)),
+            4 => {
+                match cancel_temp_basal(conn, patient_id, user_id) {
+                    Ok(true) => println!("Temp basal cancelled. Scheduled basal resumed."),
+                    Ok(false) => println!("No temp basal is running."),
+                    Err(e) => eprintln!("Error cancelling temp basal: {}", e),
+                }
+                continue;
+            }
+            5 => return,
+            _ => {
+                println!("Invalid choice");
+                continue;
+            }
+        };
+
+        let duration_minutes = read_valid_int("Duration in minutes (15–1440): ", 15, 1440);
+        match start_temp_basal(conn, patient_id, mode, duration_minutes as i64, user_id) {
+            Ok(TempBasalOutcome::Started(temp)) => println!("Temp basal started: {}", temp.describe()),
+            Ok(TempBasalOutcome::Refused(reason)) => println!("Temp basal refused: {}", reason),
+            Err(e) => eprintln!("Error starting temp basal: {}", e),
+        }
+    }
+}
+
+
+// lets a user pick the unit glucose values are shown and entered in
+pub fn choose_glucose_unit(conn: &rusqlite::Connection, user_id: &str, current: GlucoseUnit) {
+    println!("\n--- Glucose Units ---");
+    println!("Current unit: {}", current.label());
+    println!("1. mg/dL");
+    println!("2. mmol/L");
+    println!("Enter your choice: ");
+
+    let unit = match utils::get_user_choice() {
+        1 => GlucoseUnit::MgDl,
+        2 => GlucoseUnit::MmolL,
+        _ => {
+            println!("Invalid choice");
+            return;
+        }
+    };
+    match update_user_glucose_unit(conn, user_id, unit) {
+        Ok(_) => println!("Glucose values will be shown in {}.", unit.label()),
+        Err(e) => eprintln!("Error saving glucose unit: {}", e),
+    }
+}
+
+
+// alert inbox over the given patients: lists alerts by status and type and acknowledges them
+pub fn manage_alert_inbox(conn: &rusqlite::Connection, patient_ids: &[String], user_id: &str) {
+    if patient_ids.is_empty() {
+        println!("Cannot perform this action because no patient is assigned.");
+        return;
+    }
+    let mut filter = AlertFilter::default();
+    let unit = get_user_glucose_unit(conn, user_id).unwrap_or_default();
+
+    loop {
+        display_alerts(conn, patient_ids, &filter, unit);
+        println!("\n1. Show open alerts");
+        println!("2. Show resolved alerts");
+        println!("3. Show all alerts");
+        println!("4. Filter by alert type");
+        println!("5. Acknowledge an alert");
+        println!("6. Back");
+        println!("7. Snooze alerts like one of these");
+        println!("8. Quiet hours");
+        println!("9. Notification channels");
+        println!("Enter your choice: ");
+
+        match utils::get_user_choice() {
+            1 => filter.status = AlertStatusFilter::Open,
+            2 => filter.status = AlertStatusFilter::Resolved,
+            3 => filter.status = AlertStatusFilter::All,
+            4 => filter.alert_type = choose_alert_type(conn, patient_ids),
+            5 => {
+                let alert_id = read_valid_int("Alert number to acknowledge: ", 1, i32::MAX);
+                let note = read_non_empty_input("Note (action taken): ");
+                match acknowledge_alert(conn, alert_id as i64, patient_ids, user_id, note.trim()) {
+                    Ok(true) => println!("Alert {} acknowledged.", alert_id),
+                    Ok(false) => println!("No open alert with that number."),
+                    Err(e) => eprintln!("Error acknowledging alert: {}", e),
+                }
+            }
+            6 => return,
+            7 => {
+                let alert_id = read_valid_int("Alert number to snooze: ", 1, i32::MAX) as i64;
+                snooze_alerts_like(conn, patient_ids, alert_id, user_id);
+            }
+            8 => configure_quiet_hours(conn, user_id),
+            9 => configure_notification_channels(conn, user_id),
+            _ => println!("Invalid choice"),
+        }
+    }
+}
+
+// snoozes the alert's type for the alert's patient
+fn snooze_alerts_like(conn: &rusqlite::Connection, patient_ids: &[String], alert_id: i64, user_id: &str) {
+    let all = AlertFilter { status: AlertStatusFilter::All, alert_type: None };
+    let alert = match get_alerts(conn, patient_ids, &all) {
+        Ok(alerts) => alerts.into_iter().find(|a| a.alert_id == alert_id),
+        Err(e) => {
+            eprintln!("Error retrieving alerts: {}", e);
+            return;
+        }
+    };
+    let alert = match alert {
+        Some(alert) => alert,
+        None => {
+            println!("No alert with that number.");
+            return;
+        }
+    };
+    match snooze_alert_type(conn, &alert.patient_id, &alert.alert_type, user_id, &SnoozeSettings::from_env()) {
+        Ok(Some(until)) => println!("\"{}\" alerts snoozed until {}.", alert.alert_type, format_local_time(&until)),
+        Ok(None) => println!("\"{}\" alerts cannot be snoozed.", alert.alert_type),
+        Err(e) => eprintln!("Error snoozing alerts: {}", e),
+    }
+}
+
+// shows and changes the user's quiet hours
+fn configure_quiet_hours(conn: &rusqlite::Connection, user_id: &str) {
+    println!("\n--- Quiet Hours ---");
+    match get_user_quiet_hours(conn, user_id) {
+        Ok(Some(quiet_hours)) => println!("Quiet hours: {} (only urgent lows are notified)", quiet_hours.describe()),
+        Ok(None) => println!("No quiet hours set."),
+        Err(e) => eprintln!("Error retrieving quiet hours: {}", e),
+    }
+    println!("1. Set quiet hours");
+    println!("2. Turn quiet hours off");
+    println!("3. Back");
+    println!("Enter your choice: ");
+
+    let quiet_hours = match utils::get_user_choice() {
+        1 => {
+            let start = read_valid_time_hh_mm("Start (HH:MM): ");
+            let end = read_valid_time_hh_mm("End (HH:MM): ");
+            if start == end {
+                println!("Start and end must differ.");
+                return;
+            }
+            Some(QuietHours { start, end })
+        }
+        2 => None,
+        3 => return,
+        _ => {
+            println!("Invalid choice");
+            return;
+        }
+    };
+    match set_user_quiet_hours(conn, user_id, quiet_hours) {
+        Ok(_) => println!("Quiet hours updated."),
+        Err(e) => eprintln!("Error saving quiet hours: {}", e),
+    }
+}
+
+// shows and changes the channels the user receives alerts on outside the CLI
+fn configure_notification_channels(conn: &rusqlite::Connection, user_id: &str) {
+    println!("\n--- Notification Channels ---");
+    match get_channel_preferences(conn, user_id) {
+        Ok(preferences) if preferences.is_empty() => println!("Alerts are only shown in the alert inbox."),
+        Ok(preferences) => {
+            for preference in preferences {
+                println!("- {} {}", preference.channel.as_db_value(), preference.address.as_deref().unwrap_or(""));
+            }
+        }
+        Err(e) => eprintln!("Error retrieving notification channels: {}", e),
+    }
+    println!("1. Turn on local file");
+    println!("2. Turn on webhook");
+    println!("3. Turn on email");
+    println!("4. Turn a channel off");
+    println!("5. Back");
+    println!("Enter your choice: ");
+
+    let (channel, enabled, address) = match utils::get_user_choice() {
+        1 => (NotificationChannel::File, true, None),
+        2 => {
+            let url = read_non_empty_input("Webhook URL (http://host:port/path): ");
+            if parse_http_url(url.trim()).is_none() {
+                println!("Only http:// URLs are supported.");
+                return;
+            }
+            (NotificationChannel::Webhook, true, Some(url.trim().to_string()))
+        }
+        3 => {
+            let email = read_non_empty_input("Email address: ");
+            if !email.contains('@') {
+                println!("Invalid email address.");
+                return;
+            }
+            (NotificationChannel::Email, true, Some(email.trim().to_string()))
+        }
+        4 => {
+            println!("1. Local file  2. Webhook  3. Email");
+            let channel = NotificationChannel::ALL[read_valid_int("Channel to turn off: ", 1, 3) as usize - 1];
+            (channel, false, None)
+        }
+        5 => return,
+        _ => {
+            println!("Invalid choice");
+            return;
+        }
+    };
+    match set_channel_preference(conn, user_id, channel, enabled, address.as_deref()) {
+        Ok(_) if enabled => println!("New alerts will be sent by {}.", channel.as_db_value()),
+        Ok(_) => println!("Alerts are no longer sent by {}.", channel.as_db_value()),
+        Err(e) => eprintln!("Error saving notification channel: {}", e),
+    }
+}
+
+// tells the user about open alerts at the top of their menu; snoozed alerts are left out and during
+// their quiet hours only urgent lows are mentioned
+pub fn display_open_alert_notice(conn: &rusqlite::Connection, patient_ids: &[String], user_id: &str) {
+    let alerts = match get_alerts(conn, patient_ids, &AlertFilter::default()) {
+        Ok(alerts) => alerts,
+        Err(e) => {
+            eprintln!("Error retrieving alerts: {}", e);
+            return;
+        }
+    };
+    let mut open = 0;
+    for alert in alerts.iter().filter(|a| !a.snoozed) {
+        match should_notify_user(conn, user_id, &alert.alert_type) {
+            Ok(true) => open += 1,
+            Ok(false) => {}
+            Err(e) => eprintln!("Error checking quiet hours: {}", e),
+        }
+    }
+    if open > 0 {
+        println!("\n* {} open alert(s). See the alert inbox. *\n", open);
+    }
+}
+
+fn display_alerts(conn: &rusqlite::Connection, patient_ids: &[String], filter: &AlertFilter, unit: GlucoseUnit) {
+    println!(
+        "\n--- Alerts ({}, {}) ---",
+        filter.status.label(),
+        filter.alert_type.as_deref().unwrap_or("all types")
+    );
+    let alerts = match get_alerts(conn, patient_ids, filter) {
+        Ok(alerts) => alerts,
+        Err(e) => {
+            eprintln!("Error retrieving alerts: {}", e);
+            return;
+        }
+    };
+    if let Ok(snoozes) = get_active_snoozes(conn, patient_ids) {
+        for (_, alert_type, until) in snoozes {
+            println!("(\"{}\" alerts snoozed until {})", alert_type, format_local_time(&until));
+        }
+    }
+    if alerts.is_empty() {
+        println!("No alerts.");
+        return;
+    }
+
+    for alert in alerts {
+        // name the patient only when the inbox covers more than one
+        let patient = if patient_ids.len() > 1 {
+            format!(" | {}", alert.patient_name.as_deref().unwrap_or(&alert.patient_id))
+        } else {
+            String::new()
+        };
+        println!(
+            "#{} [{}] {}{}{}{}",
+            alert.alert_id,
+            alert.alert_type,
+            format_local_time(&alert.alert_time),
+            patient,
+            if alert.is_resolved { " | Resolved" } else { " | OPEN" },
+            if alert.snoozed { " | Snoozed" } else { "" }
+        );
+        println!("    {}", unit.format_text(&alert.alert_message));
+        match get_alert_escalations(conn, alert.alert_id) {
+            Ok(escalations) => {
+                for escalation in escalations {
+                    println!("    Escalated to {}", describe_escalation(&escalation));
+                }
+            }
+            Err(e) => eprintln!("Error retrieving alert escalations: {}", e),
+        }
+        match get_alert_deliveries(conn, alert.alert_id) {
+            Ok(deliveries) => {
+                for delivery in deliveries {
+                    println!(
+                        "    Notified {} by {}: {}{} ({} attempt(s), last {})",
+                        delivery.user_name,
+                        delivery.channel,
+                        delivery.status,
+                        delivery.error.map_or(String::new(), |e| format!(" - {}", e)),
+                        delivery.attempts,
+                        format_local_time(&delivery.attempted_at)
+                    );
+                }
+            }
+            Err(e) => eprintln!("Error retrieving alert deliveries: {}", e),
+        }
+        if alert.is_resolved {
+            println!(
+                "    Acknowledged by {} at {}: {}",
+                alert.resolved_by.as_deref().unwrap_or("unknown"),
+                alert.resolved_at.map_or("unknown time".to_string(), |t| format_local_time(&t)),
+                alert.resolution_note.as_deref().unwrap_or("")
+            );
+        }
+    }
+}
+
+// lets the user pick one of the alert types raised so far, or every type
+fn choose_alert_type(conn: &rusqlite::Connection, patient_ids: &[String]) -> Option<String> {
+    let types = match get_alert_types(conn, patient_ids) {
+        Ok(types) => types,
+        Err(e) => {
+            eprintln!("Error retrieving alert types: {}", e);
+            return None;
+        }
+    };
+    println!("\n0. All types");
+    for (index, alert_type) in types.iter().enumerate() {
+        println!("{}. {}", index + 1, alert_type);
+    }
+    let choice = read_valid_int("Alert type: ", 0, types.len() as i32);
+    if choice == 0 {
+        None
+    } else {
+        types.into_iter().nth(choice as usize - 1)
+    }
+}
@@ -1,5 +1,7 @@
 use crate::utils;
-use crate::access_control::Role;
+use crate::menus::menu_utils::{configure_basal_schedule, prompt_bolus_dose, manage_temp_basal, choose_glucose_unit,
+                        manage_alert_inbox, display_open_alert_notice};
+use crate::access_control::{Role, Permission};
 use crate::db::queries::{insert_activation_code,
                         add_caretaker_team_member,
                         add_caretaker_to_patient_account};
@@ -11,7 +13,17 @@ use crate::insulin::{display_patient_glucose_readings,
         get_patient_data_from_patient_table,
         get_patient_insulin_data,get_one_patient_by_caretaker_id,
         display_patient_complete_glucose_insulin_history,
-        show_patient_current_basal_bolus_limits};
+        show_patient_current_basal_bolus_limits,
+        request_bolus_dose,BolusOutcome,
+        ExtendedBolusKind,ExtendedBolusOutcome,request_extended_bolus,
+        cancel_extended_bolus,display_in_progress_extended_boluses};
+use crate::forecast::display_glucose_forecast;
+use crate::calibration::{log_fingerstick, CalibrationOutcome};
+use crate::import::{MIN_GLUCOSE_MG_DL, MAX_GLUCOSE_MG_DL};
+use crate::input_validation::{read_non_empty_input,read_valid_float,read_valid_int,read_valid_glucose};
+use crate::sensor::{display_sensor_status, start_sensor_session, SensorSettings};
+use crate::units::{get_user_glucose_unit, GlucoseUnit};
+use chrono::Utc;
 
 
 
@@ -44,6 +56,10 @@ pub fn show_patient_menu(conn: &rusqlite::Connection,role:&Role,session_id: &str
             return;
         }
 
+        // glucose values are shown and entered in the user's preferred unit
+        let unit = get_user_glucose_unit(conn, &session.user_id).unwrap_or_default();
+
+        display_open_alert_notice(conn, std::slice::from_ref(&session.user_id), &session.user_id);
         println!("=== Patient Menu ===");
         println!("1) View most recent glucose readings.");
         println!("2) View current basal and bolus options.");
@@ -52,6 +68,12 @@ pub fn show_patient_menu(conn: &rusqlite::Connection,role:&Role,session_id: &str
         println!("5) View patient insulin history.");
         println!("6. Create Caretaker activation code.");
         println!("7. Logout");
+        println!("8) Set or cancel temporary basal.");
+        println!("9) Extended or dual-wave bolus.");
+        println!("10) Log fingerstick blood glucose.");
+        println!("11) Change glucose display units.");
+        println!("12) CGM sensor status or start a new sensor.");
+        println!("13) View and acknowledge alerts.");
         println!("Enter your choice: ");
 
         let choice = utils::get_user_choice();
@@ -62,7 +84,8 @@ pub fn show_patient_menu(conn: &rusqlite::Connection,role:&Role,session_id: &str
 
             1 => {
                 //View the patient’s most recent glucose readings.
-                display_patient_glucose_readings(&conn, &session.user_id, true);
+                display_patient_glucose_readings(&conn, &session.user_id, true, unit);
+                display_glucose_forecast(conn, &session.user_id, unit);
             },
             2 => {
                 // View the patient’s current basal rate and bolus insulin options.
@@ -71,18 +94,26 @@ pub fn show_patient_menu(conn: &rusqlite::Connection,role:&Role,session_id: &str
             3 => {
                 //  Request a bolus insulin dose.
                 //– Patients cannot request more than the prescribed maximum dose or violate safety limits
-
+                if !session_manager.check_permissions(conn, session_id, role, Permission::AddGlucose) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                handle_bolus_request(conn, &session.user_id, unit);
             }, 
             4 => {
                 //Configure basal insulin dose time.
                 // Patients can adjust the basal insulin dose, which will be effective within 24 hours, so as
                 // not to overlap a previous dose.
                 // – Patients cannot request more than the prescribed maximum dose or violate safety limits.
-
+                if !session_manager.check_permissions(conn, session_id, role, Permission::AddGlucose) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                configure_basal_schedule(conn, &session.user_id, &session.user_id);
             }, 
             5 => {
                 //Review historical insulin delivery and glucose data.
-                display_patient_complete_glucose_insulin_history(conn,&session.user_id);
+                display_patient_complete_glucose_insulin_history(conn,&session.user_id, unit);
             }, 
             6 => {
                 //
@@ -97,6 +128,48 @@ pub fn show_patient_menu(conn: &rusqlite::Connection,role:&Role,session_id: &str
                 }
                 return;
             },
+            8 => {
+                // Temporary basal for exercise, illness, etc. Reverts to the scheduled basal when it ends.
+                if !session_manager.check_permissions(conn, session_id, role, Permission::AddGlucose) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                manage_temp_basal(conn, &session.user_id, &session.user_id);
+            },
+            9 => {
+                // Bolus delivered over a number of minutes; can be inspected or cancelled while running.
+                if !session_manager.check_permissions(conn, session_id, role, Permission::AddGlucose) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                manage_extended_bolus(conn, &session.user_id, unit);
+            },
+            10 => {
+                // capillary glucose value, used to calibrate the CGM and track its accuracy
+                if !session_manager.check_permissions(conn, session_id, role, Permission::AddGlucose) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                handle_fingerstick_entry(conn, &session.user_id, unit);
+            },
+            11 => {
+                choose_glucose_unit(conn, &session.user_id, unit);
+            },
+            12 => {
+                // starting a sensor begins its warm-up; only the patient or their care team may do it
+                if !session_manager.check_permissions(conn, session_id, role, Permission::AddGlucose) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                manage_sensor(conn, &session.user_id);
+            },
+            13 => {
+                if !session_manager.check_permissions(conn, session_id, role, Permission::ViewAlerts) {
+                    println!("Access denied: insufficient permissions.");
+                    continue;
+                }
+                manage_alert_inbox(conn, std::slice::from_ref(&session.user_id), &session.user_id);
+            },
             _ => println!("Invalid choice"),
         }
     }
@@ -133,3 +206,107 @@ pub fn create_and_display_caretaker_activation_code(
     }
 }
 
+
+// prompts the patient for a bolus amount and reports whether it was delivered or which limit refused it
+fn handle_bolus_request(conn: &rusqlite::Connection, patient_id: &str, unit: GlucoseUnit) {
+    let units = match prompt_bolus_dose(conn, patient_id, unit) {
+        Some(units) => units,
+        None => return,
+    };
+
+    // the patient is both the subject and the requester of the dose
+    match request_bolus_dose(conn, patient_id, units, patient_id, unit) {
+        Ok(BolusOutcome::Delivered(units)) => println!("Bolus of {:.2} units delivered and logged.", units),
+        Ok(BolusOutcome::Refused(reason)) => println!("Bolus refused: {}", reason),
+        Err(e) => eprintln!("Error requesting bolus: {}", e),
+    }
+}
+
+// records a fingerstick value and reports whether the sensor was calibrated with it
+fn handle_fingerstick_entry(conn: &rusqlite::Connection, patient_id: &str, unit: GlucoseUnit) {
+    let fingerstick = read_valid_glucose("Fingerstick blood glucose", MIN_GLUCOSE_MG_DL, MAX_GLUCOSE_MG_DL, unit);
+
+    match log_fingerstick(conn, patient_id, fingerstick, patient_id, unit) {
+        Ok(CalibrationOutcome::Calibrated { cgm_glucose, factor }) => println!(
+            "Sensor calibrated: CGM read {}, new readings are corrected by ×{:.2}.",
+            unit.format(cgm_glucose), factor
+        ),
+        Ok(CalibrationOutcome::Recorded(reason)) => println!("Fingerstick recorded without calibrating: {}", reason),
+        Err(e) => eprintln!("Error logging fingerstick: {}", e),
+    }
+}
+
+// CGM sensor menu: current sensor status and starting a replacement
+fn manage_sensor(conn: &rusqlite::Connection, patient_id: &str) {
+    loop {
+        display_sensor_status(conn, patient_id);
+        println!("\n1. Start a new sensor");
+        println!("2. Back");
+        println!("Enter your choice: ");
+
+        match utils::get_user_choice() {
+            1 => {
+                let serial_number = read_non_empty_input("Sensor serial number: ");
+                let settings = SensorSettings::from_env();
+                match start_sensor_session(conn, patient_id, serial_number.trim(), patient_id, &settings) {
+                    Ok(_) => println!(
+                        "Sensor {} started. Readings are not used for the first {} minutes while it warms up.",
+                        serial_number.trim(), settings.warmup_minutes
+                    ),
+                    Err(e) => eprintln!("Error starting sensor: {}", e),
+                }
+            }
+            2 => return,
+            _ => println!("Invalid choice"),
+        }
+    }
+}
+
+// extended (square-wave) and dual-wave bolus menu
+fn manage_extended_bolus(conn: &rusqlite::Connection, patient_id: &str, unit: GlucoseUnit) {
+    loop {
+        println!("\n--- Extended Bolus ---");
+        println!("1. View in-progress extended boluses");
+        println!("2. Start extended (square-wave) bolus");
+        println!("3. Start dual-wave bolus");
+        println!("4. Cancel an in-progress extended bolus");
+        println!("5. Back");
+        println!("Enter your choice: ");
+
+        let kind = match utils::get_user_choice() {
+            1 => {
+                display_in_progress_extended_boluses(conn, patient_id);
+                continue;
+            }
+            2 => ExtendedBolusKind::SquareWave,
+            3 => ExtendedBolusKind::DualWave {
+                immediate_percent: read_valid_float("Percentage delivered now (0–100): ", 0.0, 100.0),
+            },
+            4 => {
+                display_in_progress_extended_boluses(conn, patient_id);
+                let extended_bolus_id = read_valid_int("Extended bolus number to cancel: ", 1, i32::MAX);
+                match cancel_extended_bolus(conn, patient_id, extended_bolus_id as i64, patient_id) {
+                    Ok(Some(delivered)) => println!("Extended bolus cancelled after {:.2} extended units.", delivered),
+                    Ok(None) => println!("No in-progress extended bolus with that number."),
+                    Err(e) => eprintln!("Error cancelling extended bolus: {}", e),
+                }
+                continue;
+            }
+            5 => return,
+            _ => {
+                println!("Invalid choice");
+                continue;
+            }
+        };
+
+        show_patient_current_basal_bolus_limits(conn, &patient_id.to_string());
+        let total_units = read_valid_float("Total bolus dose (units): ", 0.0, 200.0);
+        let duration_minutes = read_valid_int("Deliver over how many minutes (15–720): ", 15, 720);
+
+        match request_extended_bolus(conn, patient_id, kind, total_units, duration_minutes as i64, patient_id, unit) {
+            Ok(ExtendedBolusOutcome::Started(bolus)) => println!("Started: {}", bolus.describe(Utc::now().naive_utc())),
+            Ok(ExtendedBolusOutcome::Refused(reason)) => println!("Bolus refused: {}", reason),
+            Err(e) => eprintln!("Error requesting extended bolus: {}", e),
+        }
+    }
+}
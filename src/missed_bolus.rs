@@ -0,0 +1,423 @@
+// Missed meal bolus detection: a sharp glucose rise after a logged or inferred meal with no bolus
+use std::time::Duration as StdDuration;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rusqlite::{Connection, Result, params, params_from_iter};
+use crate::alerts::{insert_alert, LOW_GLUCOSE_ALERT, URGENT_LOW_ALERT};
+use crate::config::env_or;
+use crate::filter::{GlucoseSource, GlucoseSourceSettings};
+use crate::insulin::{get_patient_data_from_patient_table, BOLUS_ACTION, DUAL_WAVE_BOLUS_START_ACTION, EXTENDED_BOLUS_START_ACTION};
+use crate::utils::{format_local_time, get_current_db_time_string, DB_TIME_FORMAT};
+
+pub const MISSED_BOLUS_ALERT: &str = "Missed Bolus";
+// seconds between detection passes
+const MISSED_BOLUS_CHECK_INTERVAL_SECS: u64 = 300;
+// meals and rises older than this are not evaluated any more
+const LOOKBACK_HOURS: i64 = 6;
+// a bolus this long before a meal (a pre-bolus) still covers it
+const PRE_BOLUS_MINUTES: i64 = 30;
+// the glucose at a logged meal is the last reading at most this long before it
+const BASELINE_MAX_AGE_MINUTES: i64 = 15;
+
+pub struct MissedBolusSettings {
+    // minutes after a meal that a bolus is expected by and the rise is measured over
+    pub window_minutes: i64,
+    // rise (mg/dL) within the window that counts as sharp
+    pub rise_mg_dl: f32,
+    pub glucose_source: GlucoseSource,
+}
+
+impl MissedBolusSettings {
+    pub fn from_env() -> Self {
+        Self {
+            window_minutes: env_or("GLUCOGUARD_MISSED_BOLUS_WINDOW_MINUTES", 60),
+            rise_mg_dl: env_or("GLUCOGUARD_MISSED_BOLUS_RISE_MG_DL", 50.0),
+            glucose_source: GlucoseSourceSettings::from_env().alerts,
+        }
+    }
+}
+
+// the rise found after a meal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MealRise {
+    pub meal_time: NaiveDateTime,
+    pub baseline: f32,
+    pub peak: f32,
+}
+
+/// Rise after a meal logged at `meal_time`, from the reading at the meal (or the first one after
+/// it) to the highest reading within `window_minutes`. Readings are oldest first.
+pub fn find_meal_rise(readings: &[(NaiveDateTime, f32)], meal_time: NaiveDateTime, window_minutes: i64) -> Option<MealRise> {
+    let window_end = meal_time + Duration::minutes(window_minutes);
+    let baseline = readings
+        .iter()
+        .rev()
+        .find(|(time, _)| *time <= meal_time && meal_time - *time <= Duration::minutes(BASELINE_MAX_AGE_MINUTES))
+        .or_else(|| readings.iter().find(|(time, _)| *time > meal_time && *time <= window_end))?
+        .1;
+    let peak = readings
+        .iter()
+        .filter(|(time, _)| *time > meal_time && *time <= window_end)
+        .map(|(_, glucose)| *glucose)
+        .fold(f32::MIN, f32::max);
+    if peak == f32::MIN {
+        return None;
+    }
+    Some(MealRise { meal_time, baseline, peak })
+}
+
+/// Earliest rise of at least `rise_mg_dl` within `window_minutes` in the readings (oldest first),
+/// taken as an unlogged meal starting at the low point. Only starts whose window ends by `now`
+/// are considered, so the whole window is seen. A rise out of a low (a reading at or below
+/// `low_threshold` at the start or in the window before it) is hypo treatment, not a meal.
+pub fn find_inferred_meal(readings: &[(NaiveDateTime, f32)], window_minutes: i64, rise_mg_dl: f32, low_threshold: f32, now: NaiveDateTime) -> Option<MealRise> {
+    let window = Duration::minutes(window_minutes);
+    readings
+        .iter()
+        .filter(|(start, _)| *start + window <= now)
+        .filter(|(start, _)| {
+            !readings
+                .iter()
+                .any(|(time, glucose)| *time <= *start && *start - *time <= window && *glucose <= low_threshold)
+        })
+        .find_map(|(start, baseline)| {
+            let peak = readings
+                .iter()
+                .filter(|(time, _)| *time > *start && *time <= *start + window)
+                .map(|(_, glucose)| *glucose)
+                .fold(f32::MIN, f32::max);
+            (peak - baseline >= rise_mg_dl).then_some(MealRise { meal_time: *start, baseline: *baseline, peak })
+        })
+}
+
+fn parse_db_time(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, DB_TIME_FORMAT).ok()
+}
+
+// usable readings since `since`, oldest first
+fn get_readings_since(conn: &Connection, patient_id: &str, since: &NaiveDateTime, source: GlucoseSource) -> Result<Vec<(NaiveDateTime, f32)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT reading_time, {} FROM glucose_readings
+         WHERE patient_id = ?1 AND unusable_reason IS NULL AND datetime(reading_time) >= datetime(?2)
+         ORDER BY datetime(reading_time) ASC",
+        source.column()
+    ))?;
+    let rows = stmt.query_map(params![patient_id, since.format(DB_TIME_FORMAT).to_string()], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f32>(1)?))
+    })?;
+
+    let mut readings = Vec::new();
+    for row in rows {
+        let (time_str, glucose) = row?;
+        if let Some(time) = parse_db_time(&time_str) {
+            readings.push((time, glucose));
+        }
+    }
+    Ok(readings)
+}
+
+// whether a meal bolus (normal, extended or dual-wave) was logged between `from` and `to`
+fn has_bolus_between(conn: &Connection, patient_id: &str, from: &NaiveDateTime, to: &NaiveDateTime) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM insulin_logs
+         WHERE patient_id = ?1 AND UPPER(action_type) IN (UPPER(?4), UPPER(?5), UPPER(?6))
+           AND datetime(dosage_time) >= datetime(?2) AND datetime(dosage_time) <= datetime(?3)",
+        params![
+            patient_id,
+            from.format(DB_TIME_FORMAT).to_string(),
+            to.format(DB_TIME_FORMAT).to_string(),
+            BOLUS_ACTION,
+            EXTENDED_BOLUS_START_ACTION,
+            DUAL_WAVE_BOLUS_START_ACTION
+        ],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+// whether a low or urgent low alert was raised for the patient between `from` and `to`
+fn has_low_alert_between(conn: &Connection, patient_id: &str, from: &NaiveDateTime, to: &NaiveDateTime) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM alerts
+         WHERE patient_id = ?1 AND alert_type IN (?4, ?5)
+           AND datetime(alert_time) >= datetime(?2) AND datetime(alert_time) <= datetime(?3)",
+        params![
+            patient_id,
+            from.format(DB_TIME_FORMAT).to_string(),
+            to.format(DB_TIME_FORMAT).to_string(),
+            LOW_GLUCOSE_ALERT,
+            URGENT_LOW_ALERT
+        ],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+// whether a missed bolus was already recorded for the meal, or (meal_id None) for a meal within `window` of the time
+fn is_already_recorded(conn: &Connection, patient_id: &str, meal_id: Option<i64>, meal_time: &NaiveDateTime, window: Duration) -> Result<bool> {
+    let count: i64 = match meal_id {
+        Some(meal_id) => conn.query_row(
+            "SELECT COUNT(*) FROM missed_boluses WHERE meal_id = ?1",
+            params![meal_id],
+            |row| row.get(0),
+        )?,
+        None => conn.query_row(
+            "SELECT COUNT(*) FROM missed_boluses
+             WHERE patient_id = ?1 AND datetime(meal_time) >= datetime(?2) AND datetime(meal_time) <= datetime(?3)",
+            params![
+                patient_id,
+                (*meal_time - window).format(DB_TIME_FORMAT).to_string(),
+                (*meal_time + window).format(DB_TIME_FORMAT).to_string()
+            ],
+            |row| row.get(0),
+        )?,
+    };
+    Ok(count > 0)
+}
+
+// records the missed bolus and raises its alert
+fn record_missed_bolus(conn: &Connection, patient_id: &str, meal_id: Option<i64>, carbs: Option<f32>, rise: &MealRise, window_minutes: i64) -> Result<()> {
+    let meal = match carbs {
+        Some(carbs) => format!("a {:.0} g meal logged at {}", carbs, format_local_time(&rise.meal_time)),
+        None => format!("an unlogged meal around {}", format_local_time(&rise.meal_time)),
+    };
+    let message = format!(
+        "Possible missed meal bolus: glucose rose {:.0} mg/dL ({:.0} mg/dL → {:.0} mg/dL) within {} minutes of {} and no bolus was given.",
+        rise.peak - rise.baseline, rise.baseline, rise.peak, window_minutes, meal
+    );
+    let alert_id = insert_alert(conn, patient_id, MISSED_BOLUS_ALERT, &message)?;
+    conn.execute(
+        "INSERT INTO missed_boluses (patient_id, meal_id, meal_time, inferred, baseline_glucose, peak_glucose, detected_at, alert_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            patient_id,
+            meal_id,
+            rise.meal_time.format(DB_TIME_FORMAT).to_string(),
+            meal_id.is_none(),
+            rise.baseline,
+            rise.peak,
+            get_current_db_time_string(),
+            alert_id
+        ],
+    )?;
+    Ok(())
+}
+
+/// Looks for meals in the last `LOOKBACK_HOURS` whose window has passed with a sharp rise and no
+/// bolus from `PRE_BOLUS_MINUTES` before the meal to the end of the window. Logged meals are
+/// checked first; a rise with no logged meal near it is treated as an inferred meal unless it is a
+/// recovery from a low. Each missed bolus is recorded once and raises a "Missed Bolus" alert.
+/// Returns the number found.
+pub fn evaluate_missed_boluses(conn: &Connection, patient_id: &str, settings: &MissedBolusSettings) -> Result<usize> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(0),
+    };
+    let now = Utc::now().naive_utc();
+    let window = Duration::minutes(settings.window_minutes);
+    let since = now - Duration::hours(LOOKBACK_HOURS);
+    let readings = get_readings_since(conn, patient_id, &(since - Duration::minutes(BASELINE_MAX_AGE_MINUTES)), settings.glucose_source)?;
+    let mut found = 0;
+
+    let mut stmt = conn.prepare(
+        "SELECT meal_id, meal_time, carbohydrate_amount FROM meal_logs
+         WHERE patient_id = ?1 AND datetime(meal_time) >= datetime(?2)
+         ORDER BY datetime(meal_time) ASC",
+    )?;
+    let meals = stmt
+        .query_map(params![patient_id, since.format(DB_TIME_FORMAT).to_string()], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, f32>(2)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    let meals: Vec<(i64, NaiveDateTime, f32)> = meals
+        .into_iter()
+        .filter_map(|(meal_id, time, carbs)| parse_db_time(&time).map(|time| (meal_id, time, carbs)))
+        .collect();
+
+    for (meal_id, meal_time, carbs) in &meals {
+        if *meal_time + window > now || is_already_recorded(conn, patient_id, Some(*meal_id), meal_time, window)? {
+            continue;
+        }
+        if has_bolus_between(conn, patient_id, &(*meal_time - Duration::minutes(PRE_BOLUS_MINUTES)), &(*meal_time + window))? {
+            continue;
+        }
+        let rise = find_meal_rise(&readings, *meal_time, settings.window_minutes)
+            .filter(|rise| rise.peak - rise.baseline >= settings.rise_mg_dl);
+        if let Some(rise) = rise {
+            record_missed_bolus(conn, patient_id, Some(*meal_id), Some(*carbs), &rise, settings.window_minutes)?;
+            found += 1;
+        }
+    }
+
+    // rises after the last one handled, so one rise is only inferred once
+    let mut remaining: &[(NaiveDateTime, f32)] = &readings;
+    while let Some(rise) = find_inferred_meal(remaining, settings.window_minutes, settings.rise_mg_dl, patient.low_glucose_threshold, now) {
+        let near_logged_meal = meals.iter().any(|(_, meal_time, _)| (*meal_time - rise.meal_time).abs() <= window);
+        if rise.meal_time >= since
+            && !near_logged_meal
+            && !has_low_alert_between(conn, patient_id, &(rise.meal_time - window), &rise.meal_time)?
+            && !is_already_recorded(conn, patient_id, None, &rise.meal_time, window)?
+            && !has_bolus_between(conn, patient_id, &(rise.meal_time - Duration::minutes(PRE_BOLUS_MINUTES)), &(rise.meal_time + window))?
+        {
+            record_missed_bolus(conn, patient_id, None, None, &rise, settings.window_minutes)?;
+            found += 1;
+        }
+        let window_end = rise.meal_time + window;
+        remaining = &remaining[remaining.partition_point(|(time, _)| *time <= window_end)..];
+    }
+    Ok(found)
+}
+
+// checks every patient for missed meal boluses
+pub fn evaluate_missed_boluses_for_all_patients(conn: &Connection, settings: &MissedBolusSettings) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT patient_id FROM patients")?;
+    let patient_ids = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?;
+
+    for patient_id in patient_ids {
+        if let Err(e) = evaluate_missed_boluses(conn, &patient_id, settings) {
+            eprintln!("Missed bolus check failed for patient {}: {}", patient_id, e);
+        }
+    }
+    Ok(())
+}
+
+// Run missed meal bolus detection in a background thread
+pub fn run_missed_bolus_monitor(db_path: &str, settings: MissedBolusSettings) {
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || loop {
+        match Connection::open(&db_path) {
+            Ok(conn) => {
+                let _ = conn.busy_timeout(StdDuration::from_secs(5));
+                if let Err(e) = evaluate_missed_boluses_for_all_patients(&conn, &settings) {
+                    eprintln!("Missed bolus check failed: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to open DB connection for missed bolus detection: {:?}", e),
+        }
+        std::thread::sleep(StdDuration::from_secs(MISSED_BOLUS_CHECK_INTERVAL_SECS));
+    });
+}
+
+//-----------------------Clinician report-----------------------//
+
+// missed boluses of one patient over the report periods
+pub struct MissedBolusSummary {
+    pub patient_name: String,
+    pub last_7_days: i64,
+    pub last_30_days: i64,
+    // of the last 30 days, how many were after a meal that was not logged
+    pub inferred_30_days: i64,
+    pub last_detected: Option<NaiveDateTime>,
+}
+
+/// How often a missed meal bolus was detected for each of the given patients.
+pub fn get_missed_bolus_report(conn: &Connection, patient_ids: &[String]) -> Result<Vec<MissedBolusSummary>> {
+    if patient_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let placeholders = (3..patient_ids.len() + 3).map(|i| format!("?{}", i)).collect::<Vec<_>>().join(", ");
+    let sql = format!(
+        "SELECT p.first_name || ' ' || p.last_name,
+                COUNT(CASE WHEN datetime(m.meal_time) >= datetime(?1) THEN 1 END),
+                COUNT(CASE WHEN datetime(m.meal_time) >= datetime(?2) THEN 1 END),
+                COUNT(CASE WHEN datetime(m.meal_time) >= datetime(?2) AND m.inferred = 1 THEN 1 END),
+                MAX(m.meal_time)
+         FROM patients p
+         LEFT JOIN missed_boluses m ON m.patient_id = p.patient_id
+         WHERE p.patient_id IN ({})
+         GROUP BY p.patient_id
+         ORDER BY 3 DESC, 1",
+        placeholders
+    );
+    let now = Utc::now().naive_utc();
+    let mut values = vec![
+        (now - Duration::days(7)).format(DB_TIME_FORMAT).to_string(),
+        (now - Duration::days(30)).format(DB_TIME_FORMAT).to_string(),
+    ];
+    values.extend(patient_ids.iter().cloned());
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params_from_iter(values), |row| {
+        Ok(MissedBolusSummary {
+            patient_name: row.get(0)?,
+            last_7_days: row.get(1)?,
+            last_30_days: row.get(2)?,
+            inferred_30_days: row.get(3)?,
+            last_detected: row.get::<_, Option<String>>(4)?.as_deref().and_then(parse_db_time),
+        })
+    })?;
+    rows.collect()
+}
+
+// prints the missed meal bolus report for a clinician's patients
+pub fn display_missed_bolus_report(conn: &Connection, patient_ids: &[String]) {
+    println!("\n--- Missed Meal Bolus Report ---");
+    let report = match get_missed_bolus_report(conn, patient_ids) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error building missed bolus report: {}", e);
+            return;
+        }
+    };
+    if report.is_empty() {
+        println!("No patients found.");
+        return;
+    }
+
+    println!("{:<28} {:>7} {:>8} {:>9}  Last", "Patient", "7 days", "30 days", "unlogged");
+    for summary in report {
+        println!(
+            "{:<28} {:>7} {:>8} {:>9}  {}",
+            summary.patient_name,
+            summary.last_7_days,
+            summary.last_30_days,
+            summary.inferred_30_days,
+            summary.last_detected.map_or("-".to_string(), |t| format_local_time(&t))
+        );
+    }
+    println!("(\"unlogged\" counts rises with no meal in the meal log, i.e. inferred meals.)");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(minutes: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 1).unwrap().and_hms_opt(8, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    // readings every 15 minutes from minute 0, oldest first
+    fn readings(values: &[f32]) -> Vec<(NaiveDateTime, f32)> {
+        values.iter().enumerate().map(|(i, g)| (at(15 * i as i64), *g)).collect()
+    }
+
+    #[test]
+    fn meal_rise_from_the_reading_at_the_meal() {
+        let rise = find_meal_rise(&readings(&[100.0, 130.0, 170.0, 150.0]), at(5), 60).unwrap();
+        assert_eq!((rise.baseline, rise.peak), (100.0, 170.0));
+    }
+
+    #[test]
+    fn meal_rise_falls_back_to_the_first_reading_after_the_meal() {
+        let rise = find_meal_rise(&readings(&[100.0, 130.0, 170.0]), at(-30), 60).unwrap();
+        assert_eq!((rise.baseline, rise.peak), (100.0, 170.0));
+        assert!(find_meal_rise(&readings(&[100.0]), at(5), 60).is_none());
+    }
+
+    #[test]
+    fn inferred_meal_at_the_start_of_a_rise() {
+        let values = readings(&[110.0, 100.0, 130.0, 160.0, 180.0, 175.0]);
+        let meal = find_inferred_meal(&values, 60, 75.0, 70.0, at(120)).unwrap();
+        assert_eq!((meal.meal_time, meal.baseline, meal.peak), (at(15), 100.0, 180.0));
+        // the window after the start has not fully passed yet
+        assert!(find_inferred_meal(&values, 60, 75.0, 70.0, at(60)).is_none());
+    }
+
+    #[test]
+    fn recovery_from_a_low_is_not_a_meal() {
+        let values = readings(&[55.0, 75.0, 95.0, 110.0, 110.0]);
+        assert!(find_inferred_meal(&values, 60, 50.0, 70.0, at(120)).is_none());
+    }
+}
@@ -0,0 +1,477 @@
+// Alert delivery outside the CLI: append-only file, HTTP webhook and SMTP email
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration as StdDuration;
+use chrono::NaiveDateTime;
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use serde::Serialize;
+use crate::config::env_or;
+use crate::suppression::should_notify_user;
+use crate::units::get_user_glucose_unit;
+use crate::utils::{get_current_db_time_string, DB_TIME_FORMAT};
+
+// seconds between delivery passes
+const NOTIFY_INTERVAL_SECS: u64 = 30;
+// a delivery that failed this many times is not tried again
+pub const MAX_DELIVERY_ATTEMPTS: i64 = 3;
+
+pub const DELIVERY_SENT: &str = "sent";
+pub const DELIVERY_FAILED: &str = "failed";
+// held back because the recipient was in their quiet hours; sent once the quiet hours end
+pub const DELIVERY_SKIPPED: &str = "skipped";
+
+pub struct NotifySettings {
+    // file every "file" notification is appended to
+    pub file_path: String,
+    // "host:port" of the SMTP server email notifications are sent through
+    pub smtp_address: String,
+    pub smtp_from: String,
+    // connect/read/write timeout for webhooks and SMTP
+    pub timeout_secs: u64,
+}
+
+impl NotifySettings {
+    pub fn from_env() -> Self {
+        Self {
+            file_path: env_or("GLUCOGUARD_NOTIFY_FILE", "data/notifications.log".to_string()),
+            smtp_address: env_or("GLUCOGUARD_SMTP_ADDR", "127.0.0.1:2525".to_string()),
+            smtp_from: env_or("GLUCOGUARD_SMTP_FROM", "alerts@glucoguard.local".to_string()),
+            timeout_secs: env_or("GLUCOGUARD_NOTIFY_TIMEOUT_SECS", 5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotificationChannel {
+    File,
+    Webhook,
+    Email,
+}
+
+impl NotificationChannel {
+    pub const ALL: [NotificationChannel; 3] = [NotificationChannel::File, NotificationChannel::Webhook, NotificationChannel::Email];
+
+    pub fn as_db_value(&self) -> &'static str {
+        match self {
+            NotificationChannel::File => "file",
+            NotificationChannel::Webhook => "webhook",
+            NotificationChannel::Email => "email",
+        }
+    }
+
+    pub fn from_db_value(value: &str) -> Option<Self> {
+        NotificationChannel::ALL.into_iter().find(|c| c.as_db_value() == value)
+    }
+}
+
+// the alert being delivered
+#[derive(Serialize)]
+pub struct AlertNotification {
+    pub alert_id: i64,
+    pub patient_id: String,
+    pub alert_type: String,
+    pub alert_message: String,
+    pub alert_time: String,
+}
+
+// who it is delivered to; address is the webhook URL or email address from their preferences
+pub struct Recipient {
+    pub user_id: String,
+    pub user_name: String,
+    pub address: Option<String>,
+}
+
+/// A way of delivering an alert to a user. `send` returns a description of the failure, which is
+/// stored with the delivery attempt.
+pub trait Notifier {
+    fn channel(&self) -> NotificationChannel;
+    fn send(&self, recipient: &Recipient, alert: &AlertNotification) -> std::result::Result<(), String>;
+}
+
+// appends one tab-separated line per notification to a local file
+pub struct FileNotifier {
+    pub path: String,
+}
+
+impl Notifier for FileNotifier {
+    fn channel(&self) -> NotificationChannel {
+        NotificationChannel::File
+    }
+
+    fn send(&self, recipient: &Recipient, alert: &AlertNotification) -> std::result::Result<(), String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("cannot open {}: {}", self.path, e))?;
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}",
+            get_current_db_time_string(), recipient.user_name, alert.alert_id, alert.alert_type, alert.alert_message
+        )
+        .map_err(|e| format!("cannot write {}: {}", self.path, e))
+    }
+}
+
+// body of a webhook request
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    recipient: &'a str,
+    #[serde(flatten)]
+    alert: &'a AlertNotification,
+}
+
+/// Splits an `http://host[:port][/path]` URL into ("host:port", "/path").
+pub fn parse_http_url(url: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return None;
+    }
+    let authority = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+    Some((authority, path.to_string()))
+}
+
+fn connect(address: &str, timeout: StdDuration) -> std::result::Result<TcpStream, String> {
+    let socket_address = address
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", address, e))?
+        .next()
+        .ok_or_else(|| format!("cannot resolve {}", address))?;
+    let stream = TcpStream::connect_timeout(&socket_address, timeout).map_err(|e| format!("cannot connect to {}: {}", address, e))?;
+    let _ = stream.set_read_timeout(Some(timeout));
+    let _ = stream.set_write_timeout(Some(timeout));
+    Ok(stream)
+}
+
+// POSTs the alert as JSON to the recipient's URL; any 2xx response is a delivery
+pub struct WebhookNotifier {
+    pub timeout: StdDuration,
+}
+
+impl Notifier for WebhookNotifier {
+    fn channel(&self) -> NotificationChannel {
+        NotificationChannel::Webhook
+    }
+
+    fn send(&self, recipient: &Recipient, alert: &AlertNotification) -> std::result::Result<(), String> {
+        let url = recipient.address.as_deref().ok_or("no webhook URL set")?;
+        let (host, path) = parse_http_url(url).ok_or_else(|| format!("unsupported webhook URL {}", url))?;
+        let body = serde_json::to_string(&WebhookPayload { recipient: &recipient.user_name, alert })
+            .map_err(|e| e.to_string())?;
+
+        let mut stream = connect(&host, self.timeout)?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path, host, body.len(), body
+        );
+        stream.write_all(request.as_bytes()).map_err(|e| format!("cannot send request: {}", e))?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream.take(1024))
+            .read_line(&mut status_line)
+            .map_err(|e| format!("no response: {}", e))?;
+        // e.g. "HTTP/1.1 204 No Content"
+        match status_line.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok()) {
+            Some(code) if (200..300).contains(&code) => Ok(()),
+            Some(_) => Err(format!("webhook answered {}", status_line.trim())),
+            None => Err("malformed webhook response".to_string()),
+        }
+    }
+}
+
+// sends a plain-text email to the recipient's address through an SMTP server
+pub struct EmailNotifier {
+    pub smtp_address: String,
+    pub from: String,
+    pub timeout: StdDuration,
+}
+
+// reads one SMTP reply (possibly multi-line) and checks its code
+fn expect_reply(reader: &mut BufReader<TcpStream>, expected: &str) -> std::result::Result<(), String> {
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).map_err(|e| format!("SMTP read failed: {}", e))?;
+        if line.is_empty() {
+            return Err("SMTP server closed the connection".to_string());
+        }
+        if !line.starts_with(expected) {
+            return Err(format!("SMTP server answered {}", line.trim()));
+        }
+        // "250-..." continues, "250 ..." ends the reply
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+fn smtp_command(reader: &mut BufReader<TcpStream>, command: &str, expected: &str) -> std::result::Result<(), String> {
+    reader
+        .get_mut()
+        .write_all(format!("{}\r\n", command).as_bytes())
+        .map_err(|e| format!("SMTP write failed: {}", e))?;
+    expect_reply(reader, expected)
+}
+
+impl Notifier for EmailNotifier {
+    fn channel(&self) -> NotificationChannel {
+        NotificationChannel::Email
+    }
+
+    fn send(&self, recipient: &Recipient, alert: &AlertNotification) -> std::result::Result<(), String> {
+        let to = recipient.address.as_deref().ok_or("no email address set")?;
+        let mut reader = BufReader::new(connect(&self.smtp_address, self.timeout)?);
+
+        expect_reply(&mut reader, "220")?;
+        smtp_command(&mut reader, "HELO glucoguard", "250")?;
+        smtp_command(&mut reader, &format!("MAIL FROM:<{}>", self.from), "250")?;
+        smtp_command(&mut reader, &format!("RCPT TO:<{}>", to), "250")?;
+        smtp_command(&mut reader, "DATA", "354")?;
+
+        // lines starting with "." are dot-stuffed so they cannot end the message early
+        let body = alert
+            .alert_message
+            .lines()
+            .map(|line| if line.starts_with('.') { format!(".{}", line) } else { line.to_string() })
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        let message = format!(
+            "From: GlucoGuard <{}>\r\nTo: <{}>\r\nSubject: [GlucoGuard] {}\r\n\r\n{}\r\n\r\nRaised at {} UTC (alert #{}).\r\n.",
+            self.from, to, alert.alert_type, body, alert.alert_time, alert.alert_id
+        );
+        smtp_command(&mut reader, &message, "250")?;
+        let _ = smtp_command(&mut reader, "QUIT", "221");
+        Ok(())
+    }
+}
+
+// one notifier per channel
+pub fn build_notifiers(settings: &NotifySettings) -> Vec<Box<dyn Notifier>> {
+    let timeout = StdDuration::from_secs(settings.timeout_secs.max(1));
+    vec![
+        Box::new(FileNotifier { path: settings.file_path.clone() }),
+        Box::new(WebhookNotifier { timeout }),
+        Box::new(EmailNotifier { smtp_address: settings.smtp_address.clone(), from: settings.smtp_from.clone(), timeout }),
+    ]
+}
+
+//-----------------------Channel preferences-----------------------//
+
+// one channel a user receives alerts on
+pub struct ChannelPreference {
+    pub channel: NotificationChannel,
+    pub address: Option<String>,
+}
+
+pub fn get_channel_preferences(conn: &Connection, user_id: &str) -> Result<Vec<ChannelPreference>> {
+    let mut stmt = conn.prepare(
+        "SELECT channel, address FROM notification_preferences WHERE user_id = ?1 AND enabled = 1 ORDER BY channel",
+    )?;
+    let rows = stmt.query_map(params![user_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))?;
+
+    let mut preferences = Vec::new();
+    for row in rows {
+        let (channel, address) = row?;
+        if let Some(channel) = NotificationChannel::from_db_value(&channel) {
+            preferences.push(ChannelPreference { channel, address });
+        }
+    }
+    Ok(preferences)
+}
+
+/// Turns a channel on (with its address) or off for the user. Alerts raised before a channel
+/// was turned on are not sent on it; changing the address of a channel that is already on keeps
+/// its original enable time.
+pub fn set_channel_preference(conn: &Connection, user_id: &str, channel: NotificationChannel, enabled: bool, address: Option<&str>) -> Result<usize> {
+    let now = get_current_db_time_string();
+    conn.execute(
+        "INSERT INTO notification_preferences (user_id, channel, enabled, address, updated_at, enabled_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, CASE WHEN ?3 THEN ?5 END)
+         ON CONFLICT(user_id, channel) DO UPDATE SET
+             enabled_at = CASE WHEN notification_preferences.enabled = 1 AND excluded.enabled = 1
+                               THEN notification_preferences.enabled_at
+                               ELSE excluded.enabled_at END,
+             enabled = excluded.enabled,
+             address = excluded.address,
+             updated_at = excluded.updated_at",
+        params![user_id, channel.as_db_value(), enabled, address, now],
+    )
+}
+
+//-----------------------Delivery-----------------------//
+
+fn record_delivery(conn: &Connection, alert_id: i64, user_id: &str, channel: NotificationChannel, status: &str, error: Option<&str>) -> Result<()> {
+    conn.execute(
+        "INSERT INTO alert_deliveries (alert_id, user_id, channel, status, error, attempted_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![alert_id, user_id, channel.as_db_value(), status, error, get_current_db_time_string()],
+    )?;
+    Ok(())
+}
+
+// status of the latest attempt to deliver the alert to the user on the channel
+fn get_last_delivery_status(conn: &Connection, alert_id: i64, user_id: &str, channel: NotificationChannel) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT status FROM alert_deliveries
+         WHERE alert_id = ?1 AND user_id = ?2 AND channel = ?3
+         ORDER BY delivery_id DESC
+         LIMIT 1",
+        params![alert_id, user_id, channel.as_db_value()],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Delivers open alerts on every channel their recipients have turned on. The patient receives
+/// all of their alerts and caretakers and clinicians the alerts escalated to them. A failed
+/// delivery is retried on later passes up to `MAX_DELIVERY_ATTEMPTS`. Alerts raised while snoozed
+/// are not sent, and during the recipient's quiet hours anything but an urgent low is held back
+/// (recorded once as skipped) and sent on the first pass after they end, if it is still open.
+/// Every attempt is recorded in `alert_deliveries`.
+/// Returns the number of notifications sent.
+pub fn dispatch_notifications(conn: &Connection, notifiers: &[Box<dyn Notifier>]) -> Result<usize> {
+    let mut stmt = conn.prepare(
+        "WITH recipients AS (
+             SELECT alert_id, patient_id AS user_id FROM alerts
+             UNION
+             SELECT alert_id, escalated_to FROM alert_escalations
+         )
+         SELECT a.alert_id, a.patient_id, a.alert_type, a.alert_message, a.alert_time,
+                u.id, u.user_name, n.channel, n.address
+         FROM recipients r
+         JOIN alerts a ON a.alert_id = r.alert_id
+         JOIN users u ON u.id = r.user_id
+         JOIN notification_preferences n ON n.user_id = u.id AND n.enabled = 1
+         WHERE a.is_resolved = 0 AND a.snoozed = 0
+           AND datetime(a.alert_time) >= datetime(n.enabled_at)
+           AND NOT EXISTS (
+               SELECT 1 FROM alert_deliveries d
+               WHERE d.alert_id = a.alert_id AND d.user_id = u.id AND d.channel = n.channel AND d.status = ?1
+           )
+           AND (SELECT COUNT(*) FROM alert_deliveries d
+                WHERE d.alert_id = a.alert_id AND d.user_id = u.id AND d.channel = n.channel AND d.status = ?2) < ?3
+         ORDER BY a.alert_id",
+    )?;
+    let pending = stmt
+        .query_map(params![DELIVERY_SENT, DELIVERY_FAILED, MAX_DELIVERY_ATTEMPTS], |row| {
+            Ok((
+                AlertNotification {
+                    alert_id: row.get(0)?,
+                    patient_id: row.get(1)?,
+                    alert_type: row.get(2)?,
+                    alert_message: row.get(3)?,
+                    alert_time: row.get(4)?,
+                },
+                Recipient { user_id: row.get(5)?, user_name: row.get(6)?, address: row.get(8)? },
+                row.get::<_, String>(7)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut sent = 0;
+    for (mut alert, recipient, channel) in pending {
+        let notifier = match notifiers.iter().find(|n| n.channel().as_db_value() == channel) {
+            Some(notifier) => notifier,
+            None => continue,
+        };
+        if !should_notify_user(conn, &recipient.user_id, &alert.alert_type)? {
+            // recorded once; later passes keep checking until the quiet hours end
+            if get_last_delivery_status(conn, alert.alert_id, &recipient.user_id, notifier.channel())?.as_deref() != Some(DELIVERY_SKIPPED) {
+                record_delivery(conn, alert.alert_id, &recipient.user_id, notifier.channel(), DELIVERY_SKIPPED, Some("quiet hours"))?;
+            }
+            continue;
+        }
+        // glucose values are sent in the recipient's display unit
+        alert.alert_message = get_user_glucose_unit(conn, &recipient.user_id)?.format_text(&alert.alert_message);
+        match notifier.send(&recipient, &alert) {
+            Ok(()) => {
+                record_delivery(conn, alert.alert_id, &recipient.user_id, notifier.channel(), DELIVERY_SENT, None)?;
+                sent += 1;
+            }
+            Err(e) => record_delivery(conn, alert.alert_id, &recipient.user_id, notifier.channel(), DELIVERY_FAILED, Some(&e))?,
+        }
+    }
+    Ok(sent)
+}
+
+// Run alert delivery in a background thread
+pub fn run_notification_dispatcher(db_path: &str, settings: NotifySettings) {
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || {
+        let notifiers = build_notifiers(&settings);
+        loop {
+            match Connection::open(&db_path) {
+                Ok(conn) => {
+                    let _ = conn.busy_timeout(StdDuration::from_secs(5));
+                    if let Err(e) = dispatch_notifications(&conn, &notifiers) {
+                        eprintln!("Alert delivery failed: {:?}", e);
+                    }
+                }
+                Err(e) => eprintln!("Failed to open DB connection for alert delivery: {:?}", e),
+            }
+            std::thread::sleep(StdDuration::from_secs(NOTIFY_INTERVAL_SECS));
+        }
+    });
+}
+
+// latest delivery attempt of an alert to one user on one channel
+pub struct AlertDelivery {
+    pub user_name: String,
+    pub channel: String,
+    pub status: String,
+    pub error: Option<String>,
+    pub attempts: i64,
+    pub attempted_at: NaiveDateTime,
+}
+
+// the latest attempt per recipient and channel, with the number of attempts made
+pub fn get_alert_deliveries(conn: &Connection, alert_id: i64) -> Result<Vec<AlertDelivery>> {
+    let mut stmt = conn.prepare(
+        "SELECT COALESCE(u.user_name, d.user_id), d.channel, d.status, d.error, d.attempted_at,
+                (SELECT COUNT(*) FROM alert_deliveries c
+                 WHERE c.alert_id = d.alert_id AND c.user_id = d.user_id AND c.channel = d.channel
+                   AND c.status != ?2)
+         FROM alert_deliveries d
+         LEFT JOIN users u ON u.id = d.user_id
+         WHERE d.delivery_id IN (
+             SELECT MAX(delivery_id) FROM alert_deliveries WHERE alert_id = ?1 GROUP BY user_id, channel
+         )
+         ORDER BY d.delivery_id",
+    )?;
+    let rows = stmt.query_map(params![alert_id, DELIVERY_SKIPPED], |row| {
+        Ok(AlertDelivery {
+            user_name: row.get(0)?,
+            channel: row.get(1)?,
+            status: row.get(2)?,
+            error: row.get(3)?,
+            attempted_at: NaiveDateTime::parse_from_str(&row.get::<_, String>(4)?, DB_TIME_FORMAT).unwrap_or_default(),
+            attempts: row.get(5)?,
+        })
+    })?;
+    rows.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_urls_split_into_address_and_path() {
+        assert_eq!(parse_http_url("http://example.com"), Some(("example.com:80".to_string(), "/".to_string())));
+        assert_eq!(
+            parse_http_url("http://localhost:8080/hooks/alerts"),
+            Some(("localhost:8080".to_string(), "/hooks/alerts".to_string()))
+        );
+    }
+
+    #[test]
+    fn only_plain_http_urls_with_a_host_are_accepted() {
+        assert_eq!(parse_http_url("https://example.com/hook"), None);
+        assert_eq!(parse_http_url("http:///hook"), None);
+        assert_eq!(parse_http_url("example.com"), None);
+    }
+}
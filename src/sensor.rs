@@ -0,0 +1,243 @@
+// CGM sensor sessions: serial number, warm-up, maximum wear time and expiry reminders
+use std::time::Duration as StdDuration;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use crate::alerts::insert_alert;
+use crate::config::env_or;
+use crate::utils::{format_local_time, DB_TIME_FORMAT};
+
+// alert_type raised ahead of the end of a sensor's wear time
+pub const SENSOR_EXPIRING_ALERT: &str = "Sensor Expiring";
+// alert_type raised once a sensor's wear time is over
+pub const SENSOR_EXPIRED_ALERT: &str = "Sensor Expired";
+// unusable_reason stored with readings taken while the sensor warms up
+pub const WARMUP_REASON: &str = "warm-up";
+// unusable_reason stored with readings taken after the sensor's wear time ended
+pub const EXPIRED_REASON: &str = "expired";
+// seconds between expiry checks
+const SENSOR_CHECK_INTERVAL_SECS: u64 = 300;
+
+// defaults for new sensor sessions; each session keeps the values it was started with
+pub struct SensorSettings {
+    pub warmup_minutes: i64,
+    pub max_wear_days: i64,
+    // hours before expiry that the reminder alert is raised
+    pub expiry_reminder_hours: i64,
+}
+
+impl SensorSettings {
+    pub fn from_env() -> Self {
+        Self {
+            warmup_minutes: env_or("GLUCOGUARD_SENSOR_WARMUP_MINUTES", 120),
+            max_wear_days: env_or("GLUCOGUARD_SENSOR_MAX_WEAR_DAYS", 10),
+            expiry_reminder_hours: env_or("GLUCOGUARD_SENSOR_EXPIRY_REMINDER_HOURS", 24),
+        }
+    }
+}
+
+// one row of sensor_sessions
+pub struct SensorSession {
+    pub sensor_session_id: i64,
+    pub serial_number: String,
+    pub inserted_at: NaiveDateTime,
+    pub warmup_minutes: i64,
+    pub expires_at: NaiveDateTime,
+}
+
+impl SensorSession {
+    pub fn warmup_ends_at(&self) -> NaiveDateTime {
+        self.inserted_at + Duration::minutes(self.warmup_minutes)
+    }
+
+    /// Why a reading taken at `time` by this sensor cannot be used, or `None` when it can.
+    pub fn unusable_reason(&self, time: &NaiveDateTime) -> Option<&'static str> {
+        if *time < self.warmup_ends_at() {
+            Some(WARMUP_REASON)
+        } else if *time >= self.expires_at {
+            Some(EXPIRED_REASON)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_db_time(value: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(value, DB_TIME_FORMAT).unwrap_or_default()
+}
+
+fn sensor_session_from_row(row: &rusqlite::Row) -> Result<SensorSession> {
+    Ok(SensorSession {
+        sensor_session_id: row.get(0)?,
+        serial_number: row.get(1)?,
+        inserted_at: parse_db_time(&row.get::<_, String>(2)?),
+        warmup_minutes: row.get(3)?,
+        expires_at: parse_db_time(&row.get::<_, String>(4)?),
+    })
+}
+
+/// Starts a new sensor session for the patient, ending the one in progress.
+/// Returns the new sensor_session_id.
+pub fn start_sensor_session(conn: &Connection, patient_id: &str, serial_number: &str, started_by: &str, settings: &SensorSettings) -> Result<i64> {
+    let now = Utc::now().naive_utc();
+    let now_str = now.format(DB_TIME_FORMAT).to_string();
+    let expires_at = (now + Duration::days(settings.max_wear_days)).format(DB_TIME_FORMAT).to_string();
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE sensor_sessions SET ended_at = ?1 WHERE patient_id = ?2 AND ended_at IS NULL",
+        params![now_str, patient_id],
+    )?;
+    tx.execute(
+        "INSERT INTO sensor_sessions (patient_id, serial_number, inserted_at, warmup_minutes, expires_at, ended_at, started_by)
+         VALUES (?1, ?2, ?3, ?4, ?5, NULL, ?6)",
+        params![patient_id, serial_number, now_str, settings.warmup_minutes, expires_at, started_by],
+    )?;
+    let sensor_session_id = tx.last_insert_rowid();
+    tx.commit()?;
+    Ok(sensor_session_id)
+}
+
+// the sensor currently worn by the patient, if any
+pub fn get_current_sensor_session(conn: &Connection, patient_id: &str) -> Result<Option<SensorSession>> {
+    conn.query_row(
+        "SELECT sensor_session_id, serial_number, inserted_at, warmup_minutes, expires_at
+         FROM sensor_sessions
+         WHERE patient_id = ?1 AND ended_at IS NULL
+         ORDER BY datetime(inserted_at) DESC, sensor_session_id DESC
+         LIMIT 1",
+        params![patient_id],
+        sensor_session_from_row,
+    )
+    .optional()
+}
+
+/// The sensor that was worn at `time` (DB_TIME_FORMAT), i.e. the one that produced a reading taken then.
+pub fn get_sensor_session_at(conn: &Connection, patient_id: &str, time: &str) -> Result<Option<SensorSession>> {
+    conn.query_row(
+        "SELECT sensor_session_id, serial_number, inserted_at, warmup_minutes, expires_at
+         FROM sensor_sessions
+         WHERE patient_id = ?1
+           AND datetime(inserted_at) <= datetime(?2)
+           AND (ended_at IS NULL OR datetime(ended_at) > datetime(?2))
+         ORDER BY datetime(inserted_at) DESC, sensor_session_id DESC
+         LIMIT 1",
+        params![patient_id, time],
+        sensor_session_from_row,
+    )
+    .optional()
+}
+
+// number of alerts of a type raised for the patient since the sensor was inserted
+fn count_alerts_since(conn: &Connection, patient_id: &str, alert_type: &str, since: &NaiveDateTime) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM alerts
+         WHERE patient_id = ?1 AND alert_type = ?2 AND datetime(alert_time) >= datetime(?3)",
+        params![patient_id, alert_type, since.format(DB_TIME_FORMAT).to_string()],
+        |row| row.get(0),
+    )
+}
+
+/// Raises a reminder `expiry_reminder_hours` before the current sensor expires and another alert
+/// once it has expired; each is raised once per sensor. Returns true when an alert was raised.
+pub fn evaluate_sensor_expiry(conn: &Connection, patient_id: &str, settings: &SensorSettings) -> Result<bool> {
+    let sensor = match get_current_sensor_session(conn, patient_id)? {
+        Some(sensor) => sensor,
+        None => return Ok(false),
+    };
+    let now = Utc::now().naive_utc();
+
+    let (alert_type, message) = if now >= sensor.expires_at {
+        (
+            SENSOR_EXPIRED_ALERT,
+            format!("CGM sensor {} has reached its maximum wear time; its readings are no longer used. Replace the sensor.", sensor.serial_number),
+        )
+    } else if now >= sensor.expires_at - Duration::hours(settings.expiry_reminder_hours) {
+        (
+            SENSOR_EXPIRING_ALERT,
+            format!(
+                "CGM sensor {} expires in {} hours ({}). Have a replacement ready.",
+                sensor.serial_number,
+                (sensor.expires_at - now).num_hours(),
+                format_local_time(&sensor.expires_at)
+            ),
+        )
+    } else {
+        return Ok(false);
+    };
+
+    if count_alerts_since(conn, patient_id, alert_type, &sensor.inserted_at)? > 0 {
+        return Ok(false);
+    }
+    insert_alert(conn, patient_id, alert_type, &message)?;
+    Ok(true)
+}
+
+// checks every patient's sensor for expiry
+pub fn evaluate_sensor_expiry_for_all_patients(conn: &Connection, settings: &SensorSettings) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT DISTINCT patient_id FROM sensor_sessions WHERE ended_at IS NULL")?;
+    let patient_ids = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?;
+
+    for patient_id in patient_ids {
+        if let Err(e) = evaluate_sensor_expiry(conn, &patient_id, settings) {
+            eprintln!("Sensor expiry check failed for patient {}: {}", patient_id, e);
+        }
+    }
+    Ok(())
+}
+
+// Run the sensor expiry check in a background thread
+pub fn run_sensor_monitor(db_path: &str, settings: SensorSettings) {
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || loop {
+        match Connection::open(&db_path) {
+            Ok(conn) => {
+                let _ = conn.busy_timeout(StdDuration::from_secs(5));
+                if let Err(e) = evaluate_sensor_expiry_for_all_patients(&conn, &settings) {
+                    eprintln!("Sensor expiry check failed: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to open DB connection for sensor monitor: {:?}", e),
+        }
+        std::thread::sleep(StdDuration::from_secs(SENSOR_CHECK_INTERVAL_SECS));
+    });
+}
+
+// prints the current sensor's serial number, warm-up state and remaining wear time
+pub fn display_sensor_status(conn: &Connection, patient_id: &str) {
+    println!("\n--- CGM Sensor ---");
+    let sensor = match get_current_sensor_session(conn, patient_id) {
+        Ok(Some(sensor)) => sensor,
+        Ok(None) => {
+            println!("No sensor session has been started.");
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error retrieving sensor session: {}", e);
+            return;
+        }
+    };
+
+    let now = Utc::now().naive_utc();
+    println!("Serial number: {}", sensor.serial_number);
+    println!("Inserted: {}", format_local_time(&sensor.inserted_at));
+    if now < sensor.warmup_ends_at() {
+        println!(
+            "Warming up: readings are not used until {} ({} minutes left)",
+            format_local_time(&sensor.warmup_ends_at()),
+            (sensor.warmup_ends_at() - now).num_minutes() + 1
+        );
+    }
+    if now >= sensor.expires_at {
+        println!("EXPIRED at {}: readings are no longer used. Replace the sensor.", format_local_time(&sensor.expires_at));
+    } else {
+        let left = sensor.expires_at - now;
+        println!(
+            "Expires: {} ({} days {} hours left)",
+            format_local_time(&sensor.expires_at),
+            left.num_days(),
+            left.num_hours() % 24
+        );
+    }
+}
@@ -0,0 +1,173 @@
+// CGM signal-loss and data-gap detection
+use std::time::Duration as StdDuration;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use crate::alerts::insert_alert;
+use crate::config::env_or;
+use crate::utils::DB_TIME_FORMAT;
+
+// alert_type raised when readings stop arriving
+pub const SIGNAL_LOSS_ALERT: &str = "Signal Loss";
+// an interval longer than this multiple of the cadence means at least one reading went missing
+const GAP_CADENCE_MULTIPLIER: f32 = 1.5;
+// automated dosing stops once this many expected readings in a row are missing
+const STALE_MISSED_READINGS: i64 = 3;
+
+// expected CGM cadence and signal-loss threshold; both can be overridden from the environment
+pub struct SignalSettings {
+    // minutes between readings from a working sensor
+    pub cadence_minutes: i64,
+    // minutes without a reading before a signal loss alert is raised
+    pub signal_loss_minutes: i64,
+}
+
+impl SignalSettings {
+    pub fn from_env() -> Self {
+        Self {
+            cadence_minutes: env_or("GLUCOGUARD_CGM_CADENCE_MINUTES", 5),
+            signal_loss_minutes: env_or("GLUCOGUARD_SIGNAL_LOSS_MINUTES", 20),
+        }
+    }
+
+    // readings further apart than this are shown as a gap
+    pub fn gap_threshold(&self) -> Duration {
+        Duration::seconds((self.cadence_minutes as f32 * 60.0 * GAP_CADENCE_MULTIPLIER) as i64)
+    }
+
+    // data older than this is too stale for automated dosing
+    pub fn stale_after_minutes(&self) -> i64 {
+        self.cadence_minutes * STALE_MISSED_READINGS
+    }
+}
+
+// a stretch without readings, between the reading before it and the reading after it
+pub struct DataGap {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl DataGap {
+    pub fn minutes(&self) -> i64 {
+        (self.end - self.start).num_minutes()
+    }
+}
+
+/// Gaps between consecutive reading times (in any order) longer than the settings' gap threshold.
+pub fn find_gaps(reading_times: &[NaiveDateTime], settings: &SignalSettings) -> Vec<DataGap> {
+    let mut times = reading_times.to_vec();
+    times.sort();
+    times
+        .windows(2)
+        .filter(|pair| pair[1] - pair[0] > settings.gap_threshold())
+        .map(|pair| DataGap { start: pair[0], end: pair[1] })
+        .collect()
+}
+
+// time of the patient's most recent reading, if any
+pub fn get_latest_reading_time(conn: &Connection, patient_id: &str) -> Result<Option<NaiveDateTime>> {
+    let latest: Option<String> = conn
+        .query_row(
+            "SELECT MAX(datetime(reading_time)) FROM glucose_readings WHERE patient_id = ?1",
+            params![patient_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    Ok(latest.and_then(|t| NaiveDateTime::parse_from_str(&t, DB_TIME_FORMAT).ok()))
+}
+
+/// Minutes since the patient's latest reading, or `None` when there has never been one.
+pub fn minutes_since_last_reading(conn: &Connection, patient_id: &str) -> Result<Option<i64>> {
+    let latest = get_latest_reading_time(conn, patient_id)?;
+    Ok(latest.map(|time| (Utc::now().naive_utc() - time).num_minutes()))
+}
+
+/// True when the latest reading is older than `max_age_minutes` or there are no readings at all.
+/// Automated dosing must not act on stale data.
+pub fn is_glucose_data_stale(conn: &Connection, patient_id: &str, max_age_minutes: i64) -> Result<bool> {
+    Ok(minutes_since_last_reading(conn, patient_id)?.is_none_or(|minutes| minutes > max_age_minutes))
+}
+
+/// Raises a signal loss alert when no reading has arrived for `signal_loss_minutes`.
+/// Only one alert is raised per outage: none is added if one was already raised after the latest reading.
+/// Returns true when an alert was raised.
+pub fn evaluate_signal_loss(conn: &Connection, patient_id: &str, settings: &SignalSettings) -> Result<bool> {
+    let latest = match get_latest_reading_time(conn, patient_id)? {
+        Some(time) => time,
+        // a patient who never had a sensor has no signal to lose
+        None => return Ok(false),
+    };
+    let minutes = (Utc::now().naive_utc() - latest).num_minutes();
+    if minutes < settings.signal_loss_minutes {
+        return Ok(false);
+    }
+
+    let already_alerted: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM alerts
+         WHERE patient_id = ?1 AND alert_type = ?2 AND datetime(alert_time) >= datetime(?3)",
+        params![patient_id, SIGNAL_LOSS_ALERT, latest.format(DB_TIME_FORMAT).to_string()],
+        |row| row.get(0),
+    )?;
+    if already_alerted > 0 {
+        return Ok(false);
+    }
+
+    insert_alert(
+        conn,
+        patient_id,
+        SIGNAL_LOSS_ALERT,
+        &format!("No CGM reading for {} minutes; automated dosing is paused until data returns", minutes),
+    )?;
+    Ok(true)
+}
+
+// checks every patient for signal loss
+pub fn evaluate_signal_loss_for_all_patients(conn: &Connection, settings: &SignalSettings) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT patient_id FROM patients")?;
+    let patient_ids = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?;
+
+    for patient_id in patient_ids {
+        if let Err(e) = evaluate_signal_loss(conn, &patient_id, settings) {
+            eprintln!("Signal loss check failed for patient {}: {}", patient_id, e);
+        }
+    }
+    Ok(())
+}
+
+// Run the signal loss check in a background thread once per expected reading
+pub fn run_signal_monitor(db_path: &str, settings: SignalSettings) {
+    let db_path = db_path.to_string();
+    std::thread::spawn(move || loop {
+        match Connection::open(&db_path) {
+            Ok(conn) => {
+                let _ = conn.busy_timeout(StdDuration::from_secs(5));
+                if let Err(e) = evaluate_signal_loss_for_all_patients(&conn, &settings) {
+                    eprintln!("Signal loss check failed: {:?}", e);
+                }
+            }
+            Err(e) => eprintln!("Failed to open DB connection for signal monitor: {:?}", e),
+        }
+        std::thread::sleep(StdDuration::from_secs(settings.cadence_minutes.max(1) as u64 * 60));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(minutes: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 1).unwrap().and_hms_opt(8, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn gaps_longer_than_one_and_a_half_cadences() {
+        let settings = SignalSettings { cadence_minutes: 5, signal_loss_minutes: 20 };
+        let gaps = find_gaps(&[at(25), at(0), at(5), at(12), at(30)], &settings);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!((gaps[0].start, gaps[0].end), (at(12), at(25)));
+        assert_eq!(gaps[0].minutes(), 13);
+    }
+}
@@ -0,0 +1,204 @@
+// Alert suppression: per-alert-type snooze and per-user quiet hours
+use chrono::{Duration, Local, NaiveDateTime, NaiveTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Result, params, params_from_iter};
+use crate::alerts::{HIGH_GLUCOSE_ALERT, LOW_GLUCOSE_ALERT, URGENT_LOW_ALERT};
+use crate::config::env_or;
+use crate::utils::{get_current_db_time_string, DB_TIME_FORMAT};
+
+// quiet hours are entered and stored as local HH:MM
+const QUIET_HOURS_FORMAT: &str = "%H:%M";
+
+// how long snoozing each alert type silences it; urgent lows cannot be snoozed
+pub struct SnoozeSettings {
+    pub low_minutes: i64,
+    pub high_minutes: i64,
+    // every other alert type (sensor, signal loss, forecast, ...)
+    pub other_minutes: i64,
+}
+
+impl SnoozeSettings {
+    pub fn from_env() -> Self {
+        Self {
+            low_minutes: env_or("GLUCOGUARD_SNOOZE_LOW_MINUTES", 30),
+            high_minutes: env_or("GLUCOGUARD_SNOOZE_HIGH_MINUTES", 120),
+            other_minutes: env_or("GLUCOGUARD_SNOOZE_OTHER_MINUTES", 60),
+        }
+    }
+
+    pub fn minutes_for(&self, alert_type: &str) -> i64 {
+        match alert_type {
+            LOW_GLUCOSE_ALERT => self.low_minutes,
+            HIGH_GLUCOSE_ALERT => self.high_minutes,
+            _ => self.other_minutes,
+        }
+    }
+}
+
+// urgent lows are always notified and escalated, like during quiet hours
+pub fn can_snooze(alert_type: &str) -> bool {
+    alert_type != URGENT_LOW_ALERT
+}
+
+/// Silences notifications of new alerts of `alert_type` for the patient for that type's snooze
+/// duration; the alerts are still recorded. Returns when the snooze ends, or None for a type that
+/// cannot be snoozed.
+pub fn snooze_alert_type(conn: &Connection, patient_id: &str, alert_type: &str, snoozed_by: &str, settings: &SnoozeSettings) -> Result<Option<NaiveDateTime>> {
+    if !can_snooze(alert_type) {
+        return Ok(None);
+    }
+    let until = Utc::now().naive_utc() + Duration::minutes(settings.minutes_for(alert_type));
+    conn.execute(
+        "INSERT INTO alert_snoozes (patient_id, alert_type, snoozed_until, snoozed_by, snoozed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(patient_id, alert_type) DO UPDATE SET
+             snoozed_until = excluded.snoozed_until,
+             snoozed_by = excluded.snoozed_by,
+             snoozed_at = excluded.snoozed_at",
+        params![patient_id, alert_type, until.format(DB_TIME_FORMAT).to_string(), snoozed_by, get_current_db_time_string()],
+    )?;
+    Ok(Some(until))
+}
+
+// true while new alerts of this type are snoozed for the patient
+pub fn is_snoozed(conn: &Connection, patient_id: &str, alert_type: &str) -> Result<bool> {
+    if !can_snooze(alert_type) {
+        return Ok(false);
+    }
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM alert_snoozes
+         WHERE patient_id = ?1 AND alert_type = ?2 AND datetime(snoozed_until) > datetime(?3)",
+        params![patient_id, alert_type, get_current_db_time_string()],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+// snoozes in effect for the given patients as (patient_id, alert_type, snoozed_until)
+pub fn get_active_snoozes(conn: &Connection, patient_ids: &[String]) -> Result<Vec<(String, String, NaiveDateTime)>> {
+    if patient_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let placeholders = (2..=patient_ids.len() + 1).map(|i| format!("?{}", i)).collect::<Vec<_>>().join(", ");
+    let sql = format!(
+        "SELECT patient_id, alert_type, snoozed_until FROM alert_snoozes
+         WHERE datetime(snoozed_until) > datetime(?1) AND patient_id IN ({})
+         ORDER BY alert_type",
+        placeholders
+    );
+    let mut values = vec![get_current_db_time_string()];
+    values.extend(patient_ids.iter().cloned());
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params_from_iter(values), |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            NaiveDateTime::parse_from_str(&row.get::<_, String>(2)?, DB_TIME_FORMAT).unwrap_or_default(),
+        ))
+    })?;
+    rows.collect()
+}
+
+/// Local time window in which a user is not notified of alerts other than urgent lows.
+/// A window whose end is before its start runs overnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{}–{}", self.start.format(QUIET_HOURS_FORMAT), self.end.format(QUIET_HOURS_FORMAT))
+    }
+}
+
+// the user's quiet hours, if they set any
+pub fn get_user_quiet_hours(conn: &Connection, user_id: &str) -> Result<Option<QuietHours>> {
+    let stored: Option<(Option<String>, Option<String>)> = conn
+        .query_row(
+            "SELECT quiet_hours_start, quiet_hours_end FROM users WHERE id = ?1",
+            params![user_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(match stored {
+        Some((Some(start), Some(end))) => match (
+            NaiveTime::parse_from_str(&start, QUIET_HOURS_FORMAT),
+            NaiveTime::parse_from_str(&end, QUIET_HOURS_FORMAT),
+        ) {
+            (Ok(start), Ok(end)) => Some(QuietHours { start, end }),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+// stores the user's quiet hours; None turns them off
+pub fn set_user_quiet_hours(conn: &Connection, user_id: &str, quiet_hours: Option<QuietHours>) -> Result<usize> {
+    let (start, end) = match quiet_hours {
+        Some(q) => (
+            Some(q.start.format(QUIET_HOURS_FORMAT).to_string()),
+            Some(q.end.format(QUIET_HOURS_FORMAT).to_string()),
+        ),
+        None => (None, None),
+    };
+    conn.execute(
+        "UPDATE users SET quiet_hours_start = ?1, quiet_hours_end = ?2 WHERE id = ?3",
+        params![start, end, user_id],
+    )
+}
+
+/// Whether the user should be notified of an alert of `alert_type` now: urgent lows always,
+/// anything else only outside the user's quiet hours.
+pub fn should_notify_user(conn: &Connection, user_id: &str, alert_type: &str) -> Result<bool> {
+    if alert_type == URGENT_LOW_ALERT {
+        return Ok(true);
+    }
+    Ok(match get_user_quiet_hours(conn, user_id)? {
+        Some(quiet_hours) => !quiet_hours.contains(Local::now().time()),
+        None => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(value: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(value, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn quiet_hours_within_one_day() {
+        let quiet = QuietHours { start: time("13:00"), end: time("15:00") };
+        assert!(quiet.contains(time("13:00")));
+        assert!(quiet.contains(time("14:59")));
+        assert!(!quiet.contains(time("15:00")));
+        assert!(!quiet.contains(time("09:00")));
+    }
+
+    #[test]
+    fn quiet_hours_across_midnight() {
+        let quiet = QuietHours { start: time("22:00"), end: time("07:00") };
+        assert!(quiet.contains(time("23:30")));
+        assert!(quiet.contains(time("06:59")));
+        assert!(!quiet.contains(time("07:00")));
+        assert!(!quiet.contains(time("12:00")));
+    }
+
+    #[test]
+    fn urgent_lows_cannot_be_snoozed() {
+        assert!(!can_snooze(URGENT_LOW_ALERT));
+        assert!(can_snooze(LOW_GLUCOSE_ALERT));
+        assert!(can_snooze(HIGH_GLUCOSE_ALERT));
+    }
+}
@@ -0,0 +1,126 @@
+// Predictive low-glucose suspend of basal delivery
+use chrono::NaiveDateTime;
+use rusqlite::{Connection, OptionalExtension, Result, params};
+use crate::alerts::insert_alert;
+use crate::filter::GlucoseSourceSettings;
+use crate::insulin::{get_patient_data_from_patient_table, get_recent_glucose_readings, insert_insulin_log};
+
+// insulin_logs action types for suspend / resume events
+pub const SUSPEND_ACTION: &str = "BasalSuspend";
+pub const RESUME_ACTION: &str = "BasalResume";
+// requested_by recorded on suspend / resume events
+pub const SUSPEND_REQUESTER: &str = "low_glucose_suspend";
+// alert_type raised when basal is suspended
+pub const PREDICTED_LOW_ALERT: &str = "Predicted Low";
+
+// suspend when glucose is predicted to cross the low threshold within this many minutes
+pub const PREDICTION_HORIZON_MINUTES: f32 = 30.0;
+// readings used to estimate the trend
+const TREND_WINDOW_MINUTES: i64 = 30;
+
+// what the suspend check decided for this cycle
+pub enum SuspendAction {
+    Suspended,
+    Resumed,
+    NoChange,
+}
+
+/// Least-squares slope (mg/dL per minute) of readings given oldest first.
+/// Returns `None` with fewer than two readings or when they share one timestamp.
+pub fn glucose_slope(readings: &[(NaiveDateTime, f32)]) -> Option<f32> {
+    if readings.len() < 2 {
+        return None;
+    }
+    let origin = readings[0].0;
+    let points: Vec<(f32, f32)> = readings
+        .iter()
+        .map(|(time, glucose)| ((*time - origin).num_seconds() as f32 / 60.0, *glucose))
+        .collect();
+
+    let n = points.len() as f32;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f32>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / n;
+    let variance: f32 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if variance == 0.0 {
+        return None;
+    }
+    let covariance: f32 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    Some(covariance / variance)
+}
+
+// true while the most recent suspend/resume event for the patient is a suspend
+pub fn is_basal_suspended(conn: &Connection, patient_id: &str) -> Result<bool> {
+    let last_event: Option<String> = conn
+        .query_row(
+            "SELECT action_type FROM insulin_logs
+             WHERE patient_id = ?1 AND action_type IN (?2, ?3)
+             ORDER BY datetime(dosage_time) DESC, dosage_id DESC
+             LIMIT 1",
+            params![patient_id, SUSPEND_ACTION, RESUME_ACTION],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(last_event.as_deref() == Some(SUSPEND_ACTION))
+}
+
+/// Suspends basal when recent CGM data predicts glucose below `low_glucose_threshold` within
+/// 30 minutes, and resumes once glucose is above the threshold, rising, and no longer predicted low.
+/// Each suspend and resume is logged in insulin_logs; a suspend also raises an alert.
+pub fn evaluate_low_glucose_suspend(conn: &Connection, patient_id: &str) -> Result<SuspendAction> {
+    let patient = match get_patient_data_from_patient_table(conn, patient_id)? {
+        Some(p) => p,
+        None => return Ok(SuspendAction::NoChange),
+    };
+
+    let readings = get_recent_glucose_readings(conn, patient_id, TREND_WINDOW_MINUTES, GlucoseSourceSettings::from_env().dosing)?;
+    let (latest, slope) = match (readings.last(), glucose_slope(&readings)) {
+        (Some((_, latest)), Some(slope)) => (*latest, slope),
+        _ => return Ok(SuspendAction::NoChange),
+    };
+    let predicted = latest + slope * PREDICTION_HORIZON_MINUTES;
+    let low = patient.low_glucose_threshold;
+    let suspended = is_basal_suspended(conn, patient_id)?;
+
+    if !suspended && (latest < low || predicted < low) {
+        let reason = format!(
+            "glucose {:.1} mg/dL, trend {:+.2} mg/dL/min, predicted {:.1} mg/dL in {:.0} min (low threshold {:.1} mg/dL): basal suspended",
+            latest, slope, predicted, PREDICTION_HORIZON_MINUTES, low
+        );
+        insert_insulin_log(conn, patient_id, SUSPEND_ACTION, 0.0, SUSPEND_REQUESTER, Some(&reason))?;
+        insert_alert(conn, patient_id, PREDICTED_LOW_ALERT, &format!("Basal insulin suspended: {}", reason))?;
+        return Ok(SuspendAction::Suspended);
+    }
+
+    if suspended && latest >= low && predicted >= low && slope >= 0.0 {
+        let reason = format!(
+            "glucose {:.1} mg/dL, trend {:+.2} mg/dL/min, predicted {:.1} mg/dL in {:.0} min: basal resumed",
+            latest, slope, predicted, PREDICTION_HORIZON_MINUTES
+        );
+        insert_insulin_log(conn, patient_id, RESUME_ACTION, 0.0, SUSPEND_REQUESTER, Some(&reason))?;
+        return Ok(SuspendAction::Resumed);
+    }
+
+    Ok(SuspendAction::NoChange)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    fn at(minutes: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 1).unwrap().and_hms_opt(8, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn slope_is_mg_dl_per_minute() {
+        let slope = glucose_slope(&[(at(0), 100.0), (at(5), 110.0), (at(10), 120.0)]).unwrap();
+        assert!((slope - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn slope_needs_two_distinct_times() {
+        assert_eq!(glucose_slope(&[(at(0), 100.0)]), None);
+        assert_eq!(glucose_slope(&[(at(0), 100.0), (at(0), 120.0)]), None);
+    }
+}
@@ -0,0 +1,134 @@
+// Glucose rate of change and CGM-style trend arrows
+use chrono::{Duration, NaiveDateTime};
+use rusqlite::{Connection, Result, params};
+use crate::filter::GlucoseSource;
+use crate::suspend::glucose_slope;
+use crate::units::GlucoseUnit;
+use crate::utils::DB_TIME_FORMAT;
+
+// readings this far back from the latest one are used for the rate of change
+pub const TREND_WINDOW_MINUTES: i64 = 15;
+// upper bound on how many recent readings are fetched for a trend
+const TREND_READING_LIMIT: i64 = 20;
+
+// standard CGM trend arrows; thresholds are in mg/dL per minute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrendArrow {
+    RisingQuickly,
+    Rising,
+    RisingSlowly,
+    Steady,
+    FallingSlowly,
+    Falling,
+    FallingQuickly,
+}
+
+impl TrendArrow {
+    // > 3 rising quickly, 2–3 rising, 1–2 rising slowly, within ±1 steady, and the same falling
+    pub fn from_rate(rate_per_minute: f32) -> Self {
+        match rate_per_minute {
+            r if r > 3.0 => TrendArrow::RisingQuickly,
+            r if r > 2.0 => TrendArrow::Rising,
+            r if r > 1.0 => TrendArrow::RisingSlowly,
+            r if r >= -1.0 => TrendArrow::Steady,
+            r if r >= -2.0 => TrendArrow::FallingSlowly,
+            r if r >= -3.0 => TrendArrow::Falling,
+            _ => TrendArrow::FallingQuickly,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TrendArrow::RisingQuickly => "↑↑",
+            TrendArrow::Rising => "↑",
+            TrendArrow::RisingSlowly => "↗",
+            TrendArrow::Steady => "→",
+            TrendArrow::FallingSlowly => "↘",
+            TrendArrow::Falling => "↓",
+            TrendArrow::FallingQuickly => "↓↓",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TrendArrow::RisingQuickly => "rising quickly",
+            TrendArrow::Rising => "rising",
+            TrendArrow::RisingSlowly => "rising slowly",
+            TrendArrow::Steady => "steady",
+            TrendArrow::FallingSlowly => "falling slowly",
+            TrendArrow::Falling => "falling",
+            TrendArrow::FallingQuickly => "falling quickly",
+        }
+    }
+}
+
+// rate of change at the latest reading
+#[derive(Debug, Clone, Copy)]
+pub struct GlucoseTrend {
+    pub rate_per_minute: f32,
+    pub arrow: TrendArrow,
+}
+
+impl GlucoseTrend {
+    // e.g. "↗ +1.4 mg/dL/min (rising slowly)"
+    pub fn describe(&self, unit: GlucoseUnit) -> String {
+        format!("{} {}/min ({})", self.arrow.symbol(), unit.format_delta(self.rate_per_minute), self.arrow.description())
+    }
+}
+
+/// Trend at the latest of `readings` (oldest first), from the least-squares slope of the readings
+/// within `TREND_WINDOW_MINUTES` of it. Returns `None` with fewer than two readings in that window.
+pub fn glucose_trend(readings: &[(NaiveDateTime, f32)]) -> Option<GlucoseTrend> {
+    let (latest_time, _) = *readings.last()?;
+    let window_start = latest_time - Duration::minutes(TREND_WINDOW_MINUTES);
+    let window: Vec<(NaiveDateTime, f32)> = readings
+        .iter()
+        .filter(|(time, _)| *time >= window_start)
+        .copied()
+        .collect();
+
+    let rate_per_minute = glucose_slope(&window)?;
+    Some(GlucoseTrend { rate_per_minute, arrow: TrendArrow::from_rate(rate_per_minute) })
+}
+
+/// Current glucose trend for a patient from the given source of readings.
+/// Returns `Ok(None)` when there are not enough recent readings to compute one.
+pub fn get_glucose_trend(conn: &Connection, patient_id: &str, source: GlucoseSource) -> Result<Option<GlucoseTrend>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT reading_time, {} FROM glucose_readings
+         WHERE patient_id = ?1 AND unusable_reason IS NULL
+         ORDER BY datetime(reading_time) DESC
+         LIMIT ?2",
+        source.column()
+    ))?;
+    let rows = stmt.query_map(params![patient_id, TREND_READING_LIMIT], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f32>(1)?))
+    })?;
+
+    let mut readings = Vec::new();
+    for row in rows {
+        let (time_str, glucose) = row?;
+        if let Ok(reading_time) = NaiveDateTime::parse_from_str(&time_str, DB_TIME_FORMAT) {
+            readings.push((reading_time, glucose));
+        }
+    }
+    readings.reverse();
+    Ok(glucose_trend(&readings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_maps_to_arrow_at_the_boundaries() {
+        assert_eq!(TrendArrow::from_rate(3.5), TrendArrow::RisingQuickly);
+        assert_eq!(TrendArrow::from_rate(3.0), TrendArrow::Rising);
+        assert_eq!(TrendArrow::from_rate(1.5), TrendArrow::RisingSlowly);
+        assert_eq!(TrendArrow::from_rate(1.0), TrendArrow::Steady);
+        assert_eq!(TrendArrow::from_rate(-1.0), TrendArrow::Steady);
+        assert_eq!(TrendArrow::from_rate(-1.5), TrendArrow::FallingSlowly);
+        assert_eq!(TrendArrow::from_rate(-3.0), TrendArrow::Falling);
+        assert_eq!(TrendArrow::from_rate(-3.5), TrendArrow::FallingQuickly);
+    }
+}
@@ -0,0 +1,127 @@
+// Glucose display units; values are always stored in mg/dL and converted only for display and input
+use std::sync::LazyLock;
+use regex::{Captures, Regex};
+use rusqlite::{Connection, OptionalExtension, Result, params};
+
+// mg/dL in one mmol/L of glucose
+pub const MG_DL_PER_MMOL_L: f32 = 18.0182;
+
+// "<value> mg/dL" or "<value> mg/dL/min" in a stored message, with an optional sign
+static GLUCOSE_IN_TEXT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([+-]?)(\d+(?:\.\d+)?) mg/dL(/min)?").expect("valid glucose pattern"));
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GlucoseUnit {
+    #[default]
+    MgDl,
+    MmolL,
+}
+
+impl GlucoseUnit {
+    // value stored in users.glucose_unit; anything unrecognised falls back to mg/dL
+    pub fn from_db_value(value: &str) -> Self {
+        match value {
+            "mmol/L" => GlucoseUnit::MmolL,
+            _ => GlucoseUnit::MgDl,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GlucoseUnit::MgDl => "mg/dL",
+            GlucoseUnit::MmolL => "mmol/L",
+        }
+    }
+
+    // converts a stored mg/dL value to this unit
+    pub fn display_value(&self, mg_dl: f32) -> f32 {
+        match self {
+            GlucoseUnit::MgDl => mg_dl,
+            GlucoseUnit::MmolL => mg_dl / MG_DL_PER_MMOL_L,
+        }
+    }
+
+    // converts a value entered in this unit to mg/dL for storage
+    pub fn to_mg_dl(self, value: f32) -> f32 {
+        match self {
+            GlucoseUnit::MgDl => value,
+            GlucoseUnit::MmolL => value * MG_DL_PER_MMOL_L,
+        }
+    }
+
+    // number without the unit, e.g. "104.0" or "5.8"
+    pub fn format_value(&self, mg_dl: f32) -> String {
+        format!("{:.1}", self.display_value(mg_dl))
+    }
+
+    // e.g. "104.0 mg/dL" or "5.8 mmol/L"
+    pub fn format(&self, mg_dl: f32) -> String {
+        format!("{} {}", self.format_value(mg_dl), self.label())
+    }
+
+    // signed difference, e.g. "+4.0 mg/dL" or "+0.22 mmol/L"
+    pub fn format_delta(&self, mg_dl: f32) -> String {
+        match self {
+            GlucoseUnit::MgDl => format!("{:+.1} {}", mg_dl, self.label()),
+            GlucoseUnit::MmolL => format!("{:+.2} {}", self.display_value(mg_dl), self.label()),
+        }
+    }
+
+    /// Rewrites the glucose values in a stored message (alert text, dosing reason) in this unit.
+    /// Stored messages give every glucose value as "<value> mg/dL"; signed values are differences
+    /// and "<value> mg/dL/min" is a rate of change.
+    pub fn format_text(&self, text: &str) -> String {
+        if *self == GlucoseUnit::MgDl {
+            return text.to_string();
+        }
+        GLUCOSE_IN_TEXT
+            .replace_all(text, |caps: &Captures| {
+                let magnitude: f32 = caps[2].parse().unwrap_or_default();
+                let mg_dl = if &caps[1] == "-" { -magnitude } else { magnitude };
+                if caps.get(3).is_some() {
+                    format!("{}/min", self.format_delta(mg_dl))
+                } else if caps[1].is_empty() {
+                    self.format(mg_dl)
+                } else {
+                    self.format_delta(mg_dl)
+                }
+            })
+            .into_owned()
+    }
+}
+
+// the user's preferred display unit (mg/dL when unset)
+pub fn get_user_glucose_unit(conn: &Connection, user_id: &str) -> Result<GlucoseUnit> {
+    let unit: Option<String> = conn
+        .query_row("SELECT glucose_unit FROM users WHERE id = ?1", params![user_id], |row| row.get(0))
+        .optional()?;
+    Ok(unit.map_or(GlucoseUnit::default(), |u| GlucoseUnit::from_db_value(&u)))
+}
+
+pub fn update_user_glucose_unit(conn: &Connection, user_id: &str, unit: GlucoseUnit) -> Result<usize> {
+    conn.execute("UPDATE users SET glucose_unit = ?1 WHERE id = ?2", params![unit.label(), user_id])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glucose_values_in_text_are_converted() {
+        let text = "Latest glucose 90.0 mg/dL, trend -18.0 mg/dL and +18 mg/dL.";
+        assert_eq!(GlucoseUnit::MgDl.format_text(text), text);
+        assert_eq!(
+            GlucoseUnit::MmolL.format_text(text),
+            "Latest glucose 5.0 mmol/L, trend -1.00 mmol/L and +1.00 mmol/L."
+        );
+    }
+
+    #[test]
+    fn glucose_rates_in_text_are_converted() {
+        let text = "glucose 72.1 mg/dL, trend -1.80 mg/dL/min, predicted 54.0 mg/dL in 10 min";
+        assert_eq!(
+            GlucoseUnit::MmolL.format_text(text),
+            "glucose 4.0 mmol/L, trend -0.10 mmol/L/min, predicted 3.0 mmol/L in 10 min"
+        );
+    }
+}
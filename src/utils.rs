@@ -1,6 +1,9 @@
 //Helper and Common Utilities
 use std::io::{self, Write};
-use chrono::Utc;
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+
+// timestamp format used by the glucose_readings and insulin_logs tables (matches SQLite datetime())
+pub const DB_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 // reads user choice from menu table and returns as integer
 pub fn get_user_choice() -> i32 {
@@ -11,4 +14,14 @@ pub fn get_user_choice() -> i32 {
 
 pub fn get_current_time_string()->String{
     Utc::now().to_rfc3339()
-}
\ No newline at end of file
+}
+
+// current UTC time formatted for the glucose_readings / insulin_logs time columns
+pub fn get_current_db_time_string() -> String {
+    Utc::now().format(DB_TIME_FORMAT).to_string()
+}
+
+// converts a UTC database timestamp into the local display format used across the menus
+pub fn format_local_time(utc_time: &NaiveDateTime) -> String {
+    Local.from_utc_datetime(utc_time).format("%b %d, %Y %I:%M %p").to_string()
+}